@@ -0,0 +1,77 @@
+// Throughput of the resolver cache as tokio gets more worker threads.
+//
+//   cargo bench --bench cache
+//
+// Every task looks up a mix of names,  inserting the ones that miss,  the way resolver tasks
+// use the cache.
+use rrdns::business::models::{Class, QType, ResourceRecord, Type};
+use rrdns::config::Config;
+use rrdns::resolver::cache::{Cache, InMemoryCache};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::runtime::Builder;
+
+const TASKS: usize = 64;
+const LOOKUPS_PER_TASK: usize = 20_000;
+const NAMES: usize = 10_000;
+
+fn main() {
+    let names: Arc<Vec<String>> = Arc::new(
+        (0..NAMES)
+            .map(|i| format!("host{}.example{}.com.", i, i % 97))
+            .collect(),
+    );
+
+    println!("{:>8} {:>14} {:>8}", "workers", "lookups/sec", "speedup");
+    let mut single_worker_rate = None;
+    for workers in &[1, 2, 4, 8, 16] {
+        let rate = run(*workers, names.clone());
+        let baseline = *single_worker_rate.get_or_insert(rate);
+        println!("{:>8} {:>14.0} {:>7.2}x", workers, rate, rate / baseline);
+    }
+}
+
+// Returns lookups per second.
+fn run(workers: usize, names: Arc<Vec<String>>) -> f64 {
+    let mut runtime = Builder::new()
+        .threaded_scheduler()
+        .core_threads(workers)
+        .enable_all()
+        .build()
+        .unwrap();
+    let cache: Arc<dyn Cache> = Arc::new(InMemoryCache::new(&Config::default()));
+
+    let started_at = Instant::now();
+    runtime.block_on(async {
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|task| {
+                let cache = cache.clone();
+                let names = names.clone();
+                tokio::spawn(async move {
+                    for i in 0..LOOKUPS_PER_TASK {
+                        let name = &names[(task * 7919 + i * 31) % names.len()];
+                        if cache.get(name, &QType::A).is_none() {
+                            cache.insert2(&a_record(name, i));
+                        }
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+    });
+
+    (TASKS * LOOKUPS_PER_TASK) as f64 / started_at.elapsed().as_secs_f64()
+}
+
+fn a_record(name: &str, i: usize) -> ResourceRecord {
+    ResourceRecord {
+        name: name.to_string(),
+        class: Class::IN,
+        r#type: Type::A(Ipv4Addr::new(10, 0, (i >> 8) as u8, i as u8)),
+        ttl: 300,
+        rd_length: 4,
+    }
+}
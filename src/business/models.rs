@@ -1,26 +1,39 @@
+use crate::error::ParseError;
 use itertools::interleave;
 use serde::{Deserialize, Serialize};
-use slice_as_array::{slice_as_array, slice_as_array_transmute};
 use std::cmp::Eq;
+use std::convert::TryFrom;
 use std::fmt;
 use std::hash::Hash;
 use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+// https://tools.ietf.org/html/rfc1035 4.1.1
+const HEADER_LENGTH: usize = 12;
+
+// https://tools.ietf.org/html/rfc1035 2.3.4
+const MAX_NAME_LENGTH: usize = 255;
+
+// Legit names never need more than a handful of pointers,  this only bounds the work done for
+// hostile ones.
+const MAX_POINTERS_PER_NAME: usize = 16;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ResponseCode {
-    NoError,     // No error condition, 0
-    FormatError, // Format error - 1
-    // ServerFailure, // Name Error - 3
+    NoError,        // No error condition, 0
+    FormatError,    // Format error - 1
+    ServerFailure,  // 2, Server failure.
     NameError,      // 3, No such name.
     NotImplemented, // 4
     Refused,        // 5
 }
 
 impl ResponseCode {
-    fn to_u8(&self) -> u8 {
+    pub fn to_u8(&self) -> u8 {
         match *self {
             ResponseCode::NoError => 0,
             ResponseCode::FormatError => 1,
+            ResponseCode::ServerFailure => 2,
             ResponseCode::NameError => 3,
             ResponseCode::NotImplemented => 4,
             ResponseCode::Refused => 5,
@@ -39,36 +52,390 @@ pub enum OpCode {
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MXData {
-    preference: u16,
-    exchange: String,
+    pub preference: u16,
+    pub exchange: String,
+}
+
+impl MXData {
+    fn serialize(&self) -> Vec<u8> {
+        let mut result = self.preference.to_be_bytes().to_vec();
+        result.append(&mut write_labels(&self.exchange));
+        result
+    }
+
+    fn deserialize(buf: &[u8], offset: usize, length: u16) -> Result<Self, ParseError> {
+        let preference = read_u16(buf, offset)?;
+        let (exchange, end) = read_labels(buf, offset + 2)?;
+        if end != offset + length as usize {
+            return Err(ParseError::BadRData(offset));
+        }
+        Ok(Self {
+            preference,
+            exchange,
+        })
+    }
+}
+
+// https://tools.ietf.org/html/rfc2782
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SRVData {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+impl SRVData {
+    fn serialize(&self) -> Vec<u8> {
+        let mut result = vec![];
+        result.extend_from_slice(&self.priority.to_be_bytes());
+        result.extend_from_slice(&self.weight.to_be_bytes());
+        result.extend_from_slice(&self.port.to_be_bytes());
+        result.append(&mut write_labels(&self.target));
+        result
+    }
+
+    // The target must not be compressed,  but some servers do it anyway.
+    fn deserialize(buf: &[u8], offset: usize, length: u16) -> Result<Self, ParseError> {
+        let priority = read_u16(buf, offset)?;
+        let weight = read_u16(buf, offset + 2)?;
+        let port = read_u16(buf, offset + 4)?;
+        let (target, end) = read_labels(buf, offset + 6)?;
+        if end != offset + length as usize {
+            return Err(ParseError::BadRData(offset));
+        }
+        Ok(Self {
+            priority,
+            weight,
+            port,
+            target,
+        })
+    }
+}
+
+// https://tools.ietf.org/html/rfc8659 4.1
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CAAData {
+    pub flags: u8,
+    // 1 to 255 octets,  ASCII letters and digits.
+    pub tag: String,
+    // Any octets,  up to the end of the RDATA.
+    pub value: Vec<u8>,
+}
+
+impl CAAData {
+    fn serialize(&self) -> Vec<u8> {
+        let mut result = vec![self.flags, self.tag.len() as u8];
+        result.extend_from_slice(self.tag.as_bytes());
+        result.extend_from_slice(&self.value);
+        result
+    }
+
+    fn deserialize(buf: &[u8], offset: usize, length: u16) -> Result<Self, ParseError> {
+        let rdata = read_slice(buf, offset, length as usize)?;
+        let flags = read_u8(rdata, 0).map_err(|_| ParseError::BadRData(offset))?;
+        let tag_length = read_u8(rdata, 1).map_err(|_| ParseError::BadRData(offset))? as usize;
+        if tag_length == 0 || 2 + tag_length > rdata.len() {
+            return Err(ParseError::BadRData(offset));
+        }
+        let tag = read_txt(&rdata[2..2 + tag_length]).ok_or(ParseError::BadRData(offset))?;
+        let value = rdata[2 + tag_length..].to_vec();
+        Ok(Self { flags, tag, value })
+    }
+}
+
+// https://tools.ietf.org/html/rfc3403 4.1
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NAPTRData {
+    pub order: u16,
+    pub preference: u16,
+    // <character-string>s,  up to 255 octets each and not necessarily UTF-8.
+    pub flags: Vec<u8>,
+    pub services: Vec<u8>,
+    pub regexp: Vec<u8>,
+    pub replacement: String,
+}
+
+impl NAPTRData {
+    fn serialize(&self) -> Vec<u8> {
+        let mut result = vec![];
+        result.extend_from_slice(&self.order.to_be_bytes());
+        result.extend_from_slice(&self.preference.to_be_bytes());
+        result.append(&mut write_character_string(&self.flags));
+        result.append(&mut write_character_string(&self.services));
+        result.append(&mut write_character_string(&self.regexp));
+        result.append(&mut write_labels(&self.replacement));
+        result
+    }
+
+    fn deserialize(buf: &[u8], offset: usize, length: u16) -> Result<Self, ParseError> {
+        let order = read_u16(buf, offset)?;
+        let preference = read_u16(buf, offset + 2)?;
+        let (flags, index) = read_character_string(buf, offset + 4)?;
+        let (services, index) = read_character_string(buf, index)?;
+        let (regexp, index) = read_character_string(buf, index)?;
+        let (replacement, end) = read_labels(buf, index)?;
+        if end != offset + length as usize {
+            return Err(ParseError::BadRData(offset));
+        }
+        Ok(Self {
+            order,
+            preference,
+            flags,
+            services,
+            regexp,
+            replacement,
+        })
+    }
+}
+
+// https://tools.ietf.org/html/rfc4034 5.1
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DSData {
+    pub key_tag: u16,
+    pub algorithm: u8,
+    pub digest_type: u8,
+    pub digest: Vec<u8>,
+}
+
+impl DSData {
+    fn serialize(&self) -> Vec<u8> {
+        let mut result = self.key_tag.to_be_bytes().to_vec();
+        result.push(self.algorithm);
+        result.push(self.digest_type);
+        result.extend_from_slice(&self.digest);
+        result
+    }
+
+    fn deserialize(rdata: &[u8], offset: usize) -> Result<Self, ParseError> {
+        if rdata.len() < 5 {
+            return Err(ParseError::BadRData(offset));
+        }
+        Ok(Self {
+            key_tag: u16::from_be_bytes([rdata[0], rdata[1]]),
+            algorithm: rdata[2],
+            digest_type: rdata[3],
+            digest: rdata[4..].to_vec(),
+        })
+    }
+}
+
+// https://tools.ietf.org/html/rfc4034 2.1
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DNSKEYData {
+    pub flags: u16,
+    pub protocol: u8,
+    pub algorithm: u8,
+    pub public_key: Vec<u8>,
+}
+
+impl DNSKEYData {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut result = self.flags.to_be_bytes().to_vec();
+        result.push(self.protocol);
+        result.push(self.algorithm);
+        result.extend_from_slice(&self.public_key);
+        result
+    }
+
+    fn deserialize(rdata: &[u8], offset: usize) -> Result<Self, ParseError> {
+        if rdata.len() < 5 {
+            return Err(ParseError::BadRData(offset));
+        }
+        Ok(Self {
+            flags: u16::from_be_bytes([rdata[0], rdata[1]]),
+            protocol: rdata[2],
+            algorithm: rdata[3],
+            public_key: rdata[4..].to_vec(),
+        })
+    }
+}
+
+// https://tools.ietf.org/html/rfc4034 3.1
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RRSIGData {
+    pub type_covered: QType,
+    pub algorithm: u8,
+    pub labels: u8,
+    pub original_ttl: u32,
+    pub expiration: u32, // secs since epoch,  in serial number arithmetic
+    pub inception: u32,  // secs since epoch,  in serial number arithmetic
+    pub key_tag: u16,
+    pub signer_name: String,
+    pub signature: Vec<u8>,
+}
+
+impl RRSIGData {
+    fn serialize(&self) -> Vec<u8> {
+        let mut result = self.serialize_without_signature();
+        result.extend_from_slice(&self.signature);
+        result
+    }
+
+    // https://tools.ietf.org/html/rfc4034 3.1.8.1
+    // RRSIG RDATA as it is fed to the signature,  signer name in canonical form.
+    pub fn serialize_without_signature(&self) -> Vec<u8> {
+        let mut result = self.type_covered.to_u16().to_be_bytes().to_vec();
+        result.push(self.algorithm);
+        result.push(self.labels);
+        result.extend_from_slice(&self.original_ttl.to_be_bytes());
+        result.extend_from_slice(&self.expiration.to_be_bytes());
+        result.extend_from_slice(&self.inception.to_be_bytes());
+        result.extend_from_slice(&self.key_tag.to_be_bytes());
+        result.append(&mut write_labels(&self.signer_name.to_lowercase()));
+        result
+    }
+
+    fn deserialize(buf: &[u8], offset: usize, length: u16) -> Result<Self, ParseError> {
+        let end = offset + length as usize;
+        let type_covered = QType::decimal_to_qtype(read_u16(buf, offset)?);
+        let algorithm = read_u8(buf, offset + 2)?;
+        let labels = read_u8(buf, offset + 3)?;
+        let original_ttl = read_u32(buf, offset + 4)?;
+        let expiration = read_u32(buf, offset + 8)?;
+        let inception = read_u32(buf, offset + 12)?;
+        let key_tag = read_u16(buf, offset + 16)?;
+        let (signer_name, start_of_signature) = read_labels(buf, offset + 18)?;
+        if start_of_signature > end {
+            return Err(ParseError::BadRData(offset));
+        }
+        let signature = read_slice(buf, start_of_signature, end - start_of_signature)?.to_vec();
+        Ok(Self {
+            type_covered,
+            algorithm,
+            labels,
+            original_ttl,
+            expiration,
+            inception,
+            key_tag,
+            signer_name,
+            signature,
+        })
+    }
+}
+
+// https://tools.ietf.org/html/rfc4034 4.1
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NSECData {
+    pub next_domain_name: String,
+    pub types: Vec<QType>,
+}
+
+impl NSECData {
+    fn serialize(&self) -> Vec<u8> {
+        let mut result = write_labels(&self.next_domain_name);
+        result.append(&mut write_type_bitmaps(&self.types));
+        result
+    }
+
+    fn deserialize(buf: &[u8], offset: usize, length: u16) -> Result<Self, ParseError> {
+        let end = offset + length as usize;
+        let (next_domain_name, start_of_bitmaps) = read_labels(buf, offset)?;
+        if start_of_bitmaps > end {
+            return Err(ParseError::BadRData(offset));
+        }
+        let bitmaps = read_slice(buf, start_of_bitmaps, end - start_of_bitmaps)?;
+        Ok(Self {
+            next_domain_name,
+            types: read_type_bitmaps(bitmaps).ok_or(ParseError::BadRData(offset))?,
+        })
+    }
+}
+
+// https://tools.ietf.org/html/rfc5155 3.2
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NSEC3Data {
+    pub hash_algorithm: u8,
+    pub flags: u8,
+    pub iterations: u16,
+    pub salt: Vec<u8>,
+    pub next_hashed_owner_name: Vec<u8>,
+    pub types: Vec<QType>,
+}
+
+impl NSEC3Data {
+    fn serialize(&self) -> Vec<u8> {
+        let mut result = vec![self.hash_algorithm, self.flags];
+        result.extend_from_slice(&self.iterations.to_be_bytes());
+        result.push(self.salt.len() as u8);
+        result.extend_from_slice(&self.salt);
+        result.push(self.next_hashed_owner_name.len() as u8);
+        result.extend_from_slice(&self.next_hashed_owner_name);
+        result.append(&mut write_type_bitmaps(&self.types));
+        result
+    }
+
+    fn deserialize(rdata: &[u8], offset: usize) -> Result<Self, ParseError> {
+        let bad_rdata = |_| ParseError::BadRData(offset);
+        let hash_algorithm = read_u8(rdata, 0).map_err(bad_rdata)?;
+        let flags = read_u8(rdata, 1).map_err(bad_rdata)?;
+        let iterations = read_u16(rdata, 2).map_err(bad_rdata)?;
+        let salt_length = read_u8(rdata, 4).map_err(bad_rdata)? as usize;
+        let salt = read_slice(rdata, 5, salt_length).map_err(bad_rdata)?;
+        let hash_length = read_u8(rdata, 5 + salt_length).map_err(bad_rdata)? as usize;
+        let next_hashed_owner_name =
+            read_slice(rdata, 6 + salt_length, hash_length).map_err(bad_rdata)?;
+        let bitmaps = &rdata[6 + salt_length + hash_length..];
+        Ok(Self {
+            hash_algorithm,
+            flags,
+            iterations,
+            salt: salt.to_vec(),
+            next_hashed_owner_name: next_hashed_owner_name.to_vec(),
+            types: read_type_bitmaps(bitmaps).ok_or(ParseError::BadRData(offset))?,
+        })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TXTData {
-    length: u8,
-    data: String,
+    // https://tools.ietf.org/html/rfc1035 3.3.14
+    // One or more <character-string>s,  each up to 255 octets and not necessarily UTF-8.
+    pub strings: Vec<Vec<u8>>,
 }
 
 impl TXTData {
     // length: u8
     // data: string of length u8
+    // ... repeated for every string.
     fn serialize(&self) -> Vec<u8> {
-        let mut result = vec![self.length];
-
-        result.append(&mut self.data.as_bytes().to_vec());
+        let mut result = vec![];
+        for string in &self.strings {
+            result.push(string.len() as u8);
+            result.extend_from_slice(string);
+        }
         result
     }
+
+    fn deserialize(rdata: &[u8], offset: usize) -> Result<Self, ParseError> {
+        if rdata.is_empty() {
+            return Err(ParseError::BadRData(offset));
+        }
+        let mut strings = vec![];
+        let mut index = 0;
+        while index < rdata.len() {
+            let length = rdata[index] as usize;
+            let string = rdata
+                .get(index + 1..index + 1 + length)
+                .ok_or(ParseError::BadRData(offset))?;
+            strings.push(string.to_vec());
+            index += 1 + length;
+        }
+        Ok(Self { strings })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SOAData {
-    mname: String,
-    rname: String,
-    serial: u32,
-    refresh_in_secs: u32,
-    retry_in_secs: u32,
-    expire_in_secs: u32,
-    minimum: u32,
+    pub mname: String,
+    pub rname: String,
+    pub serial: u32,
+    pub refresh_in_secs: u32,
+    pub retry_in_secs: u32,
+    pub expire_in_secs: u32,
+    // https://tools.ietf.org/html/rfc2308 4
+    // TTL of negative answers from the zone.
+    pub minimum: u32,
 }
 
 impl SOAData {
@@ -109,16 +476,19 @@ impl SOAData {
         result
     }
 
-    fn deserialize(buf: &[u8], offset: usize, _length: u16) -> Self {
-        let (mname, start_of_rname) = read_labels(buf, offset);
-        let (rname, start_of_serial) = read_labels(buf, start_of_rname);
+    fn deserialize(buf: &[u8], offset: usize, length: u16) -> Result<Self, ParseError> {
+        let (mname, start_of_rname) = read_labels(buf, offset)?;
+        let (rname, start_of_serial) = read_labels(buf, start_of_rname)?;
         let j = start_of_serial;
-        let serial = convert_slice_to_u32(&buf[j..j + 4]);
-        let refresh_in_secs = convert_slice_to_u32(&buf[j + 4..j + 8]);
-        let retry_in_secs = convert_slice_to_u32(&buf[j + 8..j + 12]);
-        let expire_in_secs = convert_slice_to_u32(&buf[j + 12..j + 16]);
-        let minimum = convert_slice_to_u32(&buf[j + 16..j + 20]);
-        Self {
+        if j + 20 != offset + length as usize {
+            return Err(ParseError::BadRData(offset));
+        }
+        let serial = read_u32(buf, j)?;
+        let refresh_in_secs = read_u32(buf, j + 4)?;
+        let retry_in_secs = read_u32(buf, j + 8)?;
+        let expire_in_secs = read_u32(buf, j + 12)?;
+        let minimum = read_u32(buf, j + 16)?;
+        Ok(Self {
             mname,
             rname,
             serial,
@@ -126,7 +496,7 @@ impl SOAData {
             retry_in_secs,
             expire_in_secs,
             minimum,
-        }
+        })
     }
 }
 
@@ -134,15 +504,25 @@ impl SOAData {
 // Type is used in ResourceRecords.
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Type {
-    A(Ipv4Addr),    // Host address. 1
-    NS(String),     // Authoritative name server for the domain. 2
-    CNAME(String),  // Canonical name of an alias. 5
-    SOA(SOAData),   // Identifies the start of zone of authority. 6
-    PTR(String),    // A pointer to another part of the domain name space. 12
-    HINFO,          // host information, 13
-    MX(MXData),     // Identifies a mail exchange for domain. 15
-    AAAA(Ipv6Addr), // ipv6 28
-    TXT(TXTData),   // Text strings
+    A(Ipv4Addr),        // Host address. 1
+    NS(String),         // Authoritative name server for the domain. 2
+    CNAME(String),      // Canonical name of an alias. 5
+    SOA(SOAData),       // Identifies the start of zone of authority. 6
+    PTR(String),        // A pointer to another part of the domain name space. 12
+    MX(MXData),         // Identifies a mail exchange for domain. 15
+    AAAA(Ipv6Addr),     // ipv6 28
+    TXT(TXTData),       // Text strings
+    SRV(SRVData),       // Location of services. 33
+    NAPTR(NAPTRData),   // Naming authority pointer. 35
+    CAA(CAAData),       // Certification authority authorization. 257
+    DS(DSData),         // Delegation signer. 43
+    RRSIG(RRSIGData),   // Signature over an RRset. 46
+    NSEC(NSECData),     // Next secure,  authenticated denial of existence. 47
+    DNSKEY(DNSKEYData), // Public key of a zone. 48
+    NSEC3(NSEC3Data),   // Hashed authenticated denial of existence. 50
+    // https://tools.ietf.org/html/rfc3597
+    // Any other type,  RDATA is kept opaque and passed along as is.
+    Unknown { code: u16, rdata: Vec<u8> },
 }
 
 impl Type {
@@ -153,82 +533,183 @@ impl Type {
             Type::CNAME(_) => QType::CNAME,
             Type::SOA(_) => QType::SOA,
             Type::PTR(_) => QType::PTR,
-            Type::HINFO => QType::HINFO,
             Type::MX(_) => QType::MX,
             Type::AAAA(_) => QType::AAAA,
             Type::TXT(_) => QType::TXT,
+            Type::SRV(_) => QType::SRV,
+            Type::NAPTR(_) => QType::NAPTR,
+            Type::CAA(_) => QType::CAA,
+            Type::DS(_) => QType::DS,
+            Type::RRSIG(_) => QType::RRSIG,
+            Type::NSEC(_) => QType::NSEC,
+            Type::DNSKEY(_) => QType::DNSKEY,
+            Type::NSEC3(_) => QType::NSEC3,
+            Type::Unknown { code, .. } => QType::decimal_to_qtype(code),
         }
     }
 
-    fn to_u16(&self) -> u16 {
+    pub fn serialize_rdata(&self) -> Vec<u8> {
+        match self {
+            Type::A(ipv4) => ipv4.octets().to_vec(),
+            Type::AAAA(ipv6) => ipv6.octets().to_vec(),
+            Type::NS(ns) => write_labels(&ns),
+            Type::TXT(txt_data) => txt_data.serialize(),
+            Type::CNAME(cname) => write_labels(cname),
+            Type::SOA(soa) => soa.serialize(),
+            Type::PTR(ptr) => write_labels(ptr),
+            Type::MX(mx) => mx.serialize(),
+            Type::SRV(srv) => srv.serialize(),
+            Type::NAPTR(naptr) => naptr.serialize(),
+            Type::CAA(caa) => caa.serialize(),
+            Type::DS(ds) => ds.serialize(),
+            Type::RRSIG(rrsig) => rrsig.serialize(),
+            Type::NSEC(nsec) => nsec.serialize(),
+            Type::DNSKEY(dnskey) => dnskey.serialize(),
+            Type::NSEC3(nsec3) => nsec3.serialize(),
+            Type::Unknown { rdata, .. } => rdata.clone(),
+        }
+    }
+
+    // https://tools.ietf.org/html/rfc4034 6.2
+    // RDATA with the names of the listed types in lower case.  NSEC is left alone as per
+    // https://tools.ietf.org/html/rfc6840 5.1.
+    pub fn canonical_rdata(&self) -> Vec<u8> {
+        let canonical = match self {
+            Type::NS(ns) => Type::NS(ns.to_lowercase()),
+            Type::CNAME(cname) => Type::CNAME(cname.to_lowercase()),
+            Type::PTR(ptr) => Type::PTR(ptr.to_lowercase()),
+            Type::SOA(soa) => Type::SOA(SOAData {
+                mname: soa.mname.to_lowercase(),
+                rname: soa.rname.to_lowercase(),
+                ..soa.clone()
+            }),
+            Type::MX(mx) => Type::MX(MXData {
+                exchange: mx.exchange.to_lowercase(),
+                ..mx.clone()
+            }),
+            Type::SRV(srv) => Type::SRV(SRVData {
+                target: srv.target.to_lowercase(),
+                ..srv.clone()
+            }),
+            Type::NAPTR(naptr) => Type::NAPTR(NAPTRData {
+                replacement: naptr.replacement.to_lowercase(),
+                ..naptr.clone()
+            }),
+            Type::RRSIG(rrsig) => Type::RRSIG(RRSIGData {
+                signer_name: rrsig.signer_name.to_lowercase(),
+                ..rrsig.clone()
+            }),
+            _ => return self.serialize_rdata(),
+        };
+        canonical.serialize_rdata()
+    }
+
+    pub fn to_u16(&self) -> u16 {
         match *self {
             Type::A(_) => 1,
             Type::NS(_) => 2,
             Type::CNAME(_) => 5,
             Type::SOA(_) => 6,
             Type::PTR(_) => 12,
-            Type::HINFO => 13,
             Type::MX(_) => 15,
             Type::AAAA(_) => 28,
             Type::TXT(_) => 16,
+            Type::SRV(_) => 33,
+            Type::NAPTR(_) => 35,
+            Type::CAA(_) => 257,
+            Type::DS(_) => 43,
+            Type::RRSIG(_) => 46,
+            Type::NSEC(_) => 47,
+            Type::DNSKEY(_) => 48,
+            Type::NSEC3(_) => 50,
+            Type::Unknown { code, .. } => code,
         }
     }
 }
 
 // https://tools.ietf.org/html/rfc1035 3.2.3
+// Serialized as its mnemonic so that it can key JSON maps.
 #[derive(Debug, PartialEq, Clone, Copy, Hash, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
 pub enum QType {
-    A,     // Host address. 1
-    NS,    // Authoritative name server for the domain. 2
-    CNAME, // Canonical name of an alias. 5
-    SOA,   // Identifies the start of zone of authority. 6
-    PTR,   // A pointer to another part of the domain name space. 12
-    HINFO, // Host information. 13
-    MX,    // Identifies a mail exchange for domain. 15
-    AAAA,  // ipv6 28
-    TXT,   // Text strings 16
-    AXFR,  // A request for a transfer of an entier zone. 252
-    MAILB, // A request for mailbox-related records (MB, MG or MR). 253
-    MAILA, // A request for mail agent RRs (obsolete - see MX). 254
-    STAR,  // (*) A request for all records, 255 - TODO: OBSOLETE
-}
-
-struct InvalidTypeError {
-    code: u16,
-}
-
-impl fmt::Display for InvalidTypeError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Invalid type error: {}", self.code)
-    }
+    A,            // Host address. 1
+    NS,           // Authoritative name server for the domain. 2
+    CNAME,        // Canonical name of an alias. 5
+    SOA,          // Identifies the start of zone of authority. 6
+    PTR,          // A pointer to another part of the domain name space. 12
+    HINFO,        // Host information. 13
+    MX,           // Identifies a mail exchange for domain. 15
+    AAAA,         // ipv6 28
+    TXT,          // Text strings 16
+    SRV,          // Location of services. 33
+    NAPTR,        // Naming authority pointer. 35
+    CAA,          // Certification authority authorization. 257
+    DS,           // Delegation signer. 43
+    RRSIG,        // Signature over an RRset. 46
+    NSEC,         // Next secure. 47
+    DNSKEY,       // Public key of a zone. 48
+    NSEC3,        // Hashed next secure. 50
+    AXFR,         // A request for a transfer of an entier zone. 252
+    MAILB,        // A request for mailbox-related records (MB, MG or MR). 253
+    MAILA,        // A request for mail agent RRs (obsolete - see MX). 254
+    STAR,         // (*) A request for all records, 255 - TODO: OBSOLETE
+    Unknown(u16), // https://tools.ietf.org/html/rfc3597
 }
 
-impl fmt::Debug for InvalidTypeError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Invalid type error")
-    }
-}
+// Every QType with a mnemonic of its own.
+const NAMED_QTYPES: [QType; 21] = [
+    QType::A,
+    QType::NS,
+    QType::CNAME,
+    QType::SOA,
+    QType::PTR,
+    QType::HINFO,
+    QType::MX,
+    QType::AAAA,
+    QType::TXT,
+    QType::SRV,
+    QType::NAPTR,
+    QType::CAA,
+    QType::DS,
+    QType::RRSIG,
+    QType::NSEC,
+    QType::DNSKEY,
+    QType::NSEC3,
+    QType::AXFR,
+    QType::MAILB,
+    QType::MAILA,
+    QType::STAR,
+];
 
 impl QType {
-    fn decimal_to_qtype(value: u16) -> Result<QType, InvalidTypeError> {
+    pub fn decimal_to_qtype(value: u16) -> QType {
         match value {
-            1 => Ok(QType::A),
-            2 => Ok(QType::NS),
-            5 => Ok(QType::CNAME),
-            6 => Ok(QType::SOA),
-            12 => Ok(QType::PTR),
-            15 => Ok(QType::MX),
-            16 => Ok(QType::TXT),
-            28 => Ok(QType::AAAA),
-            252 => Ok(QType::AXFR),
-            253 => Ok(QType::MAILB),
-            254 => Ok(QType::MAILA),
-            255 => Ok(QType::STAR),
-            _ => Err(InvalidTypeError { code: value }),
+            1 => QType::A,
+            2 => QType::NS,
+            5 => QType::CNAME,
+            6 => QType::SOA,
+            12 => QType::PTR,
+            13 => QType::HINFO,
+            15 => QType::MX,
+            16 => QType::TXT,
+            28 => QType::AAAA,
+            33 => QType::SRV,
+            35 => QType::NAPTR,
+            257 => QType::CAA,
+            43 => QType::DS,
+            46 => QType::RRSIG,
+            47 => QType::NSEC,
+            48 => QType::DNSKEY,
+            50 => QType::NSEC3,
+            252 => QType::AXFR,
+            253 => QType::MAILB,
+            254 => QType::MAILA,
+            255 => QType::STAR,
+            _ => QType::Unknown(value),
         }
     }
 
-    fn to_u16(&self) -> u16 {
+    pub fn to_u16(&self) -> u16 {
         match *self {
             QType::A => 1,
             QType::NS => 2,
@@ -239,10 +720,19 @@ impl QType {
             QType::MX => 15,
             QType::TXT => 16,
             QType::AAAA => 28,
+            QType::SRV => 33,
+            QType::NAPTR => 35,
+            QType::CAA => 257,
+            QType::DS => 43,
+            QType::RRSIG => 46,
+            QType::NSEC => 47,
+            QType::DNSKEY => 48,
+            QType::NSEC3 => 50,
             QType::AXFR => 252,
             QType::MAILB => 253,
             QType::MAILA => 254,
             QType::STAR => 255,
+            QType::Unknown(code) => code,
         }
     }
 }
@@ -251,7 +741,7 @@ impl fmt::Display for QType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "{:?}",
+            "{}",
             match *self {
                 QType::A => "A",
                 QType::NS => "NS",
@@ -262,15 +752,58 @@ impl fmt::Display for QType {
                 QType::MX => "MX",
                 QType::TXT => "TXT",
                 QType::AAAA => "AAAA",
+                QType::SRV => "SRV",
+                QType::NAPTR => "NAPTR",
+                QType::CAA => "CAA",
+                QType::DS => "DS",
+                QType::RRSIG => "RRSIG",
+                QType::NSEC => "NSEC",
+                QType::DNSKEY => "DNSKEY",
+                QType::NSEC3 => "NSEC3",
                 QType::AXFR => "AXFR",
                 QType::MAILB => "MAILB",
                 QType::MAILA => "MAILA",
                 QType::STAR => "STAR",
+                // https://tools.ietf.org/html/rfc3597 5
+                QType::Unknown(code) => return write!(f, "TYPE{}", code),
             }
         )
     }
 }
 
+impl FromStr for QType {
+    type Err = String;
+
+    // Accepts mnemonics as well as the generic TYPEnnn form.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.to_uppercase();
+        if let Some(code) = value.strip_prefix("TYPE") {
+            if let Ok(code) = code.parse::<u16>() {
+                return Ok(QType::decimal_to_qtype(code));
+            }
+        }
+        NAMED_QTYPES
+            .iter()
+            .find(|qtype| qtype.to_string() == value)
+            .copied()
+            .ok_or(format!("unknown type {}", value))
+    }
+}
+
+impl From<QType> for String {
+    fn from(qtype: QType) -> Self {
+        qtype.to_string()
+    }
+}
+
+impl TryFrom<String> for QType {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Class {
     IN, // 1 the internet
@@ -278,7 +811,7 @@ pub enum Class {
 }
 
 impl Class {
-    fn to_u16(&self) -> u16 {
+    pub fn to_u16(&self) -> u16 {
         match *self {
             Class::IN => 1,
             Class::CH => 3,
@@ -286,32 +819,17 @@ impl Class {
     }
 }
 
-struct InvalidClassError {
-    code: u16,
-}
-
-impl fmt::Display for InvalidClassError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Invalid class error: {}", self.code)
-    }
-}
-impl fmt::Debug for InvalidClassError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Invalid class error: {}", self.code)
-    }
-}
-
 impl Class {
-    fn to_class(code: u16) -> Result<Class, InvalidClassError> {
+    fn to_class(code: u16) -> Result<Class, ParseError> {
         match code {
             1 => Ok(Class::IN),
             3 => Ok(Class::CH),
-            _ => Err(InvalidClassError { code }),
+            _ => Err(ParseError::UnsupportedClass(code)),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum QClass {
     IN,   // Internet system
     CH,   // Chaos system
@@ -322,10 +840,19 @@ impl QClass {
     fn to_u16(&self) -> u16 {
         match *self {
             QClass::IN => 1,
-            QClass::CH => 2, // ???
+            QClass::CH => 3,
             QClass::STAR => 255,
         }
     }
+
+    fn from_u16(code: u16) -> Result<QClass, ParseError> {
+        match code {
+            1 => Ok(QClass::IN),
+            3 => Ok(QClass::CH),
+            255 => Ok(QClass::STAR),
+            _ => Err(ParseError::UnsupportedClass(code)),
+        }
+    }
 }
 
 // Business models.
@@ -350,18 +877,7 @@ impl ResourceRecord {
         serialized_type.push(((self.r#type.to_u16() >> 8) & 0xff) as u8);
         serialized_type.push((self.r#type.to_u16() & 0xff) as u8);
 
-        let serialized_rdata = match &self.r#type {
-            Type::A(ipv4) => ipv4.octets().to_vec(),
-            Type::AAAA(ipv6) => ipv6.octets().to_vec(),
-            Type::NS(ns) => write_labels(&ns),
-            Type::TXT(txt_data) => txt_data.serialize(),
-            Type::CNAME(cname) => write_labels(cname),
-            Type::SOA(soa) => soa.serialize(),
-            _ => panic!(
-                "ResourceRecord:serialize type not supported: {:#?}",
-                &self.r#type
-            ),
-        };
+        let serialized_rdata = self.r#type.serialize_rdata();
 
         let mut serialized_class = Vec::with_capacity(2);
         serialized_class.push(((self.class.to_u16() >> 8) & 0xff) as u8);
@@ -396,6 +912,103 @@ impl PartialEq for ResourceRecord {
     }
 }
 
+// https://tools.ietf.org/html/rfc6891 6.1.2
+// OPT pseudo-record.  It lives in the additional section but carries no data about the
+// domain,  so it is kept apart from the other resource records.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edns {
+    pub udp_payload_size: u16, // CLASS
+    pub extended_rcode: u8,    // upper 8 bits of TTL
+    pub version: u8,           // next 8 bits of TTL
+    pub dnssec_ok: bool,       // DO, first bit of the lower 16 bits of TTL
+    pub options: Vec<EdnsOption>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+pub const OPT_TYPE_CODE: u16 = 41;
+
+// Only EDNS version spoken by rrdns.
+pub const EDNS_VERSION: u8 = 0;
+
+// https://tools.ietf.org/html/rfc6891 9
+// Extended RCODE for a request with an unsupported EDNS version.
+pub const EXTENDED_RCODE_BADVERS: u8 = 1;
+
+impl Edns {
+    pub fn new(udp_payload_size: u16) -> Self {
+        Self {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: EDNS_VERSION,
+            dnssec_ok: false,
+            options: vec![],
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut rdata = vec![];
+        for option in &self.options {
+            rdata.extend_from_slice(&option.code.to_be_bytes());
+            rdata.extend_from_slice(&(option.data.len() as u16).to_be_bytes());
+            rdata.extend_from_slice(&option.data);
+        }
+
+        let flags: u16 = if self.dnssec_ok { 0x8000 } else { 0 };
+
+        let mut result = vec![0]; // root domain
+        result.extend_from_slice(&OPT_TYPE_CODE.to_be_bytes());
+        result.extend_from_slice(&self.udp_payload_size.to_be_bytes());
+        result.push(self.extended_rcode);
+        result.push(self.version);
+        result.extend_from_slice(&flags.to_be_bytes());
+        result.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        result.append(&mut rdata);
+        result
+    }
+
+    // offset points at CLASS,  right after the owner name and TYPE.
+    fn deserialize(buf: &[u8], offset: usize) -> Result<(Self, usize), ParseError> {
+        let mut index = offset;
+        let udp_payload_size = read_u16(buf, index)?;
+        let extended_rcode = read_u8(buf, index + 2)?;
+        let version = read_u8(buf, index + 3)?;
+        let flags = read_u16(buf, index + 4)?;
+        let rd_length = read_u16(buf, index + 6)? as usize;
+        index += 8;
+
+        let rdata = read_slice(buf, index, rd_length)?;
+        let mut options = vec![];
+        let mut option_index = 0;
+        while option_index < rdata.len() {
+            let code = read_u16(rdata, option_index)?;
+            let length = read_u16(rdata, option_index + 2)? as usize;
+            let data = read_slice(rdata, option_index + 4, length)
+                .map_err(|_| ParseError::BadRData(offset))?;
+            options.push(EdnsOption {
+                code,
+                data: data.to_vec(),
+            });
+            option_index += 4 + length;
+        }
+
+        Ok((
+            Self {
+                udp_payload_size,
+                extended_rcode,
+                version,
+                dnssec_ok: flags & 0x8000 != 0,
+                options,
+            },
+            index + rd_length,
+        ))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DNSQueryResponse {
     pub query: DNSQuery,
@@ -406,11 +1019,22 @@ pub struct DNSQueryResponse {
 
 impl DNSQueryResponse {
     pub fn serialize(&self) -> Vec<u8> {
-        let query = self.query.serialize();
+        // OPT is counted in the additional section.
+        let mut header = self.query.header.clone();
+        header.answers_count = self.answers.len() as u16;
+        header.ns_rr_count = self.authority.len() as u16;
+        header.additional_rr_count =
+            (self.additional.len() + self.query.edns.is_some() as usize) as u16;
+
+        let query = self.query.serialize_header_and_questions(&header);
         let answers = self.serialize_resource_records(&self.answers);
         let authority = self.serialize_resource_records(&self.authority);
         let additional = self.serialize_resource_records(&self.additional);
-        itertools::concat(vec![query, answers, authority, additional])
+        let edns = match &self.query.edns {
+            Some(edns) => edns.serialize(),
+            None => vec![],
+        };
+        itertools::concat(vec![query, answers, authority, additional, edns])
     }
 
     fn serialize_resource_records(&self, resource_records: &Vec<ResourceRecord>) -> Vec<u8> {
@@ -421,35 +1045,115 @@ impl DNSQueryResponse {
         itertools::concat(serialized_rrs)
     }
 
-    pub fn deserialize(data: &[u8]) -> DNSQueryResponse {
-        let (query, answer_section_offset) = DNSQuery::deserialize(data);
+    pub fn deserialize(data: &[u8]) -> Result<DNSQueryResponse, ParseError> {
+        let (mut query, answer_section_offset) = DNSQuery::deserialize_header_and_questions(data)?;
 
         // Read answers.
         let (answer_section, authority_section_offset) = DNSQuery::deserialize_resource_records(
             data,
             answer_section_offset,
             query.header.answers_count,
-        );
+        )?;
 
         // Read authority.
-        let (authority_section, additional_section_offset) = DNSQuery::deserialize_resource_records(
-            data,
-            authority_section_offset,
-            query.header.ns_rr_count,
-        );
+        let (authority_section, additional_section_offset) =
+            DNSQuery::deserialize_resource_records(
+                data,
+                authority_section_offset,
+                query.header.ns_rr_count,
+            )?;
 
         // Read additional.
-        let (additional_section, _) = DNSQuery::deserialize_resource_records(
+        let (additional_section, edns, _) = DNSQuery::deserialize_additional_section(
             data,
             additional_section_offset,
             query.header.additional_rr_count,
-        );
+        )?;
+        query.edns = edns;
 
-        DNSQueryResponse {
+        Ok(DNSQueryResponse {
             query,
             answers: answer_section,
             authority: authority_section,
             additional: additional_section,
+        })
+    }
+
+    // https://tools.ietf.org/html/rfc1035 4.1.1
+    // FORMERR for a message that could not be parsed.  None when not even the ID can be read.
+    pub fn format_error(buf: &[u8]) -> Option<DNSQueryResponse> {
+        let id = read_u16(buf, 0).ok()?;
+        let is_recursion_desired = buf.len() > 2 && is_ith_bit_set(buf, 23);
+        Some(DNSQueryResponse {
+            query: DNSQuery {
+                header: DNSQueryHeaderSection {
+                    id,
+                    is_query: false,
+                    op_code: OpCode::Query,
+                    is_authoritative_answer: false,
+                    is_truncated: false,
+                    is_recursion_desired,
+                    is_recursion_available: true,
+                    is_authentic_data: false,
+                    is_checking_disabled: false,
+                    response_code: ResponseCode::FormatError,
+                    questions_count: 0,
+                    answers_count: 0,
+                    ns_rr_count: 0,
+                    additional_rr_count: 0,
+                },
+                questions: vec![],
+                additionals: vec![],
+                edns: None,
+            },
+            answers: vec![],
+            authority: vec![],
+            additional: vec![],
+        })
+    }
+
+    // https://tools.ietf.org/html/rfc1035 4.2.1
+    // Response with all records dropped and TC set,  so that the client retries over TCP.
+    pub fn truncated(&self) -> DNSQueryResponse {
+        let mut query = self.query.clone();
+        query.header.is_truncated = true;
+        query.header.answers_count = 0;
+        query.header.ns_rr_count = 0;
+        query.header.additional_rr_count = 0;
+        DNSQueryResponse {
+            query,
+            answers: vec![],
+            authority: vec![],
+            additional: vec![],
+        }
+    }
+
+    // https://tools.ietf.org/html/rfc8484 5.1
+    // Smallest TTL of the records in all sections,  how long the response as a whole stays
+    // fresh.  None when there are no records.
+    pub fn min_ttl(&self) -> Option<u32> {
+        self.answers
+            .iter()
+            .chain(&self.authority)
+            .chain(&self.additional)
+            .map(|rr| rr.ttl)
+            .min()
+    }
+
+    // https://tools.ietf.org/html/rfc4035 5.5
+    // Answer for a query whose response turned out to be bogus.
+    pub fn server_failure(&self) -> DNSQueryResponse {
+        let mut query = self.query.clone();
+        query.header.response_code = ResponseCode::ServerFailure;
+        query.header.is_authentic_data = false;
+        query.header.answers_count = 0;
+        query.header.ns_rr_count = 0;
+        query.header.additional_rr_count = 0;
+        DNSQueryResponse {
+            query,
+            answers: vec![],
+            authority: vec![],
+            additional: vec![],
         }
     }
 
@@ -482,6 +1186,8 @@ pub struct DNSQueryHeaderSection {
     pub is_truncated: bool,            // 1b, 22
     pub is_recursion_desired: bool,    // 1b, 23
     pub is_recursion_available: bool,  // 1b, 24
+    pub is_authentic_data: bool,       // 1b, 26 https://tools.ietf.org/html/rfc4035 3.2.3
+    pub is_checking_disabled: bool,    // 1b, 27 https://tools.ietf.org/html/rfc4035 3.2.2
     pub response_code: ResponseCode,   // 4b, [28-31]
 
     pub questions_count: u16,     // 2B, [32-47]
@@ -514,7 +1220,12 @@ impl DNSQueryHeaderSection {
         } else {
             flags_first_byte
         };
-        // is_truncated = false/0 true/1 0 for now, 6,7
+        // is_truncated = 6,7
+        flags_first_byte = if self.is_truncated {
+            flags_first_byte ^ 0b00000010
+        } else {
+            flags_first_byte
+        };
 
         // is_recursion_desired - 7,8
         flags_first_byte = if self.is_recursion_desired {
@@ -532,9 +1243,23 @@ impl DNSQueryHeaderSection {
             flags_second_byte
         };
 
-        // response_code 4,8 [4,5,6,7]
-        flags_second_byte = flags_second_byte ^ self.response_code.to_u8();
-        result.push(flags_second_byte);
+        // is_authentic_data 2,3
+        flags_second_byte = if self.is_authentic_data {
+            flags_second_byte ^ 0b00100000
+        } else {
+            flags_second_byte
+        };
+
+        // is_checking_disabled 3,4
+        flags_second_byte = if self.is_checking_disabled {
+            flags_second_byte ^ 0b00010000
+        } else {
+            flags_second_byte
+        };
+
+        // response_code 4,8 [4,5,6,7]
+        flags_second_byte = flags_second_byte ^ self.response_code.to_u8();
+        result.push(flags_second_byte);
 
         // questions_count
         result.push(((self.questions_count >> 8) & 0xff) as u8);
@@ -623,6 +1348,7 @@ pub struct DNSQuery {
     // Number of questions will always be one.
     pub questions: Vec<DNSQuestionQuery>,
     pub additionals: Vec<ResourceRecord>,
+    pub edns: Option<Edns>,
 }
 
 impl DNSQuery {
@@ -632,9 +1358,22 @@ impl DNSQuery {
     }
 
     pub fn serialize(&self) -> Vec<u8> {
+        // OPT is the only record written in the additional section of a query.
+        let mut header = self.header.clone();
+        header.additional_rr_count = self.edns.is_some() as u16;
+
+        let mut result = self.serialize_header_and_questions(&header);
+        if let Some(edns) = &self.edns {
+            result.append(&mut edns.serialize());
+        }
+
+        result
+    }
+
+    fn serialize_header_and_questions(&self, header: &DNSQueryHeaderSection) -> Vec<u8> {
         let mut result = vec![];
 
-        let mut serialized_header = self.header.serialize();
+        let mut serialized_header = header.serialize();
         result.append(&mut serialized_header);
         let mut serialized_questions = self
             .questions
@@ -646,40 +1385,64 @@ impl DNSQuery {
         result
     }
 
-    pub fn deserialize(buf: &[u8]) -> (DNSQuery, usize) {
-        let header_section = DNSQuery::deserialize_header_section(buf);
+    pub fn deserialize(buf: &[u8]) -> Result<(DNSQuery, usize), ParseError> {
+        let (mut query, offset) = DNSQuery::deserialize_header_and_questions(buf)?;
+
+        // Queries normally carry nothing but the OPT record after the question.
+        let (_, offset) =
+            DNSQuery::deserialize_resource_records(buf, offset, query.header.answers_count)?;
+        let (_, offset) =
+            DNSQuery::deserialize_resource_records(buf, offset, query.header.ns_rr_count)?;
+        let (additionals, edns, offset) = DNSQuery::deserialize_additional_section(
+            buf,
+            offset,
+            query.header.additional_rr_count,
+        )?;
+        query.additionals = additionals;
+        query.edns = edns;
+
+        Ok((query, offset))
+    }
+
+    fn deserialize_header_and_questions(buf: &[u8]) -> Result<(DNSQuery, usize), ParseError> {
+        let header_section = DNSQuery::deserialize_header_section(buf)?;
 
         let (question_section, offset) =
-            DNSQuery::deserialize_question_section(buf, header_section.questions_count);
-        // let (additional_section, _) =
-        //     DNSQuery::parse_resource_records(buf, offset, header_section.additional_rr_count);
+            DNSQuery::deserialize_question_section(buf, header_section.questions_count)?;
 
-        (
+        Ok((
             Self {
                 header: header_section,
                 questions: question_section,
                 additionals: vec![],
+                edns: None,
             },
             offset,
-        )
+        ))
     }
 
     // https://tools.ietf.org/html/rfc1035 Section 4.1.1
-    fn deserialize_header_section(buf: &[u8]) -> DNSQueryHeaderSection {
-        let id_array = slice_as_array!(&buf[..2], [u8; 2]).expect("bad slice length");
-        let id = u16::from_be_bytes(*id_array);
+    fn deserialize_header_section(buf: &[u8]) -> Result<DNSQueryHeaderSection, ParseError> {
+        // Every field below lies within the first HEADER_LENGTH octets.
+        if buf.len() < HEADER_LENGTH {
+            return Err(ParseError::UnexpectedEnd(buf.len()));
+        }
+
+        let id = read_u16(buf, 0)?;
         let is_query = !is_ith_bit_set(buf, 16);
         let op_code = get_op_code(buf);
         let is_authoritative_answer = is_ith_bit_set(buf, 21);
         let is_truncated = is_ith_bit_set(buf, 22);
         let is_recursion_desired = is_ith_bit_set(buf, 23);
         let is_recursion_available = is_ith_bit_set(buf, 24);
+        let is_authentic_data = is_ith_bit_set(buf, 26);
+        let is_checking_disabled = is_ith_bit_set(buf, 27);
         let response_code = get_response_code(buf);
-        let questions_count = get_questions_count(buf);
-        let answers_count = get_answers_count(buf);
-        let ns_rr_count = get_ns_rr_count(buf);
-        let additional_rr_count = get_additional_rr_count(buf);
-        DNSQueryHeaderSection {
+        let questions_count = read_u16(buf, 32 / 8)?;
+        let answers_count = read_u16(buf, 48 / 8)?;
+        let ns_rr_count = read_u16(buf, 64 / 8)?;
+        let additional_rr_count = read_u16(buf, 80 / 8)?;
+        Ok(DNSQueryHeaderSection {
             id,
             is_query,
             op_code,
@@ -687,147 +1450,289 @@ impl DNSQuery {
             is_truncated,
             is_recursion_desired,
             is_recursion_available,
+            is_authentic_data,
+            is_checking_disabled,
             response_code,
             questions_count,
             answers_count,
             ns_rr_count,
             additional_rr_count,
-        }
+        })
     }
 
     fn deserialize_question_section(
         buf: &[u8],
         questions_count: u16,
-    ) -> (Vec<DNSQuestionQuery>, usize) {
+    ) -> Result<(Vec<DNSQuestionQuery>, usize), ParseError> {
         let mut queries = Vec::new();
-        let mut index = 96 / 8;
+        let mut index = HEADER_LENGTH;
         for _ in 0..questions_count {
-            let mut labels = Vec::new();
-            loop {
-                let octet_length = buf[index];
-                if octet_length == 0 {
-                    if index == 96 / 8 {
-                        labels.push(".");
-                    }
-                    break;
-                }
-                let label_bytes = &buf[index + 1..index + 1 + octet_length as usize];
-                let label = std::str::from_utf8(label_bytes).unwrap();
-                labels.push(label);
-                index += octet_length as usize + 1;
-            }
-            let qtype_u16 = convert_slice_to_u16(&buf[index + 1..index + 3]);
+            let (qname, updated_index) = read_labels(buf, index)?;
+            index = updated_index;
+
+            let qtype_u16 = read_u16(buf, index)?;
+            let qclass_u16 = read_u16(buf, index + 2)?;
             let query = DNSQuestionQuery {
-                qname: String::from(labels.join(".")),
-                qtype: QType::decimal_to_qtype(qtype_u16).unwrap(),
-                qclass: QClass::IN,
+                qname,
+                qtype: QType::decimal_to_qtype(qtype_u16),
+                qclass: QClass::from_u16(qclass_u16)?,
             };
             queries.push(query);
 
             // move index by four octets: two octets for type and two for class.
-            index += 5;
+            index += 4;
         }
 
-        (queries, index)
+        Ok((queries, index))
     }
 
     fn deserialize_resource_records(
         buf: &[u8],
         mut index: usize,
         records_count: u16,
-    ) -> (Vec<ResourceRecord>, usize) {
+    ) -> Result<(Vec<ResourceRecord>, usize), ParseError> {
         if records_count == 0 {
-            return (vec![], index);
+            return Ok((vec![], index));
         }
 
-        let mut records = Vec::with_capacity(records_count as usize);
+        // Do not trust the count for the allocation,  every record takes at least 11 octets.
+        let mut records = Vec::with_capacity((records_count as usize).min(buf.len() / 11));
 
-        // parse name.
         for _ in 0..records_count {
-            // parse name
-            let (name, updated_index) = read_labels(&buf, index);
+            let (record, updated_index) = DNSQuery::deserialize_resource_record(buf, index)?;
+            records.push(record);
             index = updated_index;
+        }
 
-            // type.
-            let type_code: u16 = convert_slice_to_u16(&buf[index..index + 2]);
-            index += 2;
+        Ok((records, index))
+    }
 
-            // class.
-            let class_code: u16 = convert_slice_to_u16(&buf[index..index + 2]);
-            index += 2;
+    // Same as deserialize_resource_records but lifts the OPT pseudo-record out of the section.
+    fn deserialize_additional_section(
+        buf: &[u8],
+        mut index: usize,
+        records_count: u16,
+    ) -> Result<(Vec<ResourceRecord>, Option<Edns>, usize), ParseError> {
+        let mut records = vec![];
+        let mut edns = None;
 
-            // ttl.
-            let ttl: u32 = convert_slice_to_u32(&buf[index..index + 4]);
-            index += 4;
+        for _ in 0..records_count {
+            let (_, type_index) = read_labels(buf, index)?;
+            if read_u16(buf, type_index)? == OPT_TYPE_CODE {
+                // https://tools.ietf.org/html/rfc6891 6.1.1
+                if edns.is_some() {
+                    return Err(ParseError::DuplicateOpt(index));
+                }
+                let (opt, updated_index) = Edns::deserialize(buf, type_index + 2)?;
+                edns = Some(opt);
+                index = updated_index;
+                continue;
+            }
 
-            // rdlength
-            let rd_length: u16 = convert_slice_to_u16(&buf[index..index + 2]);
-            index += 2;
+            let (record, updated_index) = DNSQuery::deserialize_resource_record(buf, index)?;
+            records.push(record);
+            index = updated_index;
+        }
 
-            // parse rr data
-            let rd_data: &[u8] = &buf[index..index + rd_length as usize];
+        Ok((records, edns, index))
+    }
 
-            let type_with_data: Type = match type_code {
-                1 => {
-                    let ipv4_addr = Ipv4Addr::new(rd_data[0], rd_data[1], rd_data[2], rd_data[3]);
-                    Type::A(ipv4_addr)
-                }
-                2 => {
-                    let (ns_dname, _) = read_labels(buf, index);
-                    Type::NS(ns_dname)
-                }
-                5 => {
-                    let (cname, _) = read_labels(buf, index);
-                    Type::CNAME(cname)
-                }
-                6 => {
-                    let data = SOAData::deserialize(buf, index, rd_length);
-                    Type::SOA(data)
+    fn deserialize_resource_record(
+        buf: &[u8],
+        mut index: usize,
+    ) -> Result<(ResourceRecord, usize), ParseError> {
+        let record_offset = index;
+
+        // parse name
+        let (name, updated_index) = read_labels(buf, index)?;
+        index = updated_index;
+
+        // type.
+        let type_code: u16 = read_u16(buf, index)?;
+        index += 2;
+
+        // class.
+        let class_code: u16 = read_u16(buf, index)?;
+        index += 2;
+
+        // ttl.
+        let ttl: u32 = read_u32(buf, index)?;
+        index += 4;
+
+        // rdlength
+        let rd_length: u16 = read_u16(buf, index)?;
+        index += 2;
+
+        // parse rr data
+        let rd_data: &[u8] = read_slice(buf, index, rd_length as usize)?;
+        let rd_end = index + rd_length as usize;
+
+        // Names inside RDATA may point anywhere before them,  but their uncompressed part must
+        // stay within RDLENGTH.
+        let read_rdata_labels = |offset: usize| -> Result<String, ParseError> {
+            let (labels, end) = read_labels(buf, offset)?;
+            if end > rd_end {
+                return Err(ParseError::BadRData(record_offset));
+            }
+            Ok(labels)
+        };
+
+        let type_with_data: Type = match type_code {
+            1 => {
+                if rd_data.len() != 4 {
+                    return Err(ParseError::BadRData(record_offset));
                 }
-                12 => {
-                    let (ptr_dname, _) = read_labels(buf, index);
-                    Type::PTR(ptr_dname)
+                let ipv4_addr = Ipv4Addr::new(rd_data[0], rd_data[1], rd_data[2], rd_data[3]);
+                Type::A(ipv4_addr)
+            }
+            2 => Type::NS(read_rdata_labels(index)?),
+            5 => Type::CNAME(read_rdata_labels(index)?),
+            6 => {
+                let data = SOAData::deserialize(buf, index, rd_length)?;
+                Type::SOA(data)
+            }
+            12 => Type::PTR(read_rdata_labels(index)?),
+            15 => Type::MX(MXData::deserialize(buf, index, rd_length)?),
+            16 => Type::TXT(TXTData::deserialize(rd_data, record_offset)?),
+            28 => {
+                if rd_data.len() != 16 {
+                    return Err(ParseError::BadRData(record_offset));
                 }
-                // 15 => Type::MX,
-                16 => Type::TXT(TXTData {
-                    length: rd_length as u8 - 1,
-                    data: read_txt(&rd_data[1..]),
-                }),
-                28 => {
-                    let ipv6_addr = Ipv6Addr::new(
-                        convert_slice_to_u16(&rd_data[0..2]),
-                        convert_slice_to_u16(&rd_data[2..4]),
-                        convert_slice_to_u16(&rd_data[4..6]),
-                        convert_slice_to_u16(&rd_data[6..8]),
-                        convert_slice_to_u16(&rd_data[8..10]),
-                        convert_slice_to_u16(&rd_data[10..12]),
-                        convert_slice_to_u16(&rd_data[12..14]),
-                        convert_slice_to_u16(&rd_data[14..16]),
-                    );
-                    Type::AAAA(ipv6_addr)
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(rd_data);
+                Type::AAAA(Ipv6Addr::from(octets))
+            }
+            33 => Type::SRV(SRVData::deserialize(buf, index, rd_length)?),
+            35 => Type::NAPTR(NAPTRData::deserialize(buf, index, rd_length)?),
+            257 => Type::CAA(CAAData::deserialize(buf, index, rd_length)?),
+            43 => Type::DS(DSData::deserialize(rd_data, index)?),
+            46 => Type::RRSIG(RRSIGData::deserialize(buf, index, rd_length)?),
+            47 => Type::NSEC(NSECData::deserialize(buf, index, rd_length)?),
+            48 => Type::DNSKEY(DNSKEYData::deserialize(rd_data, index)?),
+            50 => Type::NSEC3(NSEC3Data::deserialize(rd_data, index)?),
+            // https://tools.ietf.org/html/rfc3597 4
+            // RDATA of these RFC 1035 types may hold compressed names,  so it cannot be passed
+            // along opaquely.
+            7 | 8 | 9 | 14 => return Err(ParseError::UnsupportedType(type_code)),
+            _ => Type::Unknown {
+                code: type_code,
+                rdata: rd_data.to_vec(),
+            },
+        };
+        let answer = ResourceRecord {
+            name,
+            r#type: type_with_data,
+            class: Class::to_class(class_code)?,
+            ttl,
+            rd_length,
+        };
+        index = rd_end;
+
+        Ok((answer, index))
+    }
+}
+
+#[cfg(test)]
+impl DNSQuery {
+    // Recursive query for qname,  the way a stub resolver would ask it.
+    pub fn for_test(qname: &str, qtype: QType) -> Self {
+        DNSQuery {
+            header: DNSQueryHeaderSection {
+                id: 1,
+                is_query: true,
+                op_code: OpCode::Query,
+                is_authoritative_answer: false,
+                is_truncated: false,
+                is_recursion_desired: true,
+                is_recursion_available: false,
+                is_authentic_data: false,
+                is_checking_disabled: false,
+                response_code: ResponseCode::NoError,
+                questions_count: 1,
+                answers_count: 0,
+                ns_rr_count: 0,
+                additional_rr_count: 0,
+            },
+            questions: vec![DNSQuestionQuery {
+                qname: qname.to_string(),
+                qtype,
+                qclass: QClass::IN,
+            }],
+            additionals: vec![],
+            edns: None,
+        }
+    }
+}
+
+fn read_txt(buf: &[u8]) -> Option<String> {
+    std::str::from_utf8(buf).ok().map(String::from)
+}
+
+// https://tools.ietf.org/html/rfc1035 3.3
+// <character-string> is a single length octet followed by that number of characters.
+fn read_character_string(buf: &[u8], offset: usize) -> Result<(Vec<u8>, usize), ParseError> {
+    let length = read_u8(buf, offset)? as usize;
+    let bytes = read_slice(buf, offset + 1, length)?;
+    Ok((bytes.to_vec(), offset + 1 + length))
+}
+
+// https://tools.ietf.org/html/rfc4034 4.1.2
+// Window blocks of (window number, bitmap length, bitmap).  None when they are malformed.
+fn read_type_bitmaps(buf: &[u8]) -> Option<Vec<QType>> {
+    let mut types = vec![];
+    let mut index = 0;
+    while index < buf.len() {
+        let window = *buf.get(index)? as u16;
+        let length = *buf.get(index + 1)? as usize;
+        if length == 0 || length > 32 {
+            return None;
+        }
+        let bitmap = buf.get(index + 2..index + 2 + length)?;
+        for (i, octet) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if octet & (0x80 >> bit) != 0 {
+                    let code = window * 256 + (i * 8 + bit) as u16;
+                    types.push(QType::decimal_to_qtype(code));
                 }
-                _ => panic!("deserialize: type code not supported: "),
-            };
-            let answer = ResourceRecord {
-                name,
-                r#type: type_with_data,
-                class: Class::to_class(class_code).unwrap(),
-                ttl,
-                rd_length,
-            };
-            records.push(answer);
-            index += rd_length as usize;
+            }
         }
+        index += 2 + length;
+    }
+    Some(types)
+}
 
-        (records, index)
+fn write_type_bitmaps(types: &[QType]) -> Vec<u8> {
+    let mut codes: Vec<u16> = types.iter().map(QType::to_u16).collect();
+    codes.sort_unstable();
+    codes.dedup();
+
+    let mut result = vec![];
+    for window in codes
+        .iter()
+        .map(|code| (code >> 8) as u8)
+        .collect::<std::collections::BTreeSet<u8>>()
+    {
+        let mut bitmap = [0u8; 32];
+        let mut length = 0;
+        for code in codes.iter().filter(|code| (*code >> 8) as u8 == window) {
+            let low = (code & 0xff) as usize;
+            bitmap[low / 8] |= 0x80 >> (low % 8);
+            length = low / 8 + 1;
+        }
+        result.push(window);
+        result.push(length as u8);
+        result.extend_from_slice(&bitmap[..length]);
     }
+    result
 }
 
-fn read_txt(buf: &[u8]) -> String {
-    String::from(std::str::from_utf8(buf).unwrap())
+fn write_character_string(string: &[u8]) -> Vec<u8> {
+    let mut result = vec![string.len() as u8];
+    result.extend_from_slice(string);
+    result
 }
 
-fn write_labels(domain: &str) -> Vec<u8> {
+pub fn write_labels(domain: &str) -> Vec<u8> {
     if domain == "." {
         return vec![0];
     }
@@ -854,42 +1759,71 @@ fn write_labels(domain: &str) -> Vec<u8> {
     result
 }
 
-fn read_labels(buf: &[u8], offset: usize) -> (String, usize) {
+// https://tools.ietf.org/html/rfc1035 4.1.4
+// Returns the name at offset and the index right after it.  Every compression pointer must point
+// before the part of the name read so far,  so a name can never loop back on itself.
+fn read_labels(buf: &[u8], offset: usize) -> Result<(String, usize), ParseError> {
     let mut index = offset;
+    let mut lowest_index = offset;
+    let mut end_of_name = None;
+    let mut pointers_count = 0;
+    let mut name_length = 1; // terminating root label.
     let mut labels = Vec::new();
-    if buf[index] == 0 {
-        return (".".to_string(), index + 1);
-    }
     loop {
-        let octet_length = buf[index];
-        if octet_length & 0xC0 == 0xC0 {
-            // we have encountered a pointer.
-            let pointer_to_label = convert_slice_to_u16(&buf[index..index + 2]) & 0x3FFF;
-            let (labels_from_pointer, _) = read_labels(&buf, pointer_to_label as usize);
-            labels.push(labels_from_pointer);
-            index += 1;
-            break;
-        }
-        if octet_length == 0 {
-            break;
+        let octet_length = read_u8(buf, index)?;
+        match octet_length & 0xC0 {
+            0xC0 => {
+                // we have encountered a pointer.
+                let pointer_to_label = (read_u16(buf, index)? & 0x3FFF) as usize;
+                if pointer_to_label >= lowest_index {
+                    return Err(ParseError::BadPointer(index));
+                }
+                pointers_count += 1;
+                if pointers_count > MAX_POINTERS_PER_NAME {
+                    return Err(ParseError::TooManyPointers(offset));
+                }
+                if end_of_name.is_none() {
+                    end_of_name = Some(index + 2);
+                }
+                index = pointer_to_label;
+                lowest_index = pointer_to_label;
+            }
+            0x00 => {
+                if octet_length == 0 {
+                    break;
+                }
+                // Top two bits being zero caps the label at 63 octets.
+                let length = octet_length as usize;
+                name_length += length + 1;
+                if name_length > MAX_NAME_LENGTH {
+                    return Err(ParseError::NameTooLong(offset));
+                }
+                let label_bytes = read_slice(buf, index + 1, length)?;
+                let label =
+                    std::str::from_utf8(label_bytes).map_err(|_| ParseError::BadLabel(index))?;
+                labels.push(label);
+                index += length + 1;
+            }
+            _ => return Err(ParseError::BadLabelType(index)),
         }
-        let label_bytes = &buf[index + 1..index + 1 + octet_length as usize];
-        let label = std::str::from_utf8(label_bytes).unwrap();
-        labels.push(String::from(label));
-        index += octet_length as usize + 1;
     }
-    let mut joined_labels = labels.join(".");
-    if joined_labels.len() > 1 && !joined_labels.ends_with(".") {
-        joined_labels.push('.');
+
+    let end_of_name = end_of_name.unwrap_or(index + 1);
+    if labels.is_empty() {
+        return Ok((".".to_string(), end_of_name));
     }
-    (joined_labels, index + 1)
+    let mut joined_labels = labels.join(".");
+    joined_labels.push('.');
+    Ok((joined_labels, end_of_name))
 }
+
 fn get_response_code(buf: &[u8]) -> ResponseCode {
     let index_in_buf: usize = 28 / 8;
     let response_code = buf[index_in_buf] & 15;
     match response_code {
         0 => ResponseCode::NoError,
         1 => ResponseCode::FormatError,
+        2 => ResponseCode::ServerFailure,
         3 => ResponseCode::NameError,
         4 => ResponseCode::NotImplemented,
         5 => ResponseCode::Refused,
@@ -897,35 +1831,9 @@ fn get_response_code(buf: &[u8]) -> ResponseCode {
     }
 }
 
-fn get_questions_count(buf: &[u8]) -> u16 {
-    let index: usize = 32 / 8;
-    let qdcount_bytes = slice_as_array!(&buf[index..index + 2], [u8; 2]).expect("bad slice length");
-    u16::from_be_bytes(*qdcount_bytes)
-}
-
-fn get_answers_count(buf: &[u8]) -> u16 {
-    let index: usize = 48 / 8;
-    let ancount_bytes = slice_as_array!(&buf[index..index + 2], [u8; 2]).expect("bad slice length");
-    u16::from_be_bytes(*ancount_bytes)
-}
-
-fn get_ns_rr_count(buf: &[u8]) -> u16 {
-    let index: usize = 64 / 8;
-    let ns_records_count_bytes =
-        slice_as_array!(&buf[index..index + 2], [u8; 2]).expect("bad slice length");
-    u16::from_be_bytes(*ns_records_count_bytes)
-}
-
-fn get_additional_rr_count(buf: &[u8]) -> u16 {
-    let index: usize = 80 / 8;
-    let additional_rr_count_bytes =
-        slice_as_array!(&buf[index..index + 2], [u8; 2]).expect("bad slice length");
-    u16::from_be_bytes(*additional_rr_count_bytes)
-}
-
 fn get_op_code(buf: &[u8]) -> OpCode {
     let index_in_buf: usize = 17 / 8;
-    let op_code = buf[index_in_buf] & 120;
+    let op_code = (buf[index_in_buf] & 120) >> 3;
     match op_code {
         0 => OpCode::Query,
         1 => OpCode::IQuery,
@@ -936,15 +1844,26 @@ fn get_op_code(buf: &[u8]) -> OpCode {
     }
 }
 
+fn read_slice(buf: &[u8], offset: usize, length: usize) -> Result<&[u8], ParseError> {
+    buf.get(offset..offset + length)
+        .ok_or(ParseError::UnexpectedEnd(buf.len()))
+}
+
+fn read_u8(buf: &[u8], offset: usize) -> Result<u8, ParseError> {
+    buf.get(offset)
+        .copied()
+        .ok_or(ParseError::UnexpectedEnd(buf.len()))
+}
+
 // 2 octets to u16.
-fn convert_slice_to_u16(slice: &[u8]) -> u16 {
-    let slice_as_array = slice_as_array!(&slice[..2], [u8; 2]).expect("bad slice length");
-    u16::from_be_bytes(*slice_as_array)
+fn read_u16(buf: &[u8], offset: usize) -> Result<u16, ParseError> {
+    let slice = read_slice(buf, offset, 2)?;
+    Ok(u16::from_be_bytes([slice[0], slice[1]]))
 }
 
-fn convert_slice_to_u32(slice: &[u8]) -> u32 {
-    let slice_as_array = slice_as_array!(&slice[..4], [u8; 4]).expect("bad slice length");
-    u32::from_be_bytes(*slice_as_array)
+fn read_u32(buf: &[u8], offset: usize) -> Result<u32, ParseError> {
+    let slice = read_slice(buf, offset, 4)?;
+    Ok(u32::from_be_bytes([slice[0], slice[1], slice[2], slice[3]]))
 }
 
 fn transform_u32_to_array_of_u8(x: u32) -> [u8; 4] {
@@ -967,9 +1886,22 @@ fn is_ith_bit_set(buf: &[u8], i: usize) -> bool {
 #[cfg(test)]
 mod tests {
     use super::{
-        DNSQuery, DNSQueryHeaderSection, DNSQueryResponse, DNSQuestionQuery, OpCode, QClass, QType,
-        ResponseCode, SOAData,
+        read_labels, CAAData, DNSQuery, DNSQueryHeaderSection, DNSQueryResponse, DNSQuestionQuery,
+        Edns, EdnsOption, MXData, NAPTRData, NSECData, OpCode, QClass, QType, RRSIGData,
+        ResponseCode, SOAData, SRVData, TXTData, Type,
     };
+    use crate::error::ParseError;
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+
+    // lafolle.ca. A 1.2.3.4,  answer owner is a pointer to the question.
+    fn raw_response() -> Vec<u8> {
+        vec![
+            0, 1, 129, 128, 0, 1, 0, 1, 0, 0, 0, 0, // header
+            7, 108, 97, 102, 111, 108, 108, 101, 2, 99, 97, 0, 0, 1, 0, 1, // question
+            192, 12, 0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 1, 2, 3, 4, // answer
+        ]
+    }
     #[test]
     fn DNSQueryHeaderSection_serialize_id() {
         // Arrange
@@ -983,6 +1915,8 @@ mod tests {
             is_truncated: false,
             is_recursion_desired: true,
             is_recursion_available: false,
+            is_authentic_data: false,
+            is_checking_disabled: false,
             response_code: ResponseCode::NoError,
             questions_count: 1,
             answers_count: 0,
@@ -999,6 +1933,40 @@ mod tests {
         assert_eq!(expected[1], actual[1]);
     }
 
+    #[test]
+    fn dns_query_header_section_serialize_truncated() {
+        // Arrange
+        let header_section = DNSQueryHeaderSection {
+            id: 22015,
+            is_query: false,
+            op_code: OpCode::Query,
+            is_authoritative_answer: false,
+            is_truncated: true,
+            is_recursion_desired: true,
+            is_recursion_available: false,
+            is_authentic_data: false,
+            is_checking_disabled: false,
+            response_code: ResponseCode::NoError,
+            questions_count: 0,
+            answers_count: 0,
+            ns_rr_count: 0,
+            additional_rr_count: 0,
+        };
+
+        // Act
+        let actual = header_section.serialize();
+
+        // Assert
+        assert_eq!(actual[2], 0b10000011);
+        assert!(
+            DNSQuery::deserialize(&actual)
+                .unwrap()
+                .0
+                .header
+                .is_truncated
+        );
+    }
+
     #[test]
     fn DNSQuestionQuery_serialize() {
         // Arrange
@@ -1039,6 +2007,8 @@ mod tests {
                     is_truncated: false,
                     is_recursion_desired: true,
                     is_recursion_available: false,
+                    is_authentic_data: false,
+                    is_checking_disabled: false,
                     response_code: ResponseCode::NoError,
 
                     questions_count: 1,
@@ -1048,6 +2018,7 @@ mod tests {
                 },
                 questions: vec![],
                 additionals: vec![],
+                edns: None,
             },
             answers: vec![],
             authority: vec![],
@@ -1106,6 +2077,553 @@ mod tests {
         }
     }
 
+    #[test]
+    fn dns_query_edns_round_trip() {
+        // Arrange
+        let mut edns = Edns::new(1232);
+        edns.dnssec_ok = true;
+        edns.options.push(EdnsOption {
+            code: 10, // COOKIE
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        });
+        let query = DNSQuery {
+            header: DNSQueryHeaderSection {
+                id: 7,
+                is_query: true,
+                op_code: OpCode::Query,
+                is_authoritative_answer: false,
+                is_truncated: false,
+                is_recursion_desired: true,
+                is_recursion_available: false,
+                is_authentic_data: false,
+                is_checking_disabled: false,
+                response_code: ResponseCode::NoError,
+                questions_count: 1,
+                answers_count: 0,
+                ns_rr_count: 0,
+                additional_rr_count: 0,
+            },
+            questions: vec![DNSQuestionQuery {
+                qname: "lafolle.ca.".to_string(),
+                qtype: QType::A,
+                qclass: QClass::IN,
+            }],
+            additionals: vec![],
+            edns: Some(edns.clone()),
+        };
+
+        // Act
+        let raw = query.serialize();
+        let (actual, offset) = DNSQuery::deserialize(&raw).unwrap();
+
+        // Assert
+        assert_eq!(actual.header.additional_rr_count, 1);
+        assert_eq!(actual.edns, Some(edns));
+        assert!(actual.additionals.is_empty());
+        assert_eq!(offset, raw.len());
+    }
+
+    #[test]
+    fn dns_query_without_edns() {
+        // Arrange
+        // dig lafolle.ca +noedns
+        let raw = vec![
+            0, 7, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 7, 108, 97, 102, 111, 108, 108, 101, 2, 99, 97, 0,
+            0, 1, 0, 1,
+        ];
+
+        // Act
+        let (actual, _) = DNSQuery::deserialize(&raw).unwrap();
+
+        // Assert
+        assert_eq!(actual.edns, None);
+        assert_eq!(actual.serialize(), raw);
+    }
+
+    #[test]
+    fn dns_query_response_deserialize_compressed_name() {
+        // Arrange
+        let raw = raw_response();
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw).unwrap();
+
+        // Assert
+        assert_eq!(actual.query.questions[0].qname, "lafolle.ca.");
+        assert_eq!(actual.answers[0].name, "lafolle.ca.");
+        assert_eq!(actual.answers[0].r#type, Type::A(Ipv4Addr::new(1, 2, 3, 4)));
+    }
+
+    #[test]
+    fn dns_query_response_deserialize_every_truncation_fails() {
+        // Arrange
+        let raw = raw_response();
+
+        for length in 0..raw.len() {
+            // Act
+            let actual = DNSQueryResponse::deserialize(&raw[..length]);
+
+            // Assert
+            assert!(actual.is_err(), "prefix of {} octets parsed", length);
+        }
+    }
+
+    #[test]
+    fn dns_query_response_deserialize_bad_rdata_length() {
+        // Arrange
+        let mut raw = raw_response();
+        raw[39] = 3; // A with RDLENGTH 3.
+        raw.pop();
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw);
+
+        // Assert
+        assert_eq!(actual.unwrap_err(), ParseError::BadRData(28));
+    }
+
+    #[test]
+    fn read_labels_pointer_to_itself() {
+        // Arrange
+        let raw = vec![0, 0, 192, 2];
+
+        // Act
+        let actual = read_labels(&raw, 2);
+
+        // Assert
+        assert_eq!(actual.unwrap_err(), ParseError::BadPointer(2));
+    }
+
+    #[test]
+    fn read_labels_pointer_loop() {
+        // Arrange
+        // "a" followed by a pointer back to itself: 1 | a | ptr(0)
+        let raw = vec![1, 97, 192, 0];
+
+        // Act
+        let actual = read_labels(&raw, 0);
+
+        // Assert
+        assert_eq!(actual.unwrap_err(), ParseError::BadPointer(2));
+    }
+
+    #[test]
+    fn read_labels_forward_pointer() {
+        // Arrange
+        let raw = vec![192, 2, 1, 97, 0];
+
+        // Act
+        let actual = read_labels(&raw, 0);
+
+        // Assert
+        assert_eq!(actual.unwrap_err(), ParseError::BadPointer(0));
+    }
+
+    #[test]
+    fn read_labels_name_too_long() {
+        // Arrange
+        let mut raw = vec![];
+        for _ in 0..5 {
+            raw.push(63);
+            raw.extend_from_slice(&[97; 63]);
+        }
+        raw.push(0);
+
+        // Act
+        let actual = read_labels(&raw, 0);
+
+        // Assert
+        assert_eq!(actual.unwrap_err(), ParseError::NameTooLong(0));
+    }
+
+    #[test]
+    fn read_labels_reserved_label_type() {
+        // Arrange
+        let raw = vec![64, 97, 0];
+
+        // Act
+        let actual = read_labels(&raw, 0);
+
+        // Assert
+        assert_eq!(actual.unwrap_err(), ParseError::BadLabelType(0));
+    }
+
+    #[test]
+    fn dns_query_response_format_error() {
+        // Arrange
+        let raw = vec![0, 7, 1];
+
+        // Act
+        let actual = DNSQueryResponse::format_error(&raw).unwrap();
+
+        // Assert
+        assert_eq!(actual.query.header.id, 7);
+        assert_eq!(actual.query.header.response_code, ResponseCode::FormatError);
+        assert!(actual.query.header.is_recursion_desired);
+        assert!(DNSQueryResponse::format_error(&raw[..1]).is_none());
+    }
+
+    #[test]
+    fn dns_query_response_unknown_type_round_trip() {
+        // Arrange
+        // lafolle.ca. TYPE65 \# 3 010203,  without compression so that it serializes back as is.
+        let raw = vec![
+            0, 1, 129, 128, 0, 1, 0, 1, 0, 0, 0, 0, // header
+            7, 108, 97, 102, 111, 108, 108, 101, 2, 99, 97, 0, 0, 65, 0, 1, // question
+            7, 108, 97, 102, 111, 108, 108, 101, 2, 99, 97, 0, 0, 65, 0, 1, 0, 0, 1, 44, 0, 3, 1,
+            2, 3, // answer
+        ];
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw).unwrap();
+
+        // Assert
+        assert_eq!(actual.query.questions[0].qtype, QType::Unknown(65));
+        assert_eq!(
+            actual.answers[0].r#type,
+            Type::Unknown {
+                code: 65,
+                rdata: vec![1, 2, 3]
+            }
+        );
+        assert_eq!(actual.answers[0].r#type.to_qtype(), QType::Unknown(65));
+        assert_eq!(actual.serialize(), raw);
+    }
+
+    // lafolle.ca. answer of the given type and rdata,  without compression in the owner name.
+    fn raw_answer(type_code: u16, rdata: &[u8]) -> Vec<u8> {
+        let [type_high, type_low] = type_code.to_be_bytes();
+        let mut raw = vec![
+            0, 1, 129, 128, 0, 1, 0, 1, 0, 0, 0, 0, // header
+            7, 108, 97, 102, 111, 108, 108, 101, 2, 99, 97, 0, type_high, type_low, 0,
+            1, // question
+            7, 108, 97, 102, 111, 108, 108, 101, 2, 99, 97, 0, type_high, type_low, 0, 1, 0, 0, 1,
+            44, // answer
+        ];
+        raw.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        raw.extend_from_slice(rdata);
+        raw
+    }
+
+    #[test]
+    fn dns_query_response_min_ttl() {
+        // Arrange
+        let mut response = DNSQueryResponse::deserialize(&raw_response()).unwrap();
+        let mut soa = response.answers[0].clone();
+        soa.ttl = 60;
+
+        // Act
+        let answer_only = response.min_ttl();
+        response.authority.push(soa);
+        let with_authority = response.min_ttl();
+        response.answers.clear();
+        response.authority.clear();
+        let empty = response.min_ttl();
+
+        // Assert
+        assert_eq!(answer_only, Some(300));
+        assert_eq!(with_authority, Some(60));
+        assert_eq!(empty, None);
+    }
+
+    #[test]
+    fn dns_query_response_mx_compressed_exchange() {
+        // Arrange
+        // lafolle.ca. MX 10 mail.lafolle.ca.,  exchange ends with a pointer to the question.
+        let raw = raw_answer(15, &[0, 10, 4, 109, 97, 105, 108, 192, 12]);
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw).unwrap();
+
+        // Assert
+        let expected = MXData {
+            preference: 10,
+            exchange: "mail.lafolle.ca.".to_string(),
+        };
+        assert_eq!(actual.answers[0].r#type, Type::MX(expected.clone()));
+        assert_eq!(actual.answers[0].r#type.to_qtype(), QType::MX);
+        assert_eq!(
+            actual.serialize(),
+            raw_answer(15, &expected.serialize()),
+            "exchange is written back uncompressed"
+        );
+    }
+
+    #[test]
+    fn dns_query_response_srv_round_trip() {
+        // Arrange
+        // lafolle.ca. SRV 1 2 443 lafolle.ca.
+        let raw = raw_answer(
+            33,
+            &[
+                0, 1, 0, 2, 1, 187, 7, 108, 97, 102, 111, 108, 108, 101, 2, 99, 97, 0,
+            ],
+        );
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw).unwrap();
+
+        // Assert
+        assert_eq!(
+            actual.answers[0].r#type,
+            Type::SRV(SRVData {
+                priority: 1,
+                weight: 2,
+                port: 443,
+                target: "lafolle.ca.".to_string(),
+            })
+        );
+        assert_eq!(actual.serialize(), raw);
+    }
+
+    #[test]
+    fn dns_query_response_caa_round_trip() {
+        // Arrange
+        // lafolle.ca. CAA 0 issue "ca.ca"
+        let raw = raw_answer(257, &[0, 5, 105, 115, 115, 117, 101, 99, 97, 46, 99, 97]);
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw).unwrap();
+
+        // Assert
+        assert_eq!(
+            actual.answers[0].r#type,
+            Type::CAA(CAAData {
+                flags: 0,
+                tag: "issue".to_string(),
+                value: b"ca.ca".to_vec(),
+            })
+        );
+        assert_eq!(actual.serialize(), raw);
+    }
+
+    #[test]
+    fn dns_query_response_caa_value_is_octets() {
+        // Arrange
+        // lafolle.ca. CAA 0 iodef "\255\000",  not UTF-8.
+        let raw = raw_answer(257, &[0, 5, 105, 111, 100, 101, 102, 255, 0]);
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw).unwrap();
+
+        // Assert
+        assert_eq!(
+            actual.answers[0].r#type,
+            Type::CAA(CAAData {
+                flags: 0,
+                tag: "iodef".to_string(),
+                value: vec![255, 0],
+            })
+        );
+        assert_eq!(actual.serialize(), raw);
+    }
+
+    #[test]
+    fn dns_query_response_txt_strings_round_trip() {
+        // Arrange
+        // lafolle.ca. TXT "v=DKIM1" "" "\255",  the last one is not UTF-8.
+        let raw = raw_answer(16, &[7, 118, 61, 68, 75, 73, 77, 49, 0, 1, 255]);
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw).unwrap();
+
+        // Assert
+        assert_eq!(
+            actual.answers[0].r#type,
+            Type::TXT(TXTData {
+                strings: vec![b"v=DKIM1".to_vec(), vec![], vec![255]],
+            })
+        );
+        assert_eq!(actual.serialize(), raw);
+    }
+
+    #[test]
+    fn dns_query_response_txt_over_255_octets() {
+        // Arrange
+        let mut rdata = vec![200];
+        rdata.extend_from_slice(&[b'a'; 200]);
+        rdata.push(150);
+        rdata.extend_from_slice(&[b'b'; 150]);
+        let raw = raw_answer(16, &rdata);
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw).unwrap();
+
+        // Assert
+        assert_eq!(
+            actual.answers[0].r#type,
+            Type::TXT(TXTData {
+                strings: vec![vec![b'a'; 200], vec![b'b'; 150]],
+            })
+        );
+        assert_eq!(actual.serialize(), raw);
+    }
+
+    #[test]
+    fn dns_query_response_txt_string_overflows_rdata() {
+        // Arrange
+        let raw = raw_answer(16, &[3, 97, 98, 3, 97]);
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw);
+
+        // Assert
+        assert!(matches!(actual, Err(ParseError::BadRData(_))));
+    }
+
+    #[test]
+    fn dns_query_response_caa_tag_overflows_rdata() {
+        // Arrange
+        let raw = raw_answer(257, &[0, 9, 105, 115, 115]);
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw);
+
+        // Assert
+        assert_eq!(actual.unwrap_err(), ParseError::BadRData(50));
+    }
+
+    #[test]
+    fn dns_query_response_naptr_round_trip() {
+        // Arrange
+        // lafolle.ca. NAPTR 100 10 "S" "SIP+D2U" "\255" _sip._udp.lafolle.ca.
+        let mut rdata = vec![0, 100, 0, 10, 1, 83, 7, 83, 73, 80, 43, 68, 50, 85, 1, 255];
+        rdata.extend_from_slice(&[4, 95, 115, 105, 112, 4, 95, 117, 100, 112]);
+        rdata.extend_from_slice(&[7, 108, 97, 102, 111, 108, 108, 101, 2, 99, 97, 0]);
+        let raw = raw_answer(35, &rdata);
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw).unwrap();
+
+        // Assert
+        assert_eq!(
+            actual.answers[0].r#type,
+            Type::NAPTR(NAPTRData {
+                order: 100,
+                preference: 10,
+                flags: b"S".to_vec(),
+                services: b"SIP+D2U".to_vec(),
+                regexp: vec![255],
+                replacement: "_sip._udp.lafolle.ca.".to_string(),
+            })
+        );
+        assert_eq!(actual.serialize(), raw);
+    }
+
+    #[test]
+    fn dns_query_response_mx_trailing_rdata() {
+        // Arrange
+        let raw = raw_answer(15, &[0, 10, 0, 1]);
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw);
+
+        // Assert
+        assert_eq!(actual.unwrap_err(), ParseError::BadRData(50));
+    }
+
+    #[test]
+    fn dns_query_response_nsec_type_bitmaps() {
+        // Arrange
+        // https://tools.ietf.org/html/rfc4034 4.3
+        let mut rdata = vec![4, 104, 111, 115, 116, 7, 101, 120, 97, 109, 112, 108, 101];
+        rdata.extend_from_slice(&[3, 99, 111, 109, 0]);
+        rdata.extend_from_slice(&[0, 6, 0x40, 0x01, 0, 0, 0, 0x03, 4, 0x1b]);
+        rdata.extend_from_slice(&[0; 26]);
+        rdata.push(0x20);
+        let raw = raw_answer(47, &rdata);
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw).unwrap();
+
+        // Assert
+        assert_eq!(
+            actual.answers[0].r#type,
+            Type::NSEC(NSECData {
+                next_domain_name: "host.example.com.".to_string(),
+                types: vec![
+                    QType::A,
+                    QType::MX,
+                    QType::RRSIG,
+                    QType::NSEC,
+                    QType::Unknown(1234)
+                ],
+            })
+        );
+        assert_eq!(actual.serialize(), raw);
+    }
+
+    #[test]
+    fn dns_query_response_rrsig_round_trip() {
+        // Arrange
+        // lafolle.ca. RRSIG A 13 2 300 20300101000000 20000101000000 12345 lafolle.ca. AQID
+        let mut rdata = vec![
+            0, 1, 13, 2, 0, 0, 1, 44, 0x70, 0xdb, 0xd8, 0x80, 0x38, 0x6d, 0x43,
+        ];
+        rdata.extend_from_slice(&[0x80, 0x30, 0x39]);
+        rdata.extend_from_slice(&[7, 108, 97, 102, 111, 108, 108, 101, 2, 99, 97, 0, 1, 2, 3]);
+        let raw = raw_answer(46, &rdata);
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw).unwrap();
+
+        // Assert
+        assert_eq!(
+            actual.answers[0].r#type,
+            Type::RRSIG(RRSIGData {
+                type_covered: QType::A,
+                algorithm: 13,
+                labels: 2,
+                original_ttl: 300,
+                expiration: 1893456000,
+                inception: 946684800,
+                key_tag: 12345,
+                signer_name: "lafolle.ca.".to_string(),
+                signature: vec![1, 2, 3],
+            })
+        );
+        assert_eq!(actual.serialize(), raw);
+    }
+
+    #[test]
+    fn dns_query_header_authentic_data_and_checking_disabled() {
+        // Arrange
+        let mut raw = raw_response();
+        raw[3] |= 0b00110000;
+
+        // Act
+        let actual = DNSQueryResponse::deserialize(&raw).unwrap();
+
+        // Assert
+        assert!(actual.query.header.is_authentic_data);
+        assert!(actual.query.header.is_checking_disabled);
+        assert_eq!(actual.query.header.serialize()[3], raw[3]);
+    }
+
+    #[test]
+    fn qtype_mnemonics() {
+        assert_eq!(QType::Unknown(65).to_string(), "TYPE65");
+        assert_eq!("TYPE65".parse::<QType>(), Ok(QType::Unknown(65)));
+        assert_eq!("type1".parse::<QType>(), Ok(QType::A));
+        assert_eq!("aaaa".parse::<QType>(), Ok(QType::AAAA));
+        assert!("NOPE".parse::<QType>().is_err());
+    }
+
+    #[test]
+    fn qtype_as_json_map_key() {
+        // Arrange
+        let mut map = HashMap::new();
+        map.insert(QType::A, 1);
+        map.insert(QType::Unknown(65), 2);
+
+        // Act
+        let json = serde_json::to_string(&map).unwrap();
+        let actual: HashMap<QType, u8> = serde_json::from_str(&json).unwrap();
+
+        // Assert
+        assert_eq!(actual, map);
+    }
+
     #[test]
     fn soa_data_serialize() {
         // Arrange
@@ -1148,7 +2666,7 @@ mod tests {
         };
 
         // Act
-        let actual = SOAData::deserialize(&raw, 0, raw.len() as u16);
+        let actual = SOAData::deserialize(&raw, 0, raw.len() as u16).unwrap();
 
         // Assert
         assert_eq!(expected, actual);
@@ -0,0 +1,629 @@
+use crate::business::models::DSData;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Settings shared by the listeners, the handler and the resolver.  Filled from CLI args.
+#[derive(Debug, Clone)]
+pub struct Config {
+    // https://tools.ietf.org/html/rfc6891 6.2.5
+    // UDP payload size advertised upstream and the largest UDP response sent to clients.
+    pub edns_udp_payload_size: u16,
+
+    // https://tools.ietf.org/html/rfc4035 4
+    // Validate answers with DNSSEC.
+    pub dnssec_validation: bool,
+
+    // DS records of the root zone that the chain of trust starts from.
+    pub root_trust_anchors: Vec<DSData>,
+
+    // https://tools.ietf.org/html/draft-vixie-dnsext-dns0x20-00
+    // Mix the case of names asked over UDP and only take answers echoing it exactly.
+    pub qname_case_randomization: bool,
+
+    // https://tools.ietf.org/html/rfc8305 5
+    // Ask the next name server when the previous one has not answered for this long,  instead of
+    // waiting for it to time out.  Servers are asked one at a time when None.
+    pub query_stagger: Option<Duration>,
+
+    // Limits of the resolver cache,  over either one entries are evicted by cache_eviction.
+    pub cache_max_entries: usize,
+    pub cache_max_bytes: usize,
+    pub cache_eviction: Eviction,
+    // Expired records are purged from the cache this often.
+    pub cache_sweep_interval: Duration,
+
+    // Cache is loaded from this file on start and saved to it every cache_snapshot_interval.
+    pub cache_snapshot_path: Option<PathBuf>,
+    pub cache_snapshot_interval: Duration,
+
+    // Recursive resolvers to forward queries to instead of resolving them from the root.
+    pub forwarders: Vec<Forwarder>,
+    pub forward_policy: ForwardPolicy,
+    // PEM certificates that encrypted forwarders are verified against,  the system's roots
+    // when None.
+    pub forward_ca_path: Option<PathBuf>,
+
+    // Zones sent to their own servers,  whether or not the rest is forwarded.
+    pub zone_routes: Vec<ZoneRoute>,
+
+    // Zones answered from master files,  with the AA bit set.
+    pub zone_files: Vec<ZoneFile>,
+
+    // Names pinned to addresses,  answered before the resolver runs.
+    pub hosts_files: Vec<PathBuf>,
+    pub local_data: Vec<String>,
+    // How often hosts files are checked for changes.
+    pub hosts_reload_interval: Duration,
+
+    // Lists of blocked domains,  in the order they are checked.
+    pub blocklists: Vec<Blocklist>,
+
+    // https://tools.ietf.org/html/rfc7858
+    // PEM files of the DNS over TLS listener,  which only runs when both are given.
+    pub tls_certificate_path: Option<PathBuf>,
+    pub tls_private_key_path: Option<PathBuf>,
+    // TLS connections over this many are closed as soon as they are accepted.
+    pub tls_max_connections: usize,
+    // TLS connections that send no query for this long are closed.
+    pub tls_idle_timeout: Duration,
+
+    // https://tools.ietf.org/html/rfc8484
+    // Serve DNS over HTTPS with the TLS certificate,  plain HTTP otherwise,  e.g. behind a proxy.
+    pub doh_tls: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Blocklist {
+    // Label of the list in metrics.
+    pub name: String,
+    pub format: BlocklistFormat,
+    pub path: PathBuf,
+    // Taken for every entry of the list,  NXDOMAIN unless given.  RPZ entries carry their own.
+    pub action: Option<PolicyAction>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlocklistFormat {
+    // A domain per line,  "*.example.com" for the names below example.com.
+    Domains,
+    // /etc/hosts format,  the addresses are ignored.
+    Hosts,
+    // https://tools.ietf.org/html/draft-vixie-dnsop-dns-rpz-00
+    Rpz,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyAction {
+    NxDomain,
+    NoData,
+    // Names resolve to these addresses instead.
+    Sinkhole(Vec<IpAddr>),
+    // Exception,  names are resolved as usual whatever the other lists say.
+    Passthru,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneFile {
+    pub origin: String,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneRoute {
+    pub zone: String,
+    pub kind: ZoneRouteKind,
+    pub servers: Vec<SocketAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZoneRouteKind {
+    // Servers are recursive resolvers,  asked with RD=1.
+    Forward,
+    // Servers are the authoritative name servers of the zone.
+    Stub,
+}
+
+// Which cache entry goes first when the cache is full.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Eviction {
+    Lru,
+    Lfu,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Forwarder {
+    pub addr: SocketAddr,
+    pub transport: ForwardTransport,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForwardTransport {
+    // Plain DNS over UDP,  retried over TCP when the answer is truncated.
+    Udp,
+    // https://tools.ietf.org/html/rfc7858
+    // Certificate of the forwarder must be valid for server_name,  which is also sent as SNI.
+    Tls { server_name: String },
+    // https://tools.ietf.org/html/rfc8484
+    Https { server_name: String, path: String },
+}
+
+impl Forwarder {
+    pub fn udp(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            transport: ForwardTransport::Udp,
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.transport != ForwardTransport::Udp
+    }
+}
+
+// Same form as parse_forwarder takes.
+impl fmt::Display for Forwarder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.transport {
+            ForwardTransport::Udp => write!(f, "{}", self.addr),
+            ForwardTransport::Tls { server_name } => {
+                write!(f, "tls://{}#{}", self.addr, server_name)
+            }
+            ForwardTransport::Https { server_name, path } => {
+                write!(f, "https://{}{}#{}", self.addr, path, server_name)
+            }
+        }
+    }
+}
+
+// Which upstream a forwarded query goes to first.  The others are tried in turn when it fails.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForwardPolicy {
+    // Always the first configured one that works.
+    Failover,
+    RoundRobin,
+    LowestLatency,
+}
+
+impl FromStr for ForwardPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "failover" => Ok(ForwardPolicy::Failover),
+            "round_robin" => Ok(ForwardPolicy::RoundRobin),
+            "lowest_latency" => Ok(ForwardPolicy::LowestLatency),
+            _ => Err(format!("unknown forward policy: {}", s)),
+        }
+    }
+}
+
+impl FromStr for BlocklistFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "domains" => Ok(BlocklistFormat::Domains),
+            "hosts" => Ok(BlocklistFormat::Hosts),
+            "rpz" => Ok(BlocklistFormat::Rpz),
+            _ => Err(format!("unknown blocklist format: {}", s)),
+        }
+    }
+}
+
+impl FromStr for PolicyAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nxdomain" => Ok(PolicyAction::NxDomain),
+            "nodata" => Ok(PolicyAction::NoData),
+            "passthru" => Ok(PolicyAction::Passthru),
+            action => match action.strip_prefix("sinkhole:") {
+                Some(ip) => Ok(PolicyAction::Sinkhole(vec![ip
+                    .parse()
+                    .map_err(|_| format!("bad sinkhole address: {}", ip))?])),
+                None => Err(format!("unknown policy action: {}", s)),
+            },
+        }
+    }
+}
+
+impl FromStr for Eviction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lru" => Ok(Eviction::Lru),
+            "lfu" => Ok(Eviction::Lfu),
+            _ => Err(format!("unknown eviction policy: {}", s)),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            // https://dnsflagday.net/2020/
+            edns_udp_payload_size: 1232,
+            dnssec_validation: true,
+            // https://data.iana.org/root-anchors/root-anchors.xml
+            root_trust_anchors: parse_trust_anchors(
+                ". IN DS 20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBB683457104237C7F8EC8D
+                 . IN DS 38696 8 2 683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16",
+            )
+            .unwrap(),
+            qname_case_randomization: false,
+            query_stagger: None,
+            cache_max_entries: 100_000,
+            cache_max_bytes: 64 * 1024 * 1024,
+            cache_eviction: Eviction::Lru,
+            cache_sweep_interval: Duration::from_secs(60),
+            cache_snapshot_path: None,
+            cache_snapshot_interval: Duration::from_secs(300),
+            forwarders: vec![],
+            forward_policy: ForwardPolicy::Failover,
+            forward_ca_path: None,
+            zone_routes: vec![],
+            zone_files: vec![],
+            hosts_files: vec![],
+            local_data: vec![],
+            hosts_reload_interval: Duration::from_secs(5),
+            blocklists: vec![],
+            tls_certificate_path: None,
+            tls_private_key_path: None,
+            tls_max_connections: 1000,
+            // https://tools.ietf.org/html/rfc7766 6.2.3
+            tls_idle_timeout: Duration::from_secs(10),
+            doh_tls: false,
+        }
+    }
+}
+
+// Reads root DS records in presentation format,  one per line:
+//   . IN DS 20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBB683457104237C7F8EC8D
+// Blank lines and lines starting with ';' are skipped.
+pub fn parse_trust_anchors(contents: &str) -> Result<Vec<DSData>, String> {
+    let mut anchors = vec![];
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let ds_index = parts
+            .iter()
+            .position(|part| part.eq_ignore_ascii_case("DS"))
+            .ok_or(format!("not a DS record: {}", line))?;
+        if parts[0] != "." {
+            return Err(format!("trust anchor is not for the root zone: {}", line));
+        }
+        match parts[ds_index + 1..] {
+            [key_tag, algorithm, digest_type, ref digest @ ..] if !digest.is_empty() => anchors
+                .push(DSData {
+                    key_tag: key_tag
+                        .parse()
+                        .map_err(|_| format!("bad key tag: {}", line))?,
+                    algorithm: algorithm
+                        .parse()
+                        .map_err(|_| format!("bad algorithm: {}", line))?,
+                    digest_type: digest_type
+                        .parse()
+                        .map_err(|_| format!("bad digest type: {}", line))?,
+                    digest: decode_hex(&digest.concat()).ok_or(format!("bad digest: {}", line))?,
+                }),
+            _ => return Err(format!("incomplete DS record: {}", line)),
+        }
+    }
+    Ok(anchors)
+}
+
+// Address of a DNS server,  port 53 unless given:  "10.0.0.1",  "10.0.0.1:5353",  "[::1]:53".
+pub fn parse_server_addr(addr: &str) -> Result<SocketAddr, String> {
+    if let Ok(ip) = addr.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, 53));
+    }
+    addr.parse()
+        .map_err(|_| format!("not an address of a DNS server: {}", addr))
+}
+
+// Forwarder in one of the forms
+//   10.0.0.1 or 10.0.0.1:5353                          plain DNS
+//   tls://9.9.9.9#dns.quad9.net                        DNS over TLS,  port 853 by default
+//   https://1.1.1.1/dns-query#cloudflare-dns.com       DNS over HTTPS,  port 443 by default
+// The name after "#" is the one the certificate is checked against.
+pub fn parse_forwarder(forwarder: &str) -> Result<Forwarder, String> {
+    let (scheme, rest) = match forwarder.find("://") {
+        Some(index) => (&forwarder[..index], &forwarder[index + 3..]),
+        None => return parse_server_addr(forwarder).map(Forwarder::udp),
+    };
+    let mut parts = rest.splitn(2, '#');
+    let location = parts.next().unwrap_or_default();
+    let server_name = match parts.next() {
+        Some(server_name) if !server_name.is_empty() => server_name.trim_end_matches('.'),
+        _ => return Err(format!("missing #server-name: {}", forwarder)),
+    };
+    let (addr, path) = match location.find('/') {
+        Some(index) => (&location[..index], &location[index..]),
+        None => (location, ""),
+    };
+    let with_port = |port: u16| -> Result<SocketAddr, String> {
+        // https://tools.ietf.org/html/rfc3986 3.2.2
+        // IPv6 addresses are bracketed whether or not a port follows.
+        let ip = addr
+            .strip_prefix('[')
+            .and_then(|ip| ip.strip_suffix(']'))
+            .unwrap_or(addr);
+        match ip.parse::<IpAddr>() {
+            Ok(ip) => Ok(SocketAddr::new(ip, port)),
+            Err(_) => addr
+                .parse()
+                .map_err(|_| format!("not an address of a DNS server: {}", addr)),
+        }
+    };
+    let server_name = server_name.to_string();
+    let (addr, transport) = match scheme.to_lowercase().as_str() {
+        "tls" if path.is_empty() => (with_port(853)?, ForwardTransport::Tls { server_name }),
+        "https" => (
+            with_port(443)?,
+            ForwardTransport::Https {
+                server_name,
+                path: if path.is_empty() { "/dns-query" } else { path }.to_string(),
+            },
+        ),
+        _ => return Err(format!("unknown forwarder: {}", forwarder)),
+    };
+    Ok(Forwarder { addr, transport })
+}
+
+// Zone and its servers:  "corp.example.=10.0.0.1,10.0.0.2:5353".
+pub fn parse_zone_route(route: &str, kind: ZoneRouteKind) -> Result<ZoneRoute, String> {
+    let mut parts = route.splitn(2, '=');
+    let zone = parts.next().unwrap_or_default().trim();
+    let servers = parts
+        .next()
+        .ok_or(format!("expected zone=servers: {}", route))?;
+    if zone.is_empty() {
+        return Err(format!("missing zone: {}", route));
+    }
+    Ok(ZoneRoute {
+        zone: zone.to_string(),
+        kind,
+        servers: servers
+            .split(',')
+            .map(|server| parse_server_addr(server.trim()))
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+// Zone and its master file:  "example.com.=/etc/rrdns/example.com.zone".
+pub fn parse_zone_file(zone_file: &str) -> Result<ZoneFile, String> {
+    match zone_file.splitn(2, '=').collect::<Vec<&str>>()[..] {
+        [origin, path] if !origin.is_empty() && !path.is_empty() => Ok(ZoneFile {
+            origin: origin.to_string(),
+            path: PathBuf::from(path),
+        }),
+        _ => Err(format!("expected zone=path: {}", zone_file)),
+    }
+}
+
+// Name,  format and path of a blocklist and optionally its action:
+//   "ads=hosts:/etc/rrdns/ads.hosts",  "allow=domains:/etc/rrdns/allow.txt,passthru".
+pub fn parse_blocklist(blocklist: &str) -> Result<Blocklist, String> {
+    let (name, rest) = match blocklist.splitn(2, '=').collect::<Vec<&str>>()[..] {
+        [name, rest] if !name.is_empty() => (name, rest),
+        _ => return Err(format!("expected name=format:path: {}", blocklist)),
+    };
+    let (format, rest) = match rest.splitn(2, ':').collect::<Vec<&str>>()[..] {
+        [format, rest] => (format.parse()?, rest),
+        _ => return Err(format!("expected name=format:path: {}", blocklist)),
+    };
+    let mut parts = rest.rsplitn(2, ',');
+    let (path, action) = match (parts.next(), parts.next()) {
+        (Some(action), Some(path)) => (path, Some(action.parse()?)),
+        (Some(path), None) => (path, None),
+        _ => unreachable!(),
+    };
+    Ok(Blocklist {
+        name: name.to_string(),
+        format,
+        path: PathBuf::from(path),
+        action,
+    })
+}
+
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_blocklist, parse_forwarder, parse_server_addr, parse_trust_anchors, parse_zone_file,
+        parse_zone_route, BlocklistFormat, Config, Eviction, ForwardPolicy, ForwardTransport,
+        Forwarder, PolicyAction, ZoneRouteKind,
+    };
+
+    #[test]
+    fn test_config_default_root_trust_anchors() {
+        // Arrange
+        let config = Config::default();
+
+        // Act
+        let key_tags: Vec<u16> = config
+            .root_trust_anchors
+            .iter()
+            .map(|ds| ds.key_tag)
+            .collect();
+
+        // Assert
+        assert_eq!(key_tags, vec![20326, 38696]);
+        assert_eq!(config.root_trust_anchors[0].digest.len(), 32);
+    }
+
+    #[test]
+    fn test_parse_trust_anchors_skips_comments() {
+        // Arrange
+        let contents = "; root KSK\n\n.  172800 IN DS 20326 8 2 E06D44B8 0B8F1D39\n";
+
+        // Act
+        let actual = parse_trust_anchors(contents).unwrap();
+
+        // Assert
+        assert_eq!(actual.len(), 1);
+        assert_eq!(
+            actual[0].digest,
+            vec![0xe0, 0x6d, 0x44, 0xb8, 0x0b, 0x8f, 0x1d, 0x39]
+        );
+    }
+
+    #[test]
+    fn test_parse_trust_anchors_rejects_other_zones() {
+        assert!(parse_trust_anchors("com. IN DS 19718 13 2 8ACBB0CD").is_err());
+    }
+
+    #[test]
+    fn test_eviction_from_str() {
+        assert_eq!("LFU".parse::<Eviction>(), Ok(Eviction::Lfu));
+        assert_eq!("lru".parse::<Eviction>(), Ok(Eviction::Lru));
+        assert!("fifo".parse::<Eviction>().is_err());
+    }
+
+    #[test]
+    fn test_forward_policy_from_str() {
+        assert_eq!(
+            "round_robin".parse::<ForwardPolicy>(),
+            Ok(ForwardPolicy::RoundRobin)
+        );
+        assert!("random".parse::<ForwardPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_parse_server_addr() {
+        assert_eq!(
+            parse_server_addr("10.0.0.1"),
+            Ok("10.0.0.1:53".parse().unwrap())
+        );
+        assert_eq!(
+            parse_server_addr("[::1]:5353"),
+            Ok("[::1]:5353".parse().unwrap())
+        );
+        assert!(parse_server_addr("resolver.corp").is_err());
+    }
+
+    #[test]
+    fn test_parse_forwarder() {
+        assert_eq!(
+            parse_forwarder("10.0.0.1"),
+            Ok(Forwarder::udp("10.0.0.1:53".parse().unwrap()))
+        );
+        assert_eq!(
+            parse_forwarder("tls://9.9.9.9#dns.quad9.net"),
+            Ok(Forwarder {
+                addr: "9.9.9.9:853".parse().unwrap(),
+                transport: ForwardTransport::Tls {
+                    server_name: "dns.quad9.net".to_string()
+                },
+            })
+        );
+        assert_eq!(
+            parse_forwarder("https://[2606:4700::1111]:8443#cloudflare-dns.com."),
+            Ok(Forwarder {
+                addr: "[2606:4700::1111]:8443".parse().unwrap(),
+                transport: ForwardTransport::Https {
+                    server_name: "cloudflare-dns.com".to_string(),
+                    path: "/dns-query".to_string(),
+                },
+            })
+        );
+        assert_eq!(
+            parse_forwarder("tls://[2001:db8::1]#dns.example"),
+            Ok(Forwarder {
+                addr: "[2001:db8::1]:853".parse().unwrap(),
+                transport: ForwardTransport::Tls {
+                    server_name: "dns.example".to_string()
+                },
+            })
+        );
+        assert_eq!(
+            parse_forwarder("https://[2001:db8::1]/dns-query#dns.example"),
+            Ok(Forwarder {
+                addr: "[2001:db8::1]:443".parse().unwrap(),
+                transport: ForwardTransport::Https {
+                    server_name: "dns.example".to_string(),
+                    path: "/dns-query".to_string(),
+                },
+            })
+        );
+        assert!(parse_forwarder("tls://9.9.9.9").is_err());
+        assert!(parse_forwarder("quic://9.9.9.9#dns.quad9.net").is_err());
+    }
+
+    #[test]
+    fn test_parse_zone_route() {
+        // Arrange
+        let route = "10.in-addr.arpa.=10.0.0.53, 10.0.1.53:5353";
+
+        // Act
+        let actual = parse_zone_route(route, ZoneRouteKind::Stub).unwrap();
+
+        // Assert
+        assert_eq!(actual.zone, "10.in-addr.arpa.");
+        assert_eq!(actual.kind, ZoneRouteKind::Stub);
+        assert_eq!(
+            actual.servers,
+            vec![
+                "10.0.0.53:53".parse().unwrap(),
+                "10.0.1.53:5353".parse().unwrap()
+            ]
+        );
+        assert!(parse_zone_route("corp.example.", ZoneRouteKind::Forward).is_err());
+    }
+
+    #[test]
+    fn test_parse_zone_file() {
+        // Arrange
+        let zone_file = "example.com.=/etc/rrdns/example.com.zone";
+
+        // Act
+        let actual = parse_zone_file(zone_file).unwrap();
+
+        // Assert
+        assert_eq!(actual.origin, "example.com.");
+        assert_eq!(actual.path.to_str(), Some("/etc/rrdns/example.com.zone"));
+        assert!(parse_zone_file("/etc/rrdns/example.com.zone").is_err());
+    }
+
+    #[test]
+    fn test_parse_blocklist() {
+        // Arrange
+        let blocklist = "ads=hosts:/etc/rrdns/ads.hosts,sinkhole:0.0.0.0";
+
+        // Act
+        let actual = parse_blocklist(blocklist).unwrap();
+
+        // Assert
+        assert_eq!(actual.name, "ads");
+        assert_eq!(actual.format, BlocklistFormat::Hosts);
+        assert_eq!(actual.path.to_str(), Some("/etc/rrdns/ads.hosts"));
+        assert_eq!(
+            actual.action,
+            Some(PolicyAction::Sinkhole(vec!["0.0.0.0".parse().unwrap()]))
+        );
+        assert_eq!(
+            parse_blocklist("rpz=rpz:/etc/rrdns/rpz.zone")
+                .unwrap()
+                .action,
+            None
+        );
+        assert!(parse_blocklist("ads=adblock:/etc/rrdns/ads.txt").is_err());
+        assert!(parse_blocklist("allow=domains:/etc/rrdns/allow.txt,allow").is_err());
+    }
+}
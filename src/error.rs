@@ -1,4 +1,5 @@
 use crate::business::models::DNSQueryResponse;
+use std::fmt;
 use std::io::Error;
 
 #[derive(Debug)]
@@ -11,4 +12,133 @@ pub enum FetchError {
 
     // VerificationError(String),
     NoIPError(String),
+
+    // Upstream answered with a message that could not be parsed.
+    ParseError(ParseError),
+
+    // Upstream did not answer,  not even to retransmissions.
+    Timeout(String),
+}
+
+// Reasons a message on the wire could not be parsed.  Offsets are in octets from the start of
+// the message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    // Message ended at offset while more octets were expected.
+    UnexpectedEnd(usize),
+    // Compression pointer at offset does not point before the name being read.
+    BadPointer(usize),
+    // Name at offset follows more compression pointers than allowed.
+    TooManyPointers(usize),
+    // Label at offset uses one of the reserved label types (01 or 10).
+    BadLabelType(usize),
+    // Label at offset is not valid UTF-8.
+    BadLabel(usize),
+    // Name at offset is longer than 255 octets.
+    NameTooLong(usize),
+    UnsupportedType(u16),
+    UnsupportedClass(u16),
+    // RDATA of the record at offset does not match its type or RDLENGTH.
+    BadRData(usize),
+    // More than one OPT record,  the second one at offset.
+    DuplicateOpt(usize),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedEnd(offset) => write!(f, "message ends at {}", offset),
+            ParseError::BadPointer(offset) => write!(f, "bad compression pointer at {}", offset),
+            ParseError::TooManyPointers(offset) => {
+                write!(f, "too many compression pointers in name at {}", offset)
+            }
+            ParseError::BadLabelType(offset) => write!(f, "bad label type at {}", offset),
+            ParseError::BadLabel(offset) => write!(f, "label at {} is not utf-8", offset),
+            ParseError::NameTooLong(offset) => write!(f, "name at {} is too long", offset),
+            ParseError::UnsupportedType(code) => write!(f, "type {} is not supported", code),
+            ParseError::UnsupportedClass(code) => write!(f, "class {} is not supported", code),
+            ParseError::BadRData(offset) => write!(f, "bad rdata in record at {}", offset),
+            ParseError::DuplicateOpt(offset) => write!(f, "duplicate OPT record at {}", offset),
+        }
+    }
+}
+
+// Reasons a cache snapshot could not be loaded.
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(Error),
+    // Written by a version of rrdns with another snapshot format.
+    Version(String),
+    // Contents do not match the checksum in the header.
+    Checksum,
+    Format(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "{}", err),
+            SnapshotError::Version(header) => write!(f, "unsupported snapshot: {}", header),
+            SnapshotError::Checksum => write!(f, "checksum mismatch"),
+            SnapshotError::Format(err) => write!(f, "bad snapshot: {}", err),
+        }
+    }
+}
+
+impl From<Error> for SnapshotError {
+    fn from(err: Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+// Reasons a master file could not be loaded.
+#[derive(Debug)]
+pub enum MasterFileError {
+    Io(Error),
+    // Entry starting at line does not follow https://tools.ietf.org/html/rfc1035 5.1.
+    Syntax(usize, String),
+    // Records loaded do not make up a zone,  e.g. there is no SOA at its apex.
+    Zone(String),
+}
+
+impl fmt::Display for MasterFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MasterFileError::Io(err) => write!(f, "{}", err),
+            MasterFileError::Syntax(line, err) => write!(f, "line {}: {}", line, err),
+            MasterFileError::Zone(err) => write!(f, "bad zone: {}", err),
+        }
+    }
+}
+
+impl From<Error> for MasterFileError {
+    fn from(err: Error) -> Self {
+        MasterFileError::Io(err)
+    }
+}
+
+// Reasons the certificate and private key of a TLS listener could not be loaded.
+#[derive(Debug)]
+pub enum TlsConfigError {
+    Io(Error),
+    // File is not PEM or holds no certificate.
+    Certificate(String),
+    // File is not PEM or holds no PKCS#8 or RSA key that can sign.
+    PrivateKey(String),
+}
+
+impl fmt::Display for TlsConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TlsConfigError::Io(err) => write!(f, "{}", err),
+            TlsConfigError::Certificate(err) => write!(f, "bad certificate: {}", err),
+            TlsConfigError::PrivateKey(err) => write!(f, "bad private key: {}", err),
+        }
+    }
+}
+
+impl From<Error> for TlsConfigError {
+    fn from(err: Error) -> Self {
+        TlsConfigError::Io(err)
+    }
 }
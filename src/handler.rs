@@ -1,37 +1,88 @@
-use crate::business::models::{DNSQuery, DNSQueryResponse, QType};
-use crate::error::FetchError;
-use crate::resolver::cache::{Store};
+use crate::business::models::{
+    DNSQuery, DNSQueryResponse, Edns, QType, ResourceRecord, Type, EDNS_VERSION,
+    EXTENDED_RCODE_BADVERS,
+};
+use crate::config::{Config, PolicyAction};
+use crate::error::{FetchError, ParseError};
+use crate::resolver::cache::Store;
 use crate::resolver::Resolver;
 use log::info;
 use rand::prelude::*;
+use std::path::Path;
 use std::sync::Arc;
 
+mod local_data;
+use local_data::LocalData;
+
+mod policy;
+use policy::Policy;
+
+// https://tools.ietf.org/html/rfc1035 2.3.4
+pub const MIN_UDP_PAYLOAD_SIZE: u16 = 512;
+
 // Handler can be called from multiple threads.
 pub struct Handler {
     pub resolver: Arc<Resolver>,
+    edns_udp_payload_size: u16,
+    local_data: LocalData,
+    policy: Policy,
 }
 
 impl Handler {
-    pub fn new() -> Self {
+    pub fn new(config: &Config) -> Self {
         Self {
-            resolver: Arc::new(Resolver::new()),
+            resolver: Arc::new(Resolver::new(config)),
+            edns_udp_payload_size: config.edns_udp_payload_size,
+            local_data: LocalData::new(&config.hosts_files, &config.local_data)
+                .unwrap_or_else(|err| panic!("invalid local-data {}", err)),
+            policy: Policy::load(&config.blocklists)
+                .unwrap_or_else(|err| panic!("loading blocklist {}", err)),
         }
     }
 
     pub async fn handle(&self, buf: &[u8]) -> Result<DNSQueryResponse, FetchError> {
-        let (query, _) = DNSQuery::deserialize(buf);
+        let query = match DNSQuery::deserialize(buf) {
+            Ok((query, _)) if query.questions.len() == 1 => query,
+            result => {
+                if let Err(err) = result {
+                    info!("malformed query err={}", err);
+                }
+                // Reading the id is all it takes to answer FORMERR.
+                return match DNSQueryResponse::format_error(buf) {
+                    Some(response) => Err(FetchError::QueryError(response)),
+                    None => Err(FetchError::ParseError(ParseError::UnexpectedEnd(buf.len()))),
+                };
+            }
+        };
         let query_id = query.header.id;
         let query_qname = query.questions[0].qname.clone();
         let query_qtype = query.questions[0].qtype.clone();
         let query_qclass = query.questions[0].qclass.clone();
+        let client_edns = query.edns.clone();
+        // https://tools.ietf.org/html/rfc6840 5.8
+        let wants_authentic_data = query.header.is_authentic_data
+            || client_edns.as_ref().is_some_and(|edns| edns.dnssec_ok);
+
+        if let Some(edns) = &client_edns {
+            if edns.version > EDNS_VERSION {
+                return Ok(self.bad_version(query));
+            }
+        }
 
         let rewritten_query = self.rewrite_query(query);
 
-        match self.resolver.resolve(&rewritten_query).await {
+        let result = match self.local_data.answer(&rewritten_query) {
+            Some(response) => Ok(response),
+            None => self.resolve_with_policy(&rewritten_query).await,
+        };
+        match result {
             Ok(mut response) => {
                 info!("{}/{} resolved", response.query.header.id, query_id);
                 response.query.header.id = query_id;
                 response.query.header.is_recursion_available = true;
+                response.query.header.is_authentic_data &= wants_authentic_data;
+                response.query.edns = self.response_edns(&client_edns);
+                strip_dnssec_records(&mut response, &client_edns);
                 return Ok(response);
             }
             Err(err) => {
@@ -41,6 +92,9 @@ impl Handler {
                         response.query.questions[0].qname = query_qname;
                         response.query.questions[0].qtype = query_qtype;
                         response.query.questions[0].qclass = query_qclass;
+                        response.query.header.is_authentic_data &= wants_authentic_data;
+                        response.query.edns = self.response_edns(&client_edns);
+                        strip_dnssec_records(&mut response, &client_edns);
                         return Err(FetchError::QueryError(response));
                     }
                     FetchError::NetworkError(err) => {
@@ -54,15 +108,114 @@ impl Handler {
                     FetchError::NoIPError(err) => {
                         return Err(FetchError::NoIPError(err));
                     }
+                    FetchError::ParseError(err) => {
+                        return Err(FetchError::ParseError(err));
+                    }
+                    FetchError::Timeout(err) => {
+                        return Err(FetchError::Timeout(err));
+                    }
+                }
+            }
+        }
+    }
+
+    // Blocklists are checked on qname before resolving,  so that blocked names are not even
+    // looked up,  and on the CNAME targets of the answer.
+    async fn resolve_with_policy(&self, query: &DNSQuery) -> Result<DNSQueryResponse, FetchError> {
+        match self.policy.check(&query.questions[0].qname) {
+            Some(hit) if *hit.action == PolicyAction::Passthru => {
+                return self.resolver.resolve(query).await
+            }
+            Some(hit) => {
+                info!("{} blocked by {}", query.header.id, hit.list);
+                return Ok(hit.respond(query));
+            }
+            None => {}
+        }
+        let result = self.resolver.resolve(query).await;
+        if let Ok(response) = &result {
+            for rr in &response.answers {
+                if let Type::CNAME(target) = &rr.r#type {
+                    match self.policy.check(target) {
+                        Some(hit) if *hit.action == PolicyAction::Passthru => break,
+                        // The whole answer is replaced,  the chain leading to target included.
+                        Some(hit) => {
+                            info!(
+                                "{} blocked by {} at cname {}",
+                                query.header.id, hit.list, target
+                            );
+                            return Ok(hit.respond(query));
+                        }
+                        None => {}
+                    }
                 }
             }
         }
+        result
     }
 
     pub fn clone_cache(&self) -> Store {
         self.resolver.clone_cache()
     }
 
+    pub fn purge_expired_cache(&self) -> usize {
+        self.resolver.purge_expired_cache()
+    }
+
+    pub fn save_cache_snapshot(&self, path: &Path) {
+        self.resolver.save_cache_snapshot(path)
+    }
+
+    pub fn reload_local_data(&self) -> bool {
+        self.local_data.reload_if_changed()
+    }
+
+    // Largest UDP response the client who sent buf can take.
+    pub fn max_udp_response_size(&self, buf: &[u8]) -> usize {
+        match DNSQuery::deserialize(buf)
+            .ok()
+            .and_then(|(query, _)| query.edns)
+        {
+            Some(edns) => edns
+                .udp_payload_size
+                .min(self.edns_udp_payload_size)
+                .max(MIN_UDP_PAYLOAD_SIZE) as usize,
+            None => MIN_UDP_PAYLOAD_SIZE as usize,
+        }
+    }
+
+    // https://tools.ietf.org/html/rfc6891 7
+    // OPT is only sent back to clients which sent one.
+    // https://tools.ietf.org/html/rfc3225 3
+    // DO is copied from the query.
+    fn response_edns(&self, client_edns: &Option<Edns>) -> Option<Edns> {
+        client_edns.as_ref().map(|client_edns| {
+            let mut edns = Edns::new(self.edns_udp_payload_size);
+            edns.dnssec_ok = client_edns.dnssec_ok;
+            edns
+        })
+    }
+
+    // https://tools.ietf.org/html/rfc6891 6.1.3
+    fn bad_version(&self, query: DNSQuery) -> DNSQueryResponse {
+        let mut query_of_response = query;
+        query_of_response.header.is_query = false;
+        query_of_response.header.answers_count = 0;
+        query_of_response.header.ns_rr_count = 0;
+        query_of_response.additionals = vec![];
+
+        let mut edns = Edns::new(self.edns_udp_payload_size);
+        edns.extended_rcode = EXTENDED_RCODE_BADVERS;
+        query_of_response.edns = Some(edns);
+
+        DNSQueryResponse {
+            query: query_of_response,
+            answers: vec![],
+            authority: vec![],
+            additional: vec![],
+        }
+    }
+
     fn rewrite_query(&self, query: DNSQuery) -> DNSQuery {
         let mut new_query = query.clone();
 
@@ -83,10 +236,29 @@ impl Handler {
             new_query.questions[0].qname.push('.');
         }
 
-        // TODO: what does "additionals" contain in query?
+        // TODO: what does "additionals" contain in query?  Resolver attaches its own OPT when
+        // sending the query upstream.
         new_query.additionals = vec![];
         new_query.header.additional_rr_count = 0;
+        new_query.edns = None;
 
         new_query
     }
 }
+
+// https://tools.ietf.org/html/rfc4035 3.2.1
+// DNSSEC records are only sent to clients which set DO,  unless they asked for them.
+fn strip_dnssec_records(response: &mut DNSQueryResponse, client_edns: &Option<Edns>) {
+    if client_edns.as_ref().is_some_and(|edns| edns.dnssec_ok) {
+        return;
+    }
+    let qtype = response.query.questions[0].qtype;
+    let is_dnssec_record = |rr: &ResourceRecord| {
+        let rr_qtype = rr.r#type.to_qtype();
+        rr_qtype != qtype
+            && (rr_qtype == QType::RRSIG || rr_qtype == QType::NSEC || rr_qtype == QType::NSEC3)
+    };
+    response.answers.retain(|rr| !is_dnssec_record(rr));
+    response.authority.retain(|rr| !is_dnssec_record(rr));
+    response.additional.retain(|rr| !is_dnssec_record(rr));
+}
@@ -0,0 +1,307 @@
+use crate::business::models::{
+    Class, DNSQuery, DNSQueryResponse, QType, RRSet, ResourceRecord, ResponseCode, Type,
+};
+use crate::error::MasterFileError;
+use crate::resolver::master;
+use crate::resolver::zone::normalize;
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+// TTL of records from hosts files,  and of local-data entries without one.
+const LOCAL_TTL: u32 = 3600;
+
+type Records = HashMap<String, HashMap<QType, RRSet>>;
+
+// Names pinned to addresses on this resolver,  from hosts files and local-data entries.
+pub struct LocalData {
+    hosts_files: Vec<PathBuf>,
+    local_data: RRSet,
+    state: RwLock<State>,
+}
+
+struct State {
+    records: Records,
+    // Modification time and length of hosts_files when they were read,  None for unreadable
+    // ones.  Length catches writes within the resolution of the modification time.
+    modified: Vec<Option<(SystemTime, u64)>>,
+}
+
+impl LocalData {
+    // local_data entries are records in master file format:  "printer.lan. A 192.0.2.1".
+    pub fn new(hosts_files: &[PathBuf], local_data: &[String]) -> Result<Self, String> {
+        let mut rrs = vec![];
+        for entry in local_data {
+            let mut entry_rrs = master::parse(&format!("$TTL {}\n{}", LOCAL_TTL, entry), ".")
+                .map_err(|err| match err {
+                    // Line would count the $TTL above.
+                    MasterFileError::Syntax(_, err) => format!("{}: {}", entry, err),
+                    err => format!("{}: {}", entry, err),
+                })?;
+            let mut ptr_rrs = entry_rrs
+                .iter()
+                .filter_map(|rr| match rr.r#type {
+                    Type::A(ip) => Some(ptr_record(&IpAddr::V4(ip), &rr.name, rr.ttl)),
+                    Type::AAAA(ip) => Some(ptr_record(&IpAddr::V6(ip), &rr.name, rr.ttl)),
+                    _ => None,
+                })
+                .collect();
+            rrs.append(&mut entry_rrs);
+            rrs.append(&mut ptr_rrs);
+        }
+        let local_data = Self {
+            hosts_files: hosts_files.to_vec(),
+            local_data: rrs,
+            state: RwLock::new(State {
+                records: Records::new(),
+                modified: vec![],
+            }),
+        };
+        local_data.reload();
+        Ok(local_data)
+    }
+
+    // Names with local data are answered from it alone,  their other types are NODATA.  None for
+    // the names left to the resolver.
+    pub fn answer(&self, query: &DNSQuery) -> Option<DNSQueryResponse> {
+        let qname = normalize(&query.questions[0].qname);
+        let qtype = query.questions[0].qtype;
+        let state = self.state.read().unwrap();
+        let rrsets = state.records.get(&qname)?;
+        let answers: RRSet = match qtype {
+            QType::STAR => rrsets.values().flatten().cloned().collect(),
+            _ => rrsets.get(&qtype).cloned().unwrap_or_default(),
+        };
+
+        let mut query_of_response = query.clone();
+        query_of_response.header.is_query = false;
+        query_of_response.header.is_authoritative_answer = true;
+        query_of_response.header.response_code = ResponseCode::NoError;
+        query_of_response.header.answers_count = answers.len() as u16;
+        Some(DNSQueryResponse {
+            query: query_of_response,
+            answers,
+            authority: vec![],
+            additional: vec![],
+        })
+    }
+
+    // Reads the hosts files again if any of them changed on disk since they were read.
+    pub fn reload_if_changed(&self) -> bool {
+        if self.modified_times() == self.state.read().unwrap().modified {
+            return false;
+        }
+        self.reload();
+        true
+    }
+
+    // A hosts file that cannot be read is left out until it can.
+    fn reload(&self) {
+        // Taken before reading,  so that a write while reading is picked up by the next reload.
+        let modified = self.modified_times();
+        let mut rrs = self.local_data.clone();
+        for path in &self.hosts_files {
+            match fs::read_to_string(path) {
+                Ok(contents) => rrs.append(&mut parse_hosts(&contents)),
+                Err(err) => error!("reading hosts file {}: {}", path.display(), err),
+            }
+        }
+
+        let mut records = Records::new();
+        let mut count = 0;
+        for rr in rrs {
+            let rrset = records
+                .entry(rr.name.clone())
+                .or_default()
+                .entry(rr.r#type.to_qtype())
+                .or_default();
+            if !rrset.contains(&rr) {
+                rrset.push(rr);
+                count += 1;
+            }
+        }
+        info!("loaded {} local records", count);
+        *self.state.write().unwrap() = State { records, modified };
+    }
+
+    fn modified_times(&self) -> Vec<Option<(SystemTime, u64)>> {
+        self.hosts_files
+            .iter()
+            .map(|path| {
+                let metadata = fs::metadata(path).ok()?;
+                Some((metadata.modified().ok()?, metadata.len()))
+            })
+            .collect()
+    }
+}
+
+// hosts(5):  an address followed by its canonical name and aliases,  "#" starts a comment.
+// Every name gets the address,  the address points back to the canonical name.
+fn parse_hosts(contents: &str) -> RRSet {
+    let mut rrs = vec![];
+    for line in contents.lines() {
+        let mut fields = line
+            .split('#')
+            .next()
+            .unwrap_or_default()
+            .split_whitespace();
+        let ip = match fields.next() {
+            Some(ip) => ip,
+            None => continue,
+        };
+        let ip: IpAddr = match ip.parse() {
+            Ok(ip) => ip,
+            Err(_) => {
+                warn!("hosts file: ignoring line with bad address {}", line);
+                continue;
+            }
+        };
+        let names: Vec<String> = fields.map(normalize).collect();
+        if let Some(canonical_name) = names.first() {
+            rrs.push(ptr_record(&ip, canonical_name, LOCAL_TTL));
+        }
+        for name in names {
+            let (r#type, rd_length) = match ip {
+                IpAddr::V4(ip) => (Type::A(ip), 4),
+                IpAddr::V6(ip) => (Type::AAAA(ip), 16),
+            };
+            rrs.push(ResourceRecord {
+                name,
+                r#type,
+                class: Class::IN,
+                ttl: LOCAL_TTL,
+                rd_length,
+            });
+        }
+    }
+    rrs
+}
+
+fn ptr_record(ip: &IpAddr, name: &str, ttl: u32) -> ResourceRecord {
+    let r#type = Type::PTR(name.to_string());
+    ResourceRecord {
+        name: reverse_name(ip),
+        rd_length: r#type.serialize_rdata().len() as u16,
+        r#type,
+        class: Class::IN,
+        ttl,
+    }
+}
+
+// https://tools.ietf.org/html/rfc1035 3.5
+// https://tools.ietf.org/html/rfc3596 2.5
+fn reverse_name(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            format!(
+                "{}.{}.{}.{}.in-addr.arpa.",
+                octets[3], octets[2], octets[1], octets[0]
+            )
+        }
+        IpAddr::V6(ip) => {
+            let nibbles: String = ip
+                .octets()
+                .iter()
+                .rev()
+                .map(|octet| format!("{:x}.{:x}.", octet & 0xf, octet >> 4))
+                .collect();
+            format!("{}ip6.arpa.", nibbles)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_hosts, reverse_name, LocalData};
+    use crate::business::models::{DNSQuery, QType, Type};
+    use std::env;
+    use std::fs;
+    use std::net::IpAddr;
+
+    #[test]
+    fn test_parse_hosts() {
+        // Arrange
+        let contents = "
+# staging overrides
+192.0.2.10  api.example.com  api-staging.example.com # pinned
+2001:db8::10 api.example.com
+not-an-address foo
+";
+
+        // Act
+        let rrs = parse_hosts(contents);
+
+        // Assert
+        assert_eq!(rrs.len(), 5);
+        assert_eq!(rrs[0].name, "10.2.0.192.in-addr.arpa.");
+        assert_eq!(rrs[0].r#type, Type::PTR("api.example.com.".to_string()));
+        assert_eq!(rrs[2].name, "api-staging.example.com.");
+        assert_eq!(rrs[4].r#type.to_qtype(), QType::AAAA);
+    }
+
+    #[test]
+    fn test_reverse_name() {
+        assert_eq!(
+            reverse_name(&"192.0.2.1".parse::<IpAddr>().unwrap()),
+            "1.2.0.192.in-addr.arpa."
+        );
+        assert_eq!(
+            reverse_name(&"2001:db8::1".parse::<IpAddr>().unwrap()),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa."
+        );
+    }
+
+    #[test]
+    fn test_local_data_answer() {
+        // Arrange
+        let local_data = LocalData::new(&[], &["Printer.lan. A 192.0.2.1".to_string()]).unwrap();
+
+        // Act
+        let a = local_data.answer(&DNSQuery::for_test("printer.lan.", QType::A));
+        let ptr = local_data.answer(&DNSQuery::for_test("1.2.0.192.in-addr.arpa.", QType::PTR));
+        let nodata = local_data.answer(&DNSQuery::for_test("printer.lan.", QType::AAAA));
+        let miss = local_data.answer(&DNSQuery::for_test("scanner.lan.", QType::A));
+
+        // Assert
+        let a = a.unwrap();
+        assert!(a.query.header.is_authoritative_answer);
+        assert_eq!(a.answers[0].ttl, 3600);
+        assert_eq!(
+            ptr.unwrap().answers[0].r#type,
+            Type::PTR("printer.lan.".to_string())
+        );
+        assert!(nodata.unwrap().answers.is_empty());
+        assert!(miss.is_none());
+        assert!(LocalData::new(&[], &["printer.lan. A 192.0.2.300".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_local_data_reload_if_changed() {
+        // Arrange
+        let path = env::temp_dir().join(format!("rrdns-hosts-{}", std::process::id()));
+        fs::write(&path, "192.0.2.1 old.lan\n").unwrap();
+        let local_data = LocalData::new(std::slice::from_ref(&path), &[]).unwrap();
+
+        // Act
+        let unchanged = local_data.reload_if_changed();
+        fs::remove_file(&path).unwrap();
+        fs::write(&path, "192.0.2.2 new.lan newer.lan\n").unwrap();
+        let changed = local_data.reload_if_changed();
+        fs::remove_file(&path).unwrap();
+
+        // Assert
+        assert!(!unchanged);
+        assert!(changed);
+        assert!(local_data
+            .answer(&DNSQuery::for_test("old.lan.", QType::A))
+            .is_none());
+        assert!(local_data
+            .answer(&DNSQuery::for_test("new.lan.", QType::A))
+            .is_some());
+    }
+}
@@ -0,0 +1,426 @@
+use crate::business::models::{
+    Class, DNSQuery, DNSQueryResponse, QType, RRSet, ResourceRecord, ResponseCode, Type,
+};
+use crate::config::{Blocklist, BlocklistFormat, PolicyAction};
+use crate::resolver::master;
+use crate::resolver::zone::{normalize, parent_zone};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+
+// TTL of the answers made up for blocked names.
+const POLICY_TTL: u32 = 60;
+
+lazy_static! {
+    static ref RRDNS_POLICY_HITS: IntCounterVec = register_int_counter_vec!(
+        "rrdns_policy_hits",
+        "Number of queries a blocklist matched the name or a CNAME target of",
+        &["list"]
+    )
+    .unwrap();
+}
+
+// Blocklists,  checked in the order they were configured.
+pub struct Policy {
+    lists: Vec<PolicyList>,
+}
+
+struct PolicyList {
+    name: String,
+    exact: HashMap<String, PolicyAction>,
+    // "*.example.com" is kept under "example.com.",  it matches the names below it only.
+    wildcard: HashMap<String, PolicyAction>,
+}
+
+// Blocklist entry a name matched.
+pub struct Hit<'a> {
+    pub list: &'a str,
+    pub action: &'a PolicyAction,
+}
+
+impl Policy {
+    pub fn load(blocklists: &[Blocklist]) -> Result<Self, String> {
+        let mut lists = vec![];
+        for blocklist in blocklists {
+            let list = PolicyList::load(blocklist).map_err(|err| {
+                format!(
+                    "{} from {}: {}",
+                    blocklist.name,
+                    blocklist.path.display(),
+                    err
+                )
+            })?;
+            info!(
+                "loaded blocklist {} with {} entries",
+                list.name,
+                list.exact.len() + list.wildcard.len()
+            );
+            lists.push(list);
+        }
+        Ok(Self { lists })
+    }
+
+    // First list matching name,  unless a passthru entry in any list matches it.
+    pub fn check(&self, name: &str) -> Option<Hit<'_>> {
+        let name = normalize(name);
+        let hits: Vec<Hit> = self
+            .lists
+            .iter()
+            .filter_map(|list| {
+                list.find(&name).map(|action| Hit {
+                    list: &list.name,
+                    action,
+                })
+            })
+            .collect();
+        let hit = match hits
+            .iter()
+            .position(|hit| *hit.action == PolicyAction::Passthru)
+        {
+            Some(exception) => hits.into_iter().nth(exception),
+            None => hits.into_iter().next(),
+        }?;
+        RRDNS_POLICY_HITS.with_label_values(&[hit.list]).inc();
+        Some(hit)
+    }
+}
+
+impl Hit<'_> {
+    // Answer to query in place of the resolver's.  Sinkholed names get the addresses of the
+    // family asked for,  NODATA for anything else.  Blocked names are not resolution failures,
+    // so NXDOMAIN is not returned as FetchError::QueryError.
+    pub fn respond(&self, query: &DNSQuery) -> DNSQueryResponse {
+        let qname = &query.questions[0].qname;
+        let qtype = query.questions[0].qtype;
+        let (response_code, answers): (ResponseCode, RRSet) = match self.action {
+            PolicyAction::NxDomain => (ResponseCode::NameError, vec![]),
+            PolicyAction::Sinkhole(ips) => (
+                ResponseCode::NoError,
+                ips.iter()
+                    .filter_map(|ip| match (ip, qtype) {
+                        (IpAddr::V4(ip), QType::A) => Some((Type::A(*ip), 4)),
+                        (IpAddr::V6(ip), QType::AAAA) => Some((Type::AAAA(*ip), 16)),
+                        _ => None,
+                    })
+                    .map(|(r#type, rd_length)| ResourceRecord {
+                        name: qname.clone(),
+                        r#type,
+                        class: Class::IN,
+                        ttl: POLICY_TTL,
+                        rd_length,
+                    })
+                    .collect(),
+            ),
+            PolicyAction::NoData | PolicyAction::Passthru => (ResponseCode::NoError, vec![]),
+        };
+
+        let mut query_of_response = query.clone();
+        query_of_response.header.is_query = false;
+        query_of_response.header.is_authoritative_answer = false;
+        query_of_response.header.response_code = response_code;
+        query_of_response.header.answers_count = answers.len() as u16;
+        DNSQueryResponse {
+            query: query_of_response,
+            answers,
+            authority: vec![],
+            additional: vec![],
+        }
+    }
+}
+
+impl PolicyList {
+    fn load(blocklist: &Blocklist) -> Result<Self, String> {
+        let mut list = Self {
+            name: blocklist.name.clone(),
+            exact: HashMap::new(),
+            wildcard: HashMap::new(),
+        };
+        let entries = match blocklist.format {
+            BlocklistFormat::Domains => parse_domains(&read(blocklist)?),
+            BlocklistFormat::Hosts => parse_hosts(&read(blocklist)?),
+            BlocklistFormat::Rpz => {
+                parse_rpz(master::load(&blocklist.path, ".").map_err(|err| err.to_string())?)
+            }
+        };
+        for (name, action) in entries {
+            let action = blocklist
+                .action
+                .clone()
+                .or(action)
+                .unwrap_or(PolicyAction::NxDomain);
+            match name.strip_prefix("*.") {
+                Some(name) => list.wildcard.insert(normalize(name), action),
+                None => list.exact.insert(normalize(&name), action),
+            };
+        }
+        Ok(list)
+    }
+
+    // Exact entries come before wildcard ones,  the wildcard of the closest ancestor wins.
+    fn find(&self, name: &str) -> Option<&PolicyAction> {
+        if let Some(action) = self.exact.get(name) {
+            return Some(action);
+        }
+        let mut ancestor = name.to_string();
+        while ancestor != "." {
+            ancestor = parent_zone(&ancestor);
+            if let Some(action) = self.wildcard.get(&ancestor) {
+                return Some(action);
+            }
+        }
+        None
+    }
+}
+
+fn read(blocklist: &Blocklist) -> Result<String, String> {
+    fs::read_to_string(&blocklist.path).map_err(|err| err.to_string())
+}
+
+// A domain per line,  "#" starts a comment.
+fn parse_domains(contents: &str) -> Vec<(String, Option<PolicyAction>)> {
+    contents
+        .lines()
+        .filter_map(|line| line.split('#').next()?.split_whitespace().next())
+        .map(|name| (name.to_string(), None))
+        .collect()
+}
+
+// hosts(5),  the names of every line are blocked whatever address they are given.
+fn parse_hosts(contents: &str) -> Vec<(String, Option<PolicyAction>)> {
+    contents
+        .lines()
+        .flat_map(|line| {
+            line.split('#')
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .skip(1)
+        })
+        // Lists start with the usual entries of /etc/hosts.
+        .filter(|name| {
+            ![
+                "localhost",
+                "localhost.localdomain",
+                "local",
+                "broadcasthost",
+            ]
+            .contains(name)
+                && name.parse::<IpAddr>().is_err()
+        })
+        .map(|name| (name.to_string(), None))
+        .collect()
+}
+
+// https://tools.ietf.org/html/draft-vixie-dnsop-dns-rpz-00 3
+// Owners below the apex of the zone are the names the policy triggers on:
+//   bad.example.rpz.     CNAME .              NXDOMAIN
+//   *.bad.example.rpz.   CNAME *.             NODATA
+//   ok.bad.example.rpz.  CNAME rpz-passthru.  passthru
+//   ads.example.rpz.     A     0.0.0.0        sinkhole
+fn parse_rpz(rrs: RRSet) -> Vec<(String, Option<PolicyAction>)> {
+    let apex = match rrs.iter().find(|rr| rr.r#type.to_qtype() == QType::SOA) {
+        Some(soa) => soa.name.clone(),
+        None => {
+            warn!("RPZ without SOA,  taking owners as they are");
+            ".".to_string()
+        }
+    };
+    let mut triggers: HashMap<String, Vec<Type>> = HashMap::new();
+    for rr in rrs {
+        let trigger = match apex.as_str() {
+            "." => rr.name.clone(),
+            apex => match rr.name.strip_suffix(&format!(".{}", apex)) {
+                Some(trigger) => format!("{}.", trigger),
+                None => continue,
+            },
+        };
+        triggers.entry(trigger).or_default().push(rr.r#type);
+    }
+
+    let mut entries = vec![];
+    for (trigger, types) in triggers {
+        // Triggers on response IPs and name servers are not supported.
+        if trigger.split('.').any(|label| label.starts_with("rpz-")) {
+            warn!("RPZ: ignoring unsupported trigger {}", trigger);
+            continue;
+        }
+        let action = match &types[..] {
+            [Type::CNAME(target)] => match target.as_str() {
+                "." => PolicyAction::NxDomain,
+                "*." => PolicyAction::NoData,
+                "rpz-passthru." => PolicyAction::Passthru,
+                _ => {
+                    warn!("RPZ: ignoring unsupported action {} of {}", target, trigger);
+                    continue;
+                }
+            },
+            types => {
+                let ips: Vec<IpAddr> = types
+                    .iter()
+                    .filter_map(|r#type| match r#type {
+                        Type::A(ip) => Some(IpAddr::V4(*ip)),
+                        Type::AAAA(ip) => Some(IpAddr::V6(*ip)),
+                        _ => None,
+                    })
+                    .collect();
+                if ips.is_empty() {
+                    continue;
+                }
+                PolicyAction::Sinkhole(ips)
+            }
+        };
+        entries.push((trigger, Some(action)));
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_hosts, parse_rpz, Policy, PolicyList};
+    use crate::business::models::{DNSQuery, QType, ResponseCode, Type};
+    use crate::config::PolicyAction;
+    use crate::resolver::master;
+    use std::collections::HashMap;
+
+    fn list(name: &str, entries: &[(&str, PolicyAction)]) -> PolicyList {
+        let mut list = PolicyList {
+            name: name.to_string(),
+            exact: HashMap::new(),
+            wildcard: HashMap::new(),
+        };
+        for (entry, action) in entries {
+            match entry.strip_prefix("*.") {
+                Some(entry) => list.wildcard.insert(entry.to_string(), action.clone()),
+                None => list.exact.insert(entry.to_string(), action.clone()),
+            };
+        }
+        list
+    }
+
+    #[test]
+    fn test_policy_exact_and_wildcard() {
+        // Arrange
+        let policy = Policy {
+            lists: vec![list(
+                "ads",
+                &[
+                    ("tracker.example.", PolicyAction::NxDomain),
+                    ("*.ads.example.", PolicyAction::NoData),
+                ],
+            )],
+        };
+
+        // Act
+        let exact = policy.check("Tracker.example.");
+        let below_exact = policy.check("www.tracker.example.");
+        let wildcard = policy.check("a.b.ads.example.");
+        let wildcard_base = policy.check("ads.example.");
+
+        // Assert
+        assert_eq!(exact.unwrap().action, &PolicyAction::NxDomain);
+        assert!(below_exact.is_none());
+        assert_eq!(wildcard.unwrap().action, &PolicyAction::NoData);
+        assert!(wildcard_base.is_none());
+    }
+
+    #[test]
+    fn test_policy_passthru_is_an_exception() {
+        // Arrange
+        let policy = Policy {
+            lists: vec![
+                list("ads", &[("*.example.", PolicyAction::NxDomain)]),
+                list("allow", &[("ok.example.", PolicyAction::Passthru)]),
+            ],
+        };
+
+        // Act
+        let allowed = policy.check("ok.example.").unwrap();
+        let blocked = policy.check("bad.example.").unwrap();
+
+        // Assert
+        assert_eq!(allowed.list, "allow");
+        assert_eq!(allowed.action, &PolicyAction::Passthru);
+        assert_eq!(blocked.list, "ads");
+    }
+
+    #[test]
+    fn test_policy_respond() {
+        // Arrange
+        let policy = Policy {
+            lists: vec![list(
+                "malware",
+                &[
+                    (
+                        "sink.example.",
+                        PolicyAction::Sinkhole(vec!["0.0.0.0".parse().unwrap()]),
+                    ),
+                    ("bad.example.", PolicyAction::NxDomain),
+                ],
+            )],
+        };
+        let sinkhole = policy.check("sink.example.").unwrap();
+        let nxdomain = policy.check("bad.example.").unwrap();
+
+        // Act
+        let a = sinkhole.respond(&DNSQuery::for_test("sink.example.", QType::A));
+        let aaaa = sinkhole.respond(&DNSQuery::for_test("sink.example.", QType::AAAA));
+        let nxdomain = nxdomain.respond(&DNSQuery::for_test("bad.example.", QType::A));
+
+        // Assert
+        assert_eq!(a.answers[0].r#type, Type::A([0, 0, 0, 0].into()));
+        assert_eq!(a.answers[0].name, "sink.example.");
+        assert!(aaaa.answers.is_empty());
+        assert_eq!(nxdomain.query.header.response_code, ResponseCode::NameError);
+    }
+
+    #[test]
+    fn test_parse_hosts_blocklist() {
+        // Arrange
+        let contents =
+            "127.0.0.1 localhost\n0.0.0.0 0.0.0.0\n0.0.0.0 ads.example ads2.example # ad\n";
+
+        // Act
+        let entries = parse_hosts(contents);
+
+        // Assert
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["ads.example", "ads2.example"]);
+    }
+
+    #[test]
+    fn test_parse_rpz() {
+        // Arrange
+        let rrs = master::parse(
+            "
+$ORIGIN rpz.example.
+$TTL 60
+@                   SOA     localhost. root.localhost. 1 3600 600 86400 60
+                    NS      localhost.
+bad.com             CNAME   .
+*.bad.com           CNAME   *.
+ok.bad.com          CNAME   rpz-passthru.
+ads.com             A       0.0.0.0
+ads.com             AAAA    ::
+32.1.2.0.192.rpz-ip CNAME   .
+",
+            ".",
+        )
+        .unwrap();
+
+        // Act
+        let entries: HashMap<String, Option<PolicyAction>> = parse_rpz(rrs).into_iter().collect();
+
+        // Assert
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries["bad.com."], Some(PolicyAction::NxDomain));
+        assert_eq!(entries["*.bad.com."], Some(PolicyAction::NoData));
+        assert_eq!(entries["ok.bad.com."], Some(PolicyAction::Passthru));
+        match &entries["ads.com."] {
+            Some(PolicyAction::Sinkhole(ips)) => assert_eq!(ips.len(), 2),
+            _ => panic!("expected a sinkhole"),
+        }
+    }
+}
@@ -0,0 +1,9 @@
+// Resolver as a library,  so that benches can drive its parts directly.  The binary in main.rs
+// wires them to the sockets.
+pub mod business;
+pub mod config;
+pub mod error;
+pub mod handler;
+pub mod reactor;
+pub mod resolver;
+pub mod server;
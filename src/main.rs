@@ -1,10 +1,7 @@
 // Baby steps
-use crate::business::models::DNSQueryResponse;
-use crate::handler::Handler;
 use clap::{App, Arg, ArgMatches};
-use error::FetchError;
 use hyper::header::HeaderValue;
-use hyper::server::conn::AddrStream;
+use hyper::server::conn::{AddrStream, Http};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Error, Method, Request, Response, Server, StatusCode};
 use lazy_static::lazy_static;
@@ -13,22 +10,28 @@ use prometheus::{
     self, exponential_buckets, register_histogram_vec, register_int_counter, register_int_gauge,
     Encoder, HistogramVec, IntCounter, IntGauge, TextEncoder,
 };
+use rrdns::business::models::DNSQueryResponse;
+use rrdns::config::{self, Config, ZoneRouteKind};
+use rrdns::error::FetchError;
+use rrdns::handler::{Handler, MIN_UDP_PAYLOAD_SIZE};
+use rrdns::reactor::{https, tcp, tls};
 use serde_json;
 use std::convert::Infallible;
+use std::fs;
 use std::net::SocketAddr;
 use std::ops::Deref;
 use std::panic;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
 
-mod business;
-mod error;
-mod handler;
-mod reactor;
-mod resolver;
-mod server;
+// TCP connections that stay quiet for this long are closed.
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
 
 lazy_static! {
     static ref RRDNS_QUERY_COUNTER: IntCounter =
@@ -112,6 +115,192 @@ fn init<'a>() -> ArgMatches<'a> {
                 .takes_value(true)
                 .help("Prometheus metrics will be exposed on this address"),
         )
+        .arg(
+            Arg::with_name("listen_tls")
+                .long("listen_tls")
+                .takes_value(true)
+                .help("DNS over TLS queries are served on this address when a certificate is given"),
+        )
+        .arg(
+            Arg::with_name("tls_certificate")
+                .long("tls_certificate")
+                .takes_value(true)
+                .requires("tls_private_key")
+                .help("PEM certificate chain of the DNS over TLS listener"),
+        )
+        .arg(
+            Arg::with_name("tls_private_key")
+                .long("tls_private_key")
+                .takes_value(true)
+                .requires("tls_certificate")
+                .help("PEM private key of the DNS over TLS listener"),
+        )
+        .arg(
+            Arg::with_name("listen_doh")
+                .long("listen_doh")
+                .takes_value(true)
+                .help("DNS over HTTPS queries are served on this address at /dns-query, JSON ones at /resolve"),
+        )
+        .arg(
+            Arg::with_name("doh_tls")
+                .long("doh_tls")
+                .requires("listen_doh")
+                .requires("tls_certificate")
+                .help("Serve DNS over HTTPS with the TLS certificate instead of plain HTTP"),
+        )
+        .arg(
+            Arg::with_name("tls_max_connections")
+                .long("tls_max_connections")
+                .takes_value(true)
+                .help("Most DNS over TLS connections served at once"),
+        )
+        .arg(
+            Arg::with_name("tls_idle_timeout")
+                .long("tls_idle_timeout")
+                .takes_value(true)
+                .help("Seconds a DNS over TLS connection may stay without queries"),
+        )
+        .arg(
+            Arg::with_name("edns_buffer_size")
+                .long("edns_buffer_size")
+                .takes_value(true)
+                .help("EDNS(0) UDP payload size advertised upstream and to clients, at least 512"),
+        )
+        .arg(
+            Arg::with_name("no_dnssec")
+                .long("no_dnssec")
+                .help("Do not validate answers with DNSSEC"),
+        )
+        .arg(
+            Arg::with_name("randomize_case")
+                .long("randomize_case")
+                .help("Mix the case of names sent to name servers and drop answers that do not echo it (DNS 0x20)"),
+        )
+        .arg(
+            Arg::with_name("query_stagger")
+                .long("query_stagger")
+                .takes_value(true)
+                .help("Milliseconds to wait for a name server before also asking the next one, one at a time when not given"),
+        )
+        .arg(
+            Arg::with_name("trust_anchors")
+                .long("trust_anchors")
+                .takes_value(true)
+                .help("File with the DS records of the root zone to validate from"),
+        )
+        .arg(
+            Arg::with_name("cache_max_entries")
+                .long("cache_max_entries")
+                .takes_value(true)
+                .help("Most RRsets and negative answers the cache holds"),
+        )
+        .arg(
+            Arg::with_name("cache_max_bytes")
+                .long("cache_max_bytes")
+                .takes_value(true)
+                .help("Most memory in bytes the cached records may take"),
+        )
+        .arg(
+            Arg::with_name("cache_eviction")
+                .long("cache_eviction")
+                .takes_value(true)
+                .possible_values(&["lru", "lfu"])
+                .help("Which entries are evicted first when the cache is full"),
+        )
+        .arg(
+            Arg::with_name("cache_sweep_interval")
+                .long("cache_sweep_interval")
+                .takes_value(true)
+                .help("Seconds between purges of expired records from the cache"),
+        )
+        .arg(
+            Arg::with_name("cache_snapshot")
+                .long("cache_snapshot")
+                .takes_value(true)
+                .help("File the cache is saved to periodically and restored from on start"),
+        )
+        .arg(
+            Arg::with_name("cache_snapshot_interval")
+                .long("cache_snapshot_interval")
+                .takes_value(true)
+                .help("Seconds between saves of the cache snapshot"),
+        )
+        .arg(
+            Arg::with_name("forward")
+                .long("forward")
+                .takes_value(true)
+                .use_delimiter(true)
+                .help("Forward queries to these recursive resolvers, e.g. 10.0.0.1,tls://9.9.9.9#dns.quad9.net,https://1.1.1.1/dns-query#cloudflare-dns.com"),
+        )
+        .arg(
+            Arg::with_name("forward_ca")
+                .long("forward_ca")
+                .takes_value(true)
+                .help("PEM certificates to verify TLS and HTTPS forwarders against instead of the system's"),
+        )
+        .arg(
+            Arg::with_name("forward_policy")
+                .long("forward_policy")
+                .takes_value(true)
+                .possible_values(&["failover", "round_robin", "lowest_latency"])
+                .help("Which upstream a forwarded query goes to first"),
+        )
+        .arg(
+            Arg::with_name("forward_zone")
+                .long("forward_zone")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Forward a zone to its own resolvers, e.g. corp.example.=10.0.0.1,10.0.0.2"),
+        )
+        .arg(
+            Arg::with_name("zone")
+                .long("zone")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Answer a zone from its master file, e.g. example.com.=/etc/rrdns/example.com.zone"),
+        )
+        .arg(
+            Arg::with_name("hosts")
+                .long("hosts")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("File in /etc/hosts format with names to answer locally"),
+        )
+        .arg(
+            Arg::with_name("local_data")
+                .long("local_data")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Record to answer locally, e.g. 'printer.lan. 300 A 192.0.2.1'"),
+        )
+        .arg(
+            Arg::with_name("hosts_reload_interval")
+                .long("hosts_reload_interval")
+                .takes_value(true)
+                .help("Seconds between checks of the hosts files for changes"),
+        )
+        .arg(
+            Arg::with_name("blocklist")
+                .long("blocklist")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Blocked domains, e.g. ads=hosts:/etc/rrdns/ads.hosts or allow=domains:/etc/rrdns/allow.txt,passthru"),
+        )
+        .arg(
+            Arg::with_name("stub_zone")
+                .long("stub_zone")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Ask these authoritative servers for a zone, e.g. 10.in-addr.arpa.=10.0.0.53",
+                ),
+        )
         .get_matches();
 
     matches
@@ -151,8 +340,140 @@ async fn main() {
         .value_of("listen_metrics")
         .unwrap_or("127.0.0.1:9999")
         .to_string();
+    let listen_doh_addr = matches.value_of("listen_doh").map(str::to_string);
+    // https://tools.ietf.org/html/rfc7858 3.1
+    let listen_tls_addr = matches
+        .value_of("listen_tls")
+        .unwrap_or("127.0.0.1:853")
+        .to_string();
+
+    let mut config = Config::default();
+    if let Some(edns_buffer_size) = matches.value_of("edns_buffer_size") {
+        // https://tools.ietf.org/html/rfc6891 6.2.5
+        // Peers treat sizes below 512 as 512,  asking for one is a mistake.
+        config.edns_udp_payload_size = edns_buffer_size
+            .parse()
+            .ok()
+            .filter(|size| *size >= MIN_UDP_PAYLOAD_SIZE)
+            .unwrap_or_else(|| {
+                panic!(
+                    "edns_buffer_size must be a number between {} and 65535",
+                    MIN_UDP_PAYLOAD_SIZE
+                )
+            });
+    }
+    config.dnssec_validation = !matches.is_present("no_dnssec");
+    config.qname_case_randomization = matches.is_present("randomize_case");
+    if let Some(query_stagger) = matches.value_of("query_stagger") {
+        config.query_stagger = Some(Duration::from_millis(
+            query_stagger
+                .parse()
+                .expect("query_stagger must be a number of milliseconds"),
+        ));
+    }
+    if let Some(trust_anchors_path) = matches.value_of("trust_anchors") {
+        let contents =
+            fs::read_to_string(trust_anchors_path).expect("Failed to read trust anchors file.");
+        config.root_trust_anchors = config::parse_trust_anchors(&contents)
+            .unwrap_or_else(|err| panic!("invalid trust anchors: {}", err));
+    }
+    if let Some(cache_max_entries) = matches.value_of("cache_max_entries") {
+        config.cache_max_entries = cache_max_entries
+            .parse()
+            .expect("cache_max_entries must be a number");
+    }
+    if let Some(cache_max_bytes) = matches.value_of("cache_max_bytes") {
+        config.cache_max_bytes = cache_max_bytes
+            .parse()
+            .expect("cache_max_bytes must be a number");
+    }
+    if let Some(cache_eviction) = matches.value_of("cache_eviction") {
+        config.cache_eviction = cache_eviction.parse().unwrap();
+    }
+    if let Some(cache_sweep_interval) = matches.value_of("cache_sweep_interval") {
+        config.cache_sweep_interval = Duration::from_secs(
+            cache_sweep_interval
+                .parse()
+                .expect("cache_sweep_interval must be a number of seconds"),
+        );
+    }
+    config.cache_snapshot_path = matches.value_of("cache_snapshot").map(PathBuf::from);
+    if let Some(forwarders) = matches.values_of("forward") {
+        config.forwarders = forwarders
+            .map(config::parse_forwarder)
+            .collect::<Result<_, _>>()
+            .unwrap_or_else(|err| panic!("invalid forwarder: {}", err));
+    }
+    config.forward_ca_path = matches.value_of("forward_ca").map(PathBuf::from);
+    if let Some(forward_policy) = matches.value_of("forward_policy") {
+        config.forward_policy = forward_policy.parse().unwrap();
+    }
+    for zone_file in matches.values_of("zone").into_iter().flatten() {
+        config.zone_files.push(
+            config::parse_zone_file(zone_file)
+                .unwrap_or_else(|err| panic!("invalid zone: {}", err)),
+        );
+    }
+    config.hosts_files = matches
+        .values_of("hosts")
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .collect();
+    config.local_data = matches
+        .values_of("local_data")
+        .into_iter()
+        .flatten()
+        .map(String::from)
+        .collect();
+    for blocklist in matches.values_of("blocklist").into_iter().flatten() {
+        config.blocklists.push(
+            config::parse_blocklist(blocklist)
+                .unwrap_or_else(|err| panic!("invalid blocklist: {}", err)),
+        );
+    }
+    if let Some(hosts_reload_interval) = matches.value_of("hosts_reload_interval") {
+        config.hosts_reload_interval = Duration::from_secs(
+            hosts_reload_interval
+                .parse()
+                .expect("hosts_reload_interval must be a number of seconds"),
+        );
+    }
+    for (arg, kind) in &[
+        ("forward_zone", ZoneRouteKind::Forward),
+        ("stub_zone", ZoneRouteKind::Stub),
+    ] {
+        for route in matches.values_of(arg).into_iter().flatten() {
+            config.zone_routes.push(
+                config::parse_zone_route(route, kind.clone())
+                    .unwrap_or_else(|err| panic!("invalid {}: {}", arg, err)),
+            );
+        }
+    }
+    config.tls_certificate_path = matches.value_of("tls_certificate").map(PathBuf::from);
+    config.tls_private_key_path = matches.value_of("tls_private_key").map(PathBuf::from);
+    if let Some(tls_max_connections) = matches.value_of("tls_max_connections") {
+        config.tls_max_connections = tls_max_connections
+            .parse()
+            .expect("tls_max_connections must be a number");
+    }
+    if let Some(tls_idle_timeout) = matches.value_of("tls_idle_timeout") {
+        config.tls_idle_timeout = Duration::from_secs(
+            tls_idle_timeout
+                .parse()
+                .expect("tls_idle_timeout must be a number of seconds"),
+        );
+    }
+    config.doh_tls = matches.is_present("doh_tls");
+    if let Some(cache_snapshot_interval) = matches.value_of("cache_snapshot_interval") {
+        config.cache_snapshot_interval = Duration::from_secs(
+            cache_snapshot_interval
+                .parse()
+                .expect("cache_snapshot_interval must be a number of seconds"),
+        );
+    }
 
-    let handler = Arc::new(Handler::new());
+    let handler = Arc::new(Handler::new(&config));
 
     tokio::spawn(async move {
         let prometheus_exposition_addr = listen_metrics_addr.parse::<SocketAddr>().unwrap();
@@ -168,6 +489,58 @@ async fn main() {
         }
     });
 
+    // Expired records are otherwise only dropped when they are looked up.
+    let sweeper_handler = handler.clone();
+    let cache_sweep_interval = config.cache_sweep_interval;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(cache_sweep_interval);
+        loop {
+            interval.tick().await;
+            let purged = sweeper_handler.purge_expired_cache();
+            debug!("cache sweeper: purged {} entries", purged);
+        }
+    });
+
+    if let Some(cache_snapshot_path) = config.cache_snapshot_path.clone() {
+        let snapshot_handler = handler.clone();
+        let cache_snapshot_interval = config.cache_snapshot_interval;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(cache_snapshot_interval);
+            // First tick completes right away,  before anything new is cached.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                // Copying the cache and writing it out would hold up the queries sharing this
+                // thread.  Waiting for it keeps two saves from writing the file at once.
+                let snapshot_handler = snapshot_handler.clone();
+                let cache_snapshot_path = cache_snapshot_path.clone();
+                let saved = tokio::task::spawn_blocking(move || {
+                    snapshot_handler.save_cache_snapshot(&cache_snapshot_path)
+                })
+                .await;
+                if let Err(err) = saved {
+                    error!("cache snapshot save failed err={}", err);
+                }
+            }
+        });
+    }
+
+    if !config.hosts_files.is_empty() {
+        let reload_handler = handler.clone();
+        let hosts_reload_interval = config.hosts_reload_interval;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(hosts_reload_interval);
+            // First tick completes right away,  the files were just read.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if reload_handler.reload_local_data() {
+                    info!("hosts files changed,  reloaded local data");
+                }
+            }
+        });
+    }
+
     let debug_handler = handler.clone();
     tokio::spawn(async move {
         let debug_addr = listen_debug_addr.parse::<SocketAddr>().unwrap();
@@ -210,15 +583,115 @@ async fn main() {
     });
 
     let addr = listen_addr.parse::<SocketAddr>().unwrap();
+
+    let tcp_handler = handler.clone();
+    tokio::spawn(async move {
+        let mut listener = TcpListener::bind(addr).await.unwrap();
+        info!("DNS resolver (tcp) binded to address {}", addr);
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    tokio::spawn(serve_tcp_connection(stream, peer, tcp_handler.clone()));
+                }
+                Err(err) => error!("tcp: accept error={}", err),
+            }
+        }
+    });
+
+    if let (Some(certificate_path), Some(private_key_path)) = (
+        config.tls_certificate_path.as_ref(),
+        config.tls_private_key_path.as_ref(),
+    ) {
+        let tls_config = tls::server_config(certificate_path, private_key_path)
+            .unwrap_or_else(|err| panic!("invalid TLS certificate or key: {}", err));
+        let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+        let tls_addr = listen_tls_addr.parse::<SocketAddr>().unwrap();
+        let tls_listener = TcpListener::bind(tls_addr).await.unwrap();
+        info!("DNS resolver (tls) binded to address {}", tls_addr);
+        let tls_handler = handler.clone();
+        tokio::spawn(tls::serve(
+            tls_listener,
+            acceptor,
+            config.tls_max_connections,
+            config.tls_idle_timeout,
+            move |buf| answer_tls_query(buf, tls_handler.clone()),
+        ));
+    }
+
+    if let Some(listen_doh_addr) = listen_doh_addr {
+        let doh_addr = listen_doh_addr.parse::<SocketAddr>().unwrap();
+        let doh_acceptor = match (
+            config.doh_tls,
+            config.tls_certificate_path.as_ref(),
+            config.tls_private_key_path.as_ref(),
+        ) {
+            (true, Some(certificate_path), Some(private_key_path)) => {
+                let mut tls_config = tls::server_config(certificate_path, private_key_path)
+                    .unwrap_or_else(|err| panic!("invalid TLS certificate or key: {}", err));
+                // https://tools.ietf.org/html/rfc8484 5.2
+                tls_config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+                Some(TlsAcceptor::from(Arc::new(tls_config)))
+            }
+            _ => None,
+        };
+        let doh_handler = handler.clone();
+        let handshake_timeout = config.tls_idle_timeout;
+        let mut listener = TcpListener::bind(doh_addr)
+            .await
+            .unwrap_or_else(|err| panic!("cannot listen on {}: {}", doh_addr, err));
+        info!("DNS over HTTPS server binded to {}", doh_addr);
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        error!("doh: accept error={}", err);
+                        continue;
+                    }
+                };
+                let doh_acceptor = doh_acceptor.clone();
+                let doh_handler = doh_handler.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req| https_service(req, doh_handler.clone()));
+                    let served = match doh_acceptor {
+                        Some(acceptor) => {
+                            match timeout(handshake_timeout, acceptor.accept(stream)).await {
+                                Ok(Ok(stream)) => {
+                                    Http::new().serve_connection(stream, service).await
+                                }
+                                Ok(Err(err)) => {
+                                    debug!("doh: handshake error={} peer={}", err, peer);
+                                    return;
+                                }
+                                Err(_) => {
+                                    debug!("doh: handshake timeout peer={}", peer);
+                                    return;
+                                }
+                            }
+                        }
+                        None => Http::new().serve_connection(stream, service).await,
+                    };
+                    if let Err(err) = served {
+                        debug!("doh: connection error={} peer={}", err, peer);
+                    }
+                });
+            }
+        });
+    }
+
     let socket = tokio::net::UdpSocket::bind(addr).await.unwrap();
     info!("DNS resolver binded to address {}", listen_addr);
     let (mut socket_rx, mut socket_tx) = socket.split();
-    let (response_tx, mut response_rx) =
-        mpsc::channel::<(Result<DNSQueryResponse, FetchError>, SocketAddr, Instant)>(5);
+    let (response_tx, mut response_rx) = mpsc::channel::<(
+        Result<DNSQueryResponse, FetchError>,
+        SocketAddr,
+        Instant,
+        usize,
+    )>(5);
 
     // Read DNS queries from socket.
     let read_handler = tokio::spawn(async move {
-        let mut buf = [0; 1024];
+        let mut buf = vec![0; config.edns_udp_payload_size as usize];
         while let Ok((bytes_read_count, peer)) = socket_rx.recv_from(&mut buf).await {
             debug!("bytes read count: {}", bytes_read_count);
 
@@ -236,37 +709,21 @@ async fn main() {
     // Write DNS responses to socket.
     let write_handler = tokio::spawn(async move {
         loop {
-            let (response_result, peer, start_instant) = response_rx.recv().await.unwrap();
-            match response_result {
-                Ok(response) | Err(FetchError::QueryError(response)) => {
-                    let raw_response = response.serialize();
-                    let written_bytes = socket_tx.send_to(&raw_response, &peer).await.unwrap();
-                    let latency = start_instant.elapsed();
-                    RRDNS_RESOLUTION_DURATION
-                        .with_label_values(&["resolveit"])
-                        .observe(latency.as_secs_f64());
-                    RRDNS_QUERY_RESPONSE_SIZE
-                        .with_label_values(&["queryresponsesize"])
-                        .observe(raw_response.len() as f64);
-                    debug!(
-                        "{} written_bytes={} latency={:?}",
-                        response.query.header.id, written_bytes, latency
-                    );
-                }
-                Err(FetchError::NetworkError(err)) => {
-                    // What to do?
-                    RRDNS_RESOLUTION_FAILURE.inc();
-                    error!("ISE::NetworkError={}", err);
-                }
-                Err(FetchError::InfiniteRecursionError(err)) => {
-                    // Not sending any response back for now.
-                    RRDNS_RESOLUTION_FAILURE.inc();
-                    error!("terminal err={}", err)
-                }
-                Err(FetchError::NoIPError(err)) => {
-                    RRDNS_RESOLUTION_FAILURE.inc();
-                    error!("no ip error={}", err);
+            let (response_result, peer, start_instant, max_response_size) =
+                response_rx.recv().await.unwrap();
+            if let Some(response) = into_response(response_result) {
+                // https://tools.ietf.org/html/rfc1035 4.2.1
+                // Client is expected to retry over TCP when it sees TC.
+                let mut raw_response = response.serialize();
+                if raw_response.len() > max_response_size {
+                    raw_response = response.truncated().serialize();
                 }
+                let written_bytes = socket_tx.send_to(&raw_response, &peer).await.unwrap();
+                let latency = observe_response(&raw_response, start_instant);
+                debug!(
+                    "{} written_bytes={} latency={:?}",
+                    response.query.header.id, written_bytes, latency
+                );
             }
             RRDNS_PENDING_QUERIES_GAUGE.dec();
         }
@@ -281,13 +738,202 @@ async fn process(
     buf: Vec<u8>, // propagated
     peer: SocketAddr,
     handler: Arc<Handler>,
-    mut response_tx: mpsc::Sender<(Result<DNSQueryResponse, FetchError>, SocketAddr, Instant)>,
+    mut response_tx: mpsc::Sender<(
+        Result<DNSQueryResponse, FetchError>,
+        SocketAddr,
+        Instant,
+        usize,
+    )>,
 ) {
     let start = Instant::now();
     RRDNS_QUERY_COUNTER.inc();
     let response_result = handler.handle(&buf).await;
+    let max_response_size = handler.max_udp_response_size(&buf);
     response_tx
-        .send((response_result, peer, start))
+        .send((response_result, peer, start, max_response_size))
         .await
         .unwrap();
 }
+
+async fn serve_tcp_connection(mut stream: TcpStream, peer: SocketAddr, handler: Arc<Handler>) {
+    loop {
+        let buf = match timeout(TCP_IDLE_TIMEOUT, tcp::read_message(&mut stream)).await {
+            Ok(Ok(Some(buf))) => buf,
+            Ok(Ok(None)) => break,
+            Ok(Err(err)) => {
+                error!("tcp: read error={} peer={}", err, peer);
+                break;
+            }
+            Err(_) => {
+                debug!("tcp: idle timeout peer={}", peer);
+                break;
+            }
+        };
+
+        RRDNS_QUERY_SIZE
+            .with_label_values(&["querysize"])
+            .observe(buf.len() as f64);
+        RRDNS_PENDING_QUERIES_GAUGE.inc();
+        RRDNS_QUERY_COUNTER.inc();
+        let start = Instant::now();
+
+        let written = match into_response(handler.handle(&buf).await) {
+            Some(response) => {
+                let raw_response = response.serialize();
+                let written = tcp::write_message(&mut stream, &raw_response).await;
+                let latency = observe_response(&raw_response, start);
+                debug!(
+                    "{} tcp: written_bytes={} latency={:?}",
+                    response.query.header.id,
+                    raw_response.len(),
+                    latency
+                );
+                written
+            }
+            None => Ok(()),
+        };
+        RRDNS_PENDING_QUERIES_GAUGE.dec();
+
+        if let Err(err) = written {
+            error!("tcp: write error={} peer={}", err, peer);
+            break;
+        }
+    }
+}
+
+// https://tools.ietf.org/html/rfc8484 4
+// Wire format queries at /dns-query,  JSON ones at /resolve.
+async fn https_service(
+    req: Request<Body>,
+    handler: Arc<Handler>,
+) -> Result<Response<Body>, Infallible> {
+    let mut response = Response::new(Body::empty());
+    let json = req.uri().path() == https::RESOLVE_PATH;
+    let buf = match req.uri().path() {
+        https::DNS_QUERY_PATH => https::read_query(req).await,
+        https::RESOLVE_PATH => https::read_resolve_query(&req).map(|query| query.serialize()),
+        _ => Err(StatusCode::NOT_FOUND),
+    };
+    let buf = match buf {
+        Ok(buf) => buf,
+        Err(status) => {
+            *response.status_mut() = status;
+            return Ok(response);
+        }
+    };
+
+    RRDNS_QUERY_SIZE
+        .with_label_values(&["querysize"])
+        .observe(buf.len() as f64);
+    RRDNS_PENDING_QUERIES_GAUGE.inc();
+    RRDNS_QUERY_COUNTER.inc();
+    let start = Instant::now();
+
+    match into_response(handler.handle(&buf).await) {
+        Some(dns_response) => {
+            let (content_type, body) = if json {
+                let body = serde_json::to_vec(&https::JsonResponse::from(&dns_response)).unwrap();
+                ("application/json", body)
+            } else {
+                (https::DNS_MESSAGE, dns_response.serialize())
+            };
+            let latency = observe_response(&body, start);
+            debug!(
+                "{} https: written_bytes={} latency={:?}",
+                dns_response.query.header.id,
+                body.len(),
+                latency
+            );
+            response.headers_mut().insert(
+                hyper::header::CONTENT_TYPE,
+                HeaderValue::from_static(content_type),
+            );
+            // https://tools.ietf.org/html/rfc8484 5.1
+            if let Some(min_ttl) = dns_response.min_ttl() {
+                response.headers_mut().insert(
+                    hyper::header::CACHE_CONTROL,
+                    HeaderValue::from_str(&format!("max-age={}", min_ttl)).unwrap(),
+                );
+            }
+            *response.body_mut() = Body::from(body);
+        }
+        // Nothing to answer with,  not even SERVFAIL.
+        None => *response.status_mut() = StatusCode::BAD_GATEWAY,
+    }
+    RRDNS_PENDING_QUERIES_GAUGE.dec();
+
+    Ok(response)
+}
+
+// https://tools.ietf.org/html/rfc7858 3.3
+// Queries on a connection are resolved concurrently and answered as they complete,  so that a
+// slow one does not hold back those behind it.
+async fn answer_tls_query(buf: Vec<u8>, handler: Arc<Handler>) -> Option<Vec<u8>> {
+    RRDNS_QUERY_SIZE
+        .with_label_values(&["querysize"])
+        .observe(buf.len() as f64);
+    RRDNS_PENDING_QUERIES_GAUGE.inc();
+    RRDNS_QUERY_COUNTER.inc();
+
+    let start = Instant::now();
+    let raw_response = into_response(handler.handle(&buf).await).map(|response| {
+        let raw_response = response.serialize();
+        let latency = observe_response(&raw_response, start);
+        debug!(
+            "{} tls: written_bytes={} latency={:?}",
+            response.query.header.id,
+            raw_response.len(),
+            latency
+        );
+        raw_response
+    });
+    RRDNS_PENDING_QUERIES_GAUGE.dec();
+    raw_response
+}
+
+// Response to send back to the client,  if any.
+fn into_response(
+    response_result: Result<DNSQueryResponse, FetchError>,
+) -> Option<DNSQueryResponse> {
+    match response_result {
+        Ok(response) | Err(FetchError::QueryError(response)) => Some(response),
+        Err(FetchError::NetworkError(err)) => {
+            // What to do?
+            RRDNS_RESOLUTION_FAILURE.inc();
+            error!("ISE::NetworkError={}", err);
+            None
+        }
+        Err(FetchError::InfiniteRecursionError(err)) => {
+            // Not sending any response back for now.
+            RRDNS_RESOLUTION_FAILURE.inc();
+            error!("terminal err={}", err);
+            None
+        }
+        Err(FetchError::NoIPError(err)) => {
+            RRDNS_RESOLUTION_FAILURE.inc();
+            error!("no ip error={}", err);
+            None
+        }
+        Err(FetchError::ParseError(err)) => {
+            RRDNS_RESOLUTION_FAILURE.inc();
+            error!("parse error={}", err);
+            None
+        }
+        Err(FetchError::Timeout(err)) => {
+            RRDNS_RESOLUTION_FAILURE.inc();
+            error!("timeout error={}", err);
+            None
+        }
+    }
+}
+
+fn observe_response(raw_response: &[u8], start_instant: Instant) -> Duration {
+    let latency = start_instant.elapsed();
+    RRDNS_RESOLUTION_DURATION
+        .with_label_values(&["resolveit"])
+        .observe(latency.as_secs_f64());
+    RRDNS_QUERY_RESPONSE_SIZE
+        .with_label_values(&["queryresponsesize"])
+        .observe(raw_response.len() as f64);
+    latency
+}
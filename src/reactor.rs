@@ -1,24 +1,92 @@
-use crate::business::models::{DNSQueryResponse, ResponseCode};
+use crate::business::models::{DNSQuery, DNSQueryResponse, ResponseCode};
+use crate::config::ForwardTransport;
 use crate::error::FetchError;
+use crate::resolver::zone::randomize_case;
+use lazy_static::lazy_static;
 use log::{debug, error, info};
+use prometheus::{register_int_counter_vec, IntCounterVec};
 use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use tokio::net::UdpSocket;
+use std::io::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::udp::SendHalf;
+use tokio::stream::StreamExt;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::time::{delay_queue, timeout, DelayQueue, Elapsed};
+use tokio_rustls::rustls::ClientConfig;
 
 pub mod cmd;
+pub mod https;
+pub mod tcp;
+pub mod tls;
+pub mod udp;
 use cmd::{ReactorQuery, ReactorResponse};
+use https::HttpsUpstreams;
+use tls::TlsUpstreams;
+
+// https://tools.ietf.org/html/rfc1536 1
+// Queries are sent again when unanswered for this long,  the wait doubling every time.
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(400);
+const RETRANSMISSIONS: u32 = 2;
+// Queries over streams,  truncated answers asked for again over TCP and queries to encrypted
+// upstreams,  have a single deadline.
+const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref RRDNS_MISMATCHED_RESPONSES: IntCounterVec = register_int_counter_vec!(
+        "rrdns_mismatched_responses",
+        "Number of datagrams dropped because they did not match the query sent from their port",
+        &["reason"]
+    )
+    .unwrap();
+}
 
 pub struct Reactor {
     addr: &'static str,
     rx: Receiver<ReactorQuery>,
+    // Must be at least the EDNS UDP payload size advertised to authorities.
+    recv_buffer_size: usize,
+    // Queries for encrypted upstreams do not go through UDP sockets.
+    tls_upstreams: Arc<TlsUpstreams>,
+    https_upstreams: Arc<HttpsUpstreams>,
+    // https://tools.ietf.org/html/draft-vixie-dnsext-dns0x20-00
+    randomize_case: bool,
+}
+
+// Query waiting for its answer on the port bound for it.  Ports are bound for a single query,
+// so queries to different peers never share an entry even with the same id.
+struct Outstanding {
+    cmd: ReactorQuery,
+    // Name as the resolver asked it,  before its case was mixed.
+    qname: String,
+    // Dropped with the entry,  which stops receiving on the port.
+    _cancel_tx: oneshot::Sender<()>,
+    send_half: SendHalf,
+    wire_data: Vec<u8>,
+    retransmissions: u32,
+    timer: delay_queue::Key,
 }
 
 impl Reactor {
-    pub fn new(addr: &'static str) -> Sender<ReactorQuery> {
+    // tls_config verifies the certificates of encrypted upstreams.
+    pub fn spawn(
+        addr: &'static str,
+        recv_buffer_size: usize,
+        tls_config: ClientConfig,
+        randomize_case: bool,
+    ) -> Sender<ReactorQuery> {
         let (tx, rx) = channel(10);
 
-        let reactor = Reactor { addr, rx };
+        let reactor = Reactor {
+            addr,
+            rx,
+            recv_buffer_size,
+            tls_upstreams: Arc::new(TlsUpstreams::new(Arc::new(tls_config.clone()))),
+            https_upstreams: Arc::new(HttpsUpstreams::new(tls_config)),
+            randomize_case,
+        };
 
         tokio::spawn(reactor.run());
         debug!("new reactor created/spawned");
@@ -27,65 +95,437 @@ impl Reactor {
     }
 
     pub async fn run(mut self) {
-        let addr = SocketAddr::new(IpAddr::V4(self.addr.parse::<Ipv4Addr>().unwrap()), 34256);
-        let mut socket = match UdpSocket::bind(addr).await {
-            Ok(socket) => socket,
-            Err(err) => {
-                error!("could not bind the reactor to {} because of {}", addr, err);
-                std::process::exit(1)
-            }
-        };
+        let addr = self.addr.parse::<IpAddr>().unwrap();
+        let (datagrams_tx, mut datagrams_rx) = channel(100);
+        let mut registry: HashMap<u16, Outstanding> = HashMap::new();
+        // Ports of the outstanding queries,  by when they are due for a retransmission.
+        let mut timers = DelayQueue::new();
 
-        let mut registry = HashMap::new();
-
-        info!("Reactor is binded to address {}", addr);
+        info!(
+            "Reactor sends queries from random ports of address {}",
+            addr
+        );
         loop {
-            let mut read_buf = [0 as u8; 1024];
-
             tokio::select! {
 
                 Some(cmd) = self.rx.recv() => {
+                    match cmd.transport.clone() {
+                        ForwardTransport::Udp => {}
+                        ForwardTransport::Tls { server_name } => {
+                            tokio::spawn(Reactor::request_over_tls(self.tls_upstreams.clone(), cmd, server_name));
+                            continue;
+                        }
+                        ForwardTransport::Https { server_name, path } => {
+                            tokio::spawn(Reactor::request_over_https(self.https_upstreams.clone(), cmd, server_name, path));
+                            continue;
+                        }
+                    }
+
+                    let mut cmd = cmd;
+                    let qname = cmd.query.questions.first().map(|question| question.qname.clone()).unwrap_or_default();
+                    if self.randomize_case {
+                        if let Some(question) = cmd.query.questions.first_mut() {
+                            question.qname = randomize_case(&qname);
+                        }
+                    }
+
                     let wire_data = cmd.query.serialize();
-                    match socket.send_to(wire_data.as_slice(), cmd.peer_addr).await {
-                        Ok(written_bytes) => {
-                            info!("{} reactor: written {} bytes to addr={}", cmd.query.header.id, written_bytes, cmd.peer_addr);
-                            if registry.contains_key(&cmd.query.header.id) {
-                                error!("{} key={} exists", cmd.query.header.id, cmd.query.header.id);
-                            }
-                            registry.insert(cmd.query.header.id, cmd);
+                    match udp::send(addr, cmd.peer_addr, &wire_data).await {
+                        Ok((port, recv_half, send_half)) => {
+                            info!("{} reactor: written to addr={} from port={}", cmd.query.header.id, cmd.peer_addr, port);
+                            let (cancel_tx, cancel_rx) = oneshot::channel();
+                            tokio::spawn(udp::receive(recv_half, port, self.recv_buffer_size, datagrams_tx.clone(), cancel_rx));
+                            let timer = timers.insert(port, RETRANSMIT_TIMEOUT);
+                            registry.insert(port, Outstanding { cmd, qname, _cancel_tx: cancel_tx, send_half, wire_data, retransmissions: 0, timer });
                         },
                         Err(err) => {
-                            error!("reactor: send_to error={} addr={}", err, addr);
-                            cmd.respond_tx.send(Err(FetchError::NetworkError(err))).unwrap();
+                            error!("reactor: send_to error={} addr={}", err, cmd.peer_addr);
+                            if cmd.respond_tx.send(Err(FetchError::NetworkError(err))).is_err() {
+                                error!("{} reactor: oneshot receiver is closed", cmd.query.header.id);
+                            }
                         }
                     };
                 },
 
-                Ok(read_bytes_count) = socket.recv(&mut read_buf) => {
-                    let response_data = &read_buf[..read_bytes_count];
-                    let response = DNSQueryResponse::deserialize(response_data);
-
-                    if let Some(cmd) = registry.remove(&response.query.header.id) {
-                        let reactor_response = ReactorResponse {
-                            response
-                        };
-                        if cmd.respond_tx.is_closed() {
-                            panic!("reactor: oneshot receiver is closed");
+                Some(datagram) = datagrams_rx.recv() => {
+                    let cmd = match registry.get(&datagram.port) {
+                        Some(outstanding) => &outstanding.cmd,
+                        // Already answered,  the port is being closed.
+                        None => continue,
+                    };
+                    let id = cmd.query.header.id;
+                    let response = match DNSQueryResponse::deserialize(&datagram.data) {
+                        Ok(response) => response,
+                        // Whoever sent it,  the answer may still come:  retransmission or the
+                        // timeout decide the query.
+                        Err(err) => {
+                            info!("{} reactor: dropping malformed response err={} from addr={}", id, err, datagram.from);
+                            RRDNS_MISMATCHED_RESPONSES.with_label_values(&["malformed"]).inc();
+                            continue;
                         }
+                    };
 
-                        if reactor_response.response.query.header.response_code != ResponseCode::NoError {
-                            cmd.respond_tx.send(Err(FetchError::QueryError(reactor_response.response))).unwrap();
-                            continue;
+                    if let Some(reason) = mismatch(&cmd.query, cmd.peer_addr, &response, datagram.from, self.randomize_case) {
+                        info!("{} reactor: dropping response with mismatched {} from addr={}", id, reason, datagram.from);
+                        RRDNS_MISMATCHED_RESPONSES.with_label_values(&[reason]).inc();
+                        continue;
+                    }
+
+                    let Outstanding { mut cmd, qname, timer, .. } = registry.remove(&datagram.port).unwrap();
+                    timers.remove(&timer);
+                    // Resolver stops waiting for upstreams that take too long.
+                    if cmd.respond_tx.is_closed() {
+                        info!("{} reactor: late response from addr={}", id, cmd.peer_addr);
+                        continue;
+                    }
+
+                    // Answer did not fit in a datagram, ask the same authority again over TCP.
+                    if response.query.header.is_truncated {
+                        info!("{} reactor: truncated response from addr={}, retrying over tcp", id, cmd.peer_addr);
+                        if let Some(question) = cmd.query.questions.first_mut() {
+                            question.qname = qname;
                         }
+                        tokio::spawn(Reactor::retry_over_tcp(cmd));
+                        continue;
+                    }
 
-                        cmd.respond_tx.send(Ok(reactor_response)).unwrap();
+                    let mut response = response;
+                    if self.randomize_case {
+                        restore_qname(&mut response, &qname);
+                    }
+                    let result = if response.query.header.response_code != ResponseCode::NoError {
+                        Err(FetchError::QueryError(response))
                     } else {
-                        // This should never happen.
-                        error!("\"{}\" is missing in registry", response.query.header.id);
+                        Ok(ReactorResponse { response })
+                    };
+                    // Resolver may have stopped waiting since is_closed,  e.g. another server answered first.
+                    if cmd.respond_tx.send(result).is_err() {
+                        error!("{} reactor: oneshot receiver is closed", id);
+                    }
+                }
+
+                Some(Ok(expired)) = timers.next() => {
+                    let port = expired.into_inner();
+                    let outstanding = registry.get_mut(&port).unwrap();
+                    let id = outstanding.cmd.query.header.id;
+                    // Resolver stops waiting for upstreams that take too long.
+                    if outstanding.cmd.respond_tx.is_closed() {
+                        info!("{} reactor: dropping abandoned query to addr={}", id, outstanding.cmd.peer_addr);
+                        registry.remove(&port);
+                        continue;
+                    }
+
+                    if outstanding.retransmissions < RETRANSMISSIONS {
+                        outstanding.retransmissions += 1;
+                        info!("{} reactor: no answer from addr={}, retransmission {}", id, outstanding.cmd.peer_addr, outstanding.retransmissions);
+                        if let Err(err) = outstanding.send_half.send_to(&outstanding.wire_data, &outstanding.cmd.peer_addr).await {
+                            error!("{} reactor: send_to error={} addr={}", id, err, outstanding.cmd.peer_addr);
+                        }
+                        outstanding.timer = timers.insert(port, RETRANSMIT_TIMEOUT * 2u32.pow(outstanding.retransmissions));
+                        continue;
+                    }
+
+                    let outstanding = registry.remove(&port).unwrap();
+                    info!("{} reactor: timed out waiting for addr={}", id, outstanding.cmd.peer_addr);
+                    let err = format!("{} no answer from {} after {} retransmissions", id, outstanding.cmd.peer_addr, RETRANSMISSIONS);
+                    if outstanding.cmd.respond_tx.send(Err(FetchError::Timeout(err))).is_err() {
+                        error!("{} reactor: oneshot receiver is closed", id);
                     }
                 }
 
             }
         }
     }
+
+    async fn retry_over_tcp(cmd: ReactorQuery) {
+        let raw_response = timeout(TCP_TIMEOUT, tcp::request(&cmd.query, cmd.peer_addr)).await;
+        Reactor::respond_in_time(cmd, "tcp", raw_response);
+    }
+
+    async fn request_over_tls(
+        upstreams: Arc<TlsUpstreams>,
+        cmd: ReactorQuery,
+        server_name: String,
+    ) {
+        let request = upstreams.request(&cmd.query, cmd.peer_addr, &server_name);
+        let raw_response = timeout(TCP_TIMEOUT, request).await;
+        Reactor::respond_in_time(cmd, "tls", raw_response);
+    }
+
+    async fn request_over_https(
+        upstreams: Arc<HttpsUpstreams>,
+        cmd: ReactorQuery,
+        server_name: String,
+        path: String,
+    ) {
+        let request = upstreams.request(&cmd.query, cmd.peer_addr, &server_name, &path);
+        let raw_response = timeout(TCP_TIMEOUT, request).await;
+        Reactor::respond_in_time(cmd, "https", raw_response);
+    }
+
+    // Same as respond,  for requests given up on after TCP_TIMEOUT.  Dropping them closes their
+    // connection.
+    fn respond_in_time(
+        cmd: ReactorQuery,
+        transport: &str,
+        raw_response: Result<Result<Vec<u8>, Error>, Elapsed>,
+    ) {
+        match raw_response {
+            Ok(raw_response) => Reactor::respond(cmd, transport, raw_response),
+            Err(_) => {
+                let err = format!(
+                    "{} no answer from {} over {}",
+                    cmd.query.header.id, cmd.peer_addr, transport
+                );
+                if cmd.respond_tx.send(Err(FetchError::Timeout(err))).is_err() {
+                    error!(
+                        "{} reactor: oneshot receiver is closed",
+                        cmd.query.header.id
+                    );
+                }
+            }
+        }
+    }
+
+    // Hands the answer read over a stream to the query waiting for it.
+    fn respond(cmd: ReactorQuery, transport: &str, raw_response: Result<Vec<u8>, Error>) {
+        let result = match raw_response {
+            Ok(raw_response) => match DNSQueryResponse::deserialize(&raw_response) {
+                Ok(response) if response.query.header.response_code != ResponseCode::NoError => {
+                    Err(FetchError::QueryError(response))
+                }
+                Ok(response) => Ok(ReactorResponse { response }),
+                Err(err) => {
+                    error!(
+                        "{} reactor: could not parse {} response err={} addr={}",
+                        cmd.query.header.id, transport, err, cmd.peer_addr
+                    );
+                    Err(FetchError::ParseError(err))
+                }
+            },
+            Err(err) => {
+                error!(
+                    "{} reactor: {} error={} addr={}",
+                    cmd.query.header.id, transport, err, cmd.peer_addr
+                );
+                Err(FetchError::NetworkError(err))
+            }
+        };
+
+        if cmd.respond_tx.send(result).is_err() {
+            error!(
+                "{} reactor: oneshot receiver is closed",
+                cmd.query.header.id
+            );
+        }
+    }
+}
+
+// https://tools.ietf.org/html/rfc5452 9.1
+// Answers are only taken from the address the query went to,  with its id and its question.
+// Returns why response does not match query,  None when it does.
+fn mismatch(
+    query: &DNSQuery,
+    peer_addr: SocketAddr,
+    response: &DNSQueryResponse,
+    from: SocketAddr,
+    exact_case: bool,
+) -> Option<&'static str> {
+    if from != peer_addr {
+        return Some("source");
+    }
+    if response.query.header.id != query.header.id {
+        return Some("id");
+    }
+    match (query.questions.first(), response.query.questions.first()) {
+        (Some(asked), Some(echoed)) => {
+            if echoed.qtype != asked.qtype
+                || echoed.qclass != asked.qclass
+                || !echoed.qname.eq_ignore_ascii_case(&asked.qname)
+            {
+                return Some("question");
+            }
+            // https://tools.ietf.org/html/draft-vixie-dnsext-dns0x20-00 4
+            // Echo of the name has to match it bit for bit.
+            if exact_case && echoed.qname != asked.qname {
+                return Some("case");
+            }
+            None
+        }
+        // Some servers leave the question out of errors,  which are not cached.
+        (Some(_), None)
+            if !matches!(
+                response.query.header.response_code,
+                ResponseCode::NoError | ResponseCode::NameError
+            ) =>
+        {
+            None
+        }
+        (None, None) => None,
+        _ => Some("question"),
+    }
+}
+
+// Answers name records after the echo of the mixed case query name,  they are cached under the
+// name as the resolver asked it.
+fn restore_qname(response: &mut DNSQueryResponse, qname: &str) {
+    let sent = match response.query.questions.first_mut() {
+        Some(question) => std::mem::replace(&mut question.qname, qname.to_string()),
+        None => return,
+    };
+    response
+        .answers
+        .iter_mut()
+        .chain(response.authority.iter_mut())
+        .chain(response.additional.iter_mut())
+        .filter(|rr| rr.name == sent)
+        .for_each(|rr| rr.name = qname.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mismatch, restore_qname, Reactor, RETRANSMISSIONS};
+    use crate::business::models::{DNSQuery, DNSQueryResponse};
+    use crate::config::ForwardTransport;
+    use crate::error::FetchError;
+    use crate::reactor::cmd::ReactorQuery;
+    use std::collections::HashSet;
+    use tokio::net::UdpSocket;
+    use tokio::sync::oneshot;
+    use tokio::time::{timeout, Duration};
+    use tokio_rustls::rustls::ClientConfig;
+
+    #[tokio::test]
+    async fn test_reactor_retransmits_then_times_out() {
+        // Arrange
+        let mut peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        let mut reactor_tx = Reactor::spawn("127.0.0.1", 512, ClientConfig::new(), false);
+        let mut raw_query = vec![0, 1, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        raw_query.extend_from_slice(b"\x07printer\x03lan\x00\x00\x01\x00\x01");
+        let (query, _) = DNSQuery::deserialize(&raw_query).unwrap();
+        let (respond_tx, respond_rx) = oneshot::channel();
+
+        // Act
+        reactor_tx
+            .send(ReactorQuery {
+                query,
+                peer_addr,
+                transport: ForwardTransport::Udp,
+                respond_tx,
+            })
+            .await
+            .unwrap();
+        let mut ports = HashSet::new();
+        let mut buf = [0u8; 512];
+        for _ in 0..=RETRANSMISSIONS {
+            let (_, from) = peer.recv_from(&mut buf).await.unwrap();
+            ports.insert(from.port());
+        }
+        let result = timeout(Duration::from_secs(10), respond_rx).await.unwrap();
+
+        // Assert
+        assert_eq!(ports.len(), 1);
+        assert!(matches!(result, Ok(Err(FetchError::Timeout(_)))));
+    }
+
+    #[tokio::test]
+    async fn test_reactor_drops_malformed_response() {
+        // Arrange
+        let mut peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        let mut reactor_tx = Reactor::spawn("127.0.0.1", 512, ClientConfig::new(), false);
+        let mut raw_query = vec![0, 1, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        raw_query.extend_from_slice(b"\x07printer\x03lan\x00\x00\x01\x00\x01");
+        let (query, _) = DNSQuery::deserialize(&raw_query).unwrap();
+        let (respond_tx, respond_rx) = oneshot::channel();
+
+        // Act
+        reactor_tx
+            .send(ReactorQuery {
+                query,
+                peer_addr,
+                transport: ForwardTransport::Udp,
+                respond_tx,
+            })
+            .await
+            .unwrap();
+        let mut buf = [0u8; 512];
+        let (len, from) = peer.recv_from(&mut buf).await.unwrap();
+        // Right id,  cut short in the question.
+        let malformed = &buf[..len - 3];
+        assert!(DNSQueryResponse::deserialize(malformed).is_err());
+        peer.send_to(malformed, &from).await.unwrap();
+        let mut raw_response = buf[..len].to_vec();
+        raw_response[2] |= 0x80;
+        peer.send_to(&raw_response, &from).await.unwrap();
+        let result = timeout(Duration::from_secs(10), respond_rx).await.unwrap();
+
+        // Assert
+        let response = result.unwrap().unwrap().response;
+        assert_eq!(response.query.header.id, 1);
+    }
+
+    #[test]
+    fn test_reactor_mismatch() {
+        // Arrange
+        let mut raw_query = vec![0, 1, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        raw_query.extend_from_slice(b"\x07PrInTeR\x03lAn\x00\x00\x01\x00\x01");
+        let (query, _) = DNSQuery::deserialize(&raw_query).unwrap();
+        let peer_addr = "192.0.2.53:53".parse().unwrap();
+        let response = |header: &[u8], question: &[u8]| {
+            let mut raw_response = header.to_vec();
+            raw_response.extend_from_slice(question);
+            DNSQueryResponse::deserialize(&raw_response).unwrap()
+        };
+        let answer = [0, 1, 0x81, 0x80, 0, 1, 0, 0, 0, 0, 0, 0];
+        let lowercase = response(&answer, b"\x07printer\x03lan\x00\x00\x01\x00\x01");
+        let echo = response(&answer, b"\x07PrInTeR\x03lAn\x00\x00\x01\x00\x01");
+        let other_id = response(
+            &[0, 2, 0x81, 0x80, 0, 1, 0, 0, 0, 0, 0, 0],
+            b"\x07PrInTeR\x03lAn\x00\x00\x01\x00\x01",
+        );
+        let other_type = response(&answer, b"\x07PrInTeR\x03lAn\x00\x00\x1c\x00\x01");
+        let format_error = response(&[0, 1, 0x81, 0x81, 0, 0, 0, 0, 0, 0, 0, 0], b"");
+        let no_question = response(&[0, 1, 0x81, 0x80, 0, 0, 0, 0, 0, 0, 0, 0], b"");
+
+        // Act
+        let check = |response: &DNSQueryResponse, from: &str, exact_case: bool| {
+            mismatch(
+                &query,
+                peer_addr,
+                response,
+                from.parse().unwrap(),
+                exact_case,
+            )
+        };
+
+        // Assert
+        assert_eq!(check(&echo, "192.0.2.53:53", true), None);
+        assert_eq!(check(&lowercase, "192.0.2.53:53", false), None);
+        assert_eq!(check(&lowercase, "192.0.2.53:53", true), Some("case"));
+        assert_eq!(check(&echo, "192.0.2.54:53", true), Some("source"));
+        assert_eq!(check(&echo, "192.0.2.53:5353", true), Some("source"));
+        assert_eq!(check(&other_id, "192.0.2.53:53", true), Some("id"));
+        assert_eq!(check(&other_type, "192.0.2.53:53", true), Some("question"));
+        assert_eq!(check(&format_error, "192.0.2.53:53", true), None);
+        assert_eq!(check(&no_question, "192.0.2.53:53", true), Some("question"));
+    }
+
+    #[test]
+    fn test_reactor_restore_qname() {
+        // Arrange
+        let mut raw_response = vec![0, 1, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        raw_response.extend_from_slice(b"\x07PrInTeR\x03lAn\x00\x00\x01\x00\x01");
+        raw_response
+            .extend_from_slice(b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x00\x3c\x00\x04\xc0\x00\x02\x01");
+        let mut response = DNSQueryResponse::deserialize(&raw_response).unwrap();
+
+        // Act
+        restore_qname(&mut response, "printer.lan.");
+
+        // Assert
+        assert_eq!(response.query.questions[0].qname, "printer.lan.");
+        assert_eq!(response.answers[0].name, "printer.lan.");
+    }
 }
@@ -1,4 +1,5 @@
 use crate::business::models::{DNSQuery, DNSQueryResponse};
+use crate::config::ForwardTransport;
 use crate::error::FetchError;
 use std::net::SocketAddr;
 
@@ -8,6 +9,7 @@ use tokio::sync::oneshot::Sender;
 pub struct ReactorQuery {
     pub query: DNSQuery,
     pub peer_addr: SocketAddr,
+    pub transport: ForwardTransport,
     pub respond_tx: Sender<Result<ReactorResponse, FetchError>>,
 }
 
@@ -0,0 +1,527 @@
+// https://tools.ietf.org/html/rfc8484 4.1
+// DNS over HTTPS carries the wire format query as the body of a POST,  or base64url encoded in
+// the dns parameter of a GET.
+use crate::business::models::{
+    DNSQuery, DNSQueryHeaderSection, DNSQueryResponse, DNSQuestionQuery, Edns, OpCode, QClass,
+    QType, ResourceRecord, ResponseCode,
+};
+use crate::resolver::master::decode_base64url;
+use crate::resolver::zone::normalize;
+use hyper::body::HttpBody;
+use hyper::client::connect::dns::Name;
+use hyper::client::HttpConnector;
+use hyper::header::{ACCEPT, CONTENT_TYPE};
+use hyper::service::Service;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use hyper_rustls::HttpsConnector;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::{self, Ready};
+use std::io::{Error, ErrorKind};
+use std::iter::{self, Once};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio_rustls::rustls::ClientConfig;
+
+pub const DNS_QUERY_PATH: &str = "/dns-query";
+
+// JSON API in the style of https://developers.google.com/speed/public-dns/docs/doh/json,  for
+// tools without a DNS library.
+pub const RESOLVE_PATH: &str = "/resolve";
+
+// https://tools.ietf.org/html/rfc8484 6
+pub const DNS_MESSAGE: &str = "application/dns-message";
+
+// Largest message that fits the length prefix of DNS over TCP,  which the answer may be sent on.
+const MAX_MESSAGE_SIZE: usize = u16::MAX as usize;
+
+// Wire format query carried by request,  or the status it is refused with.
+pub async fn read_query(request: Request<Body>) -> Result<Vec<u8>, StatusCode> {
+    let query = match *request.method() {
+        Method::GET => {
+            let encoded = request
+                .uri()
+                .query()
+                .unwrap_or_default()
+                .split('&')
+                .find_map(|param| param.strip_prefix("dns="))
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            decode_base64url(encoded).ok_or(StatusCode::BAD_REQUEST)?
+        }
+        Method::POST => {
+            let content_type = request.headers().get(CONTENT_TYPE);
+            if content_type.map(|value| value.as_bytes()) != Some(DNS_MESSAGE.as_bytes()) {
+                return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            }
+            // Read in chunks so that an oversized body is refused without being buffered.
+            let mut body = request.into_body();
+            let mut query = vec![];
+            while let Some(chunk) = body.data().await {
+                query.extend_from_slice(&chunk.map_err(|_| StatusCode::BAD_REQUEST)?);
+                if query.len() > MAX_MESSAGE_SIZE {
+                    return Err(StatusCode::PAYLOAD_TOO_LARGE);
+                }
+            }
+            query
+        }
+        _ => return Err(StatusCode::METHOD_NOT_ALLOWED),
+    };
+
+    match query.len() {
+        0 => Err(StatusCode::BAD_REQUEST),
+        length if length > MAX_MESSAGE_SIZE => Err(StatusCode::PAYLOAD_TOO_LARGE),
+        _ => Ok(query),
+    }
+}
+
+// Query asked by a JSON API request:  name is required,  type is a mnemonic or a number and
+// defaults to A,  cd and do set the CD and DO bits.
+pub fn read_resolve_query(request: &Request<Body>) -> Result<DNSQuery, StatusCode> {
+    if request.method() != Method::GET {
+        return Err(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    let mut qname = None;
+    let mut qtype = QType::A;
+    let mut checking_disabled = false;
+    let mut dnssec_ok = false;
+    for param in request.uri().query().unwrap_or_default().split('&') {
+        let mut key_value = param.splitn(2, '=');
+        let key = key_value.next().unwrap_or_default();
+        let value = key_value.next().unwrap_or_default();
+        match key {
+            "name" => {
+                let name = percent_decode(value).ok_or(StatusCode::BAD_REQUEST)?;
+                if !is_valid_name(&name) {
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+                qname = Some(normalize(&name))
+            }
+            "type" => {
+                qtype = match value.parse::<u16>() {
+                    Ok(code) => QType::decimal_to_qtype(code),
+                    Err(_) => value.parse().map_err(|_| StatusCode::BAD_REQUEST)?,
+                }
+            }
+            "cd" => checking_disabled = is_true(value),
+            "do" => dnssec_ok = is_true(value),
+            _ => {}
+        }
+    }
+    let qname = qname.ok_or(StatusCode::BAD_REQUEST)?;
+
+    Ok(DNSQuery {
+        header: DNSQueryHeaderSection {
+            id: 0,
+            is_query: true,
+            op_code: OpCode::Query,
+            is_authoritative_answer: false,
+            is_truncated: false,
+            is_recursion_desired: true,
+            is_recursion_available: false,
+            is_authentic_data: false,
+            is_checking_disabled: checking_disabled,
+            response_code: ResponseCode::NoError,
+            questions_count: 1,
+            answers_count: 0,
+            ns_rr_count: 0,
+            additional_rr_count: 0,
+        },
+        questions: vec![DNSQuestionQuery {
+            qname,
+            qtype,
+            qclass: QClass::IN,
+        }],
+        additionals: vec![],
+        // Answer goes back over HTTP,  no UDP payload size to stay under.
+        edns: Some(Edns {
+            dnssec_ok,
+            ..Edns::new(u16::MAX)
+        }),
+    })
+}
+
+fn is_true(value: &str) -> bool {
+    value == "1" || value.eq_ignore_ascii_case("true")
+}
+
+// https://tools.ietf.org/html/rfc3986 2.1
+// Given "www%2Eexample.com" return "www.example.com",  None when an escape is cut short or the
+// octets are not UTF-8.
+fn percent_decode(value: &str) -> Option<String> {
+    let mut octets = vec![];
+    let mut bytes = value.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            octets.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            octets.push(byte);
+        }
+    }
+    String::from_utf8(octets).ok()
+}
+
+// https://tools.ietf.org/html/rfc1035 2.3.4
+// Labels are 1 to 63 octets,  names 255 octets in wire format.  Given "." return true,  given
+// "www..example.com" return false.
+fn is_valid_name(name: &str) -> bool {
+    if name == "." {
+        return true;
+    }
+    let name = name.strip_suffix('.').unwrap_or(name);
+    name.len() <= 253
+        && name
+            .split('.')
+            .all(|label| !label.is_empty() && label.len() <= 63)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct JsonResponse<'a> {
+    // RCODE.
+    status: u8,
+    #[serde(rename = "TC")]
+    truncated: bool,
+    #[serde(rename = "RD")]
+    recursion_desired: bool,
+    #[serde(rename = "RA")]
+    recursion_available: bool,
+    #[serde(rename = "AD")]
+    authentic_data: bool,
+    #[serde(rename = "CD")]
+    checking_disabled: bool,
+    question: Vec<JsonQuestion<'a>>,
+    answer: &'a [ResourceRecord],
+    authority: &'a [ResourceRecord],
+    additional: &'a [ResourceRecord],
+}
+
+#[derive(Serialize)]
+struct JsonQuestion<'a> {
+    name: &'a str,
+    #[serde(rename = "type")]
+    qtype: QType,
+}
+
+impl<'a> From<&'a DNSQueryResponse> for JsonResponse<'a> {
+    fn from(response: &'a DNSQueryResponse) -> Self {
+        let header = &response.query.header;
+        Self {
+            status: header.response_code.to_u8(),
+            truncated: header.is_truncated,
+            recursion_desired: header.is_recursion_desired,
+            recursion_available: header.is_recursion_available,
+            authentic_data: header.is_authentic_data,
+            checking_disabled: header.is_checking_disabled,
+            question: response
+                .query
+                .questions
+                .iter()
+                .map(|question| JsonQuestion {
+                    name: &question.qname,
+                    qtype: question.qtype,
+                })
+                .collect(),
+            answer: &response.answers,
+            authority: &response.authority,
+            additional: &response.additional,
+        }
+    }
+}
+
+type HttpsClient = Client<HttpsConnector<HttpConnector<FixedAddr>>>;
+
+// DNS over HTTPS upstreams,  asked with POST.  Each one has a client of its own that keeps its
+// connections open between queries,  over HTTP/2 when the upstream speaks it.
+pub struct HttpsUpstreams {
+    config: Arc<ClientConfig>,
+    clients: Mutex<HashMap<(SocketAddr, String), HttpsClient>>,
+}
+
+impl HttpsUpstreams {
+    pub fn new(mut config: ClientConfig) -> Self {
+        // https://tools.ietf.org/html/rfc8484 5.2
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Self {
+            config: Arc::new(config),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Sends query to peer,  whose certificate must be valid for server_name,  and waits for the
+    // raw answer.
+    pub async fn request(
+        &self,
+        query: &DNSQuery,
+        peer: SocketAddr,
+        server_name: &str,
+        path: &str,
+    ) -> Result<Vec<u8>, Error> {
+        let client = self.client(peer, server_name);
+        let request = Request::post(format!("https://{}:{}{}", server_name, peer.port(), path))
+            .header(CONTENT_TYPE, DNS_MESSAGE)
+            .header(ACCEPT, DNS_MESSAGE)
+            .body(Body::from(query.serialize()))
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+
+        let response = client.request(request).await.map_err(Error::other)?;
+        if response.status() != StatusCode::OK {
+            return Err(Error::other(format!(
+                "{} answered {}",
+                peer,
+                response.status()
+            )));
+        }
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(Error::other)?;
+        Ok(body.to_vec())
+    }
+
+    fn client(&self, peer: SocketAddr, server_name: &str) -> HttpsClient {
+        let mut clients = self.clients.lock().unwrap();
+        clients
+            .entry((peer, server_name.to_string()))
+            .or_insert_with(|| {
+                let mut http = HttpConnector::new_with_resolver(FixedAddr(peer.ip()));
+                http.enforce_http(false);
+                Client::builder().build(HttpsConnector::from((http, self.config.clone())))
+            })
+            .clone()
+    }
+}
+
+// Resolves the name of an upstream to its configured address,  rather than looking it up.
+#[derive(Clone)]
+struct FixedAddr(IpAddr);
+
+impl Service<Name> for FixedAddr {
+    type Response = Once<IpAddr>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _name: Name) -> Self::Future {
+        future::ready(Ok(iter::once(self.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_query, read_resolve_query, HttpsUpstreams, JsonResponse, DNS_MESSAGE};
+    use crate::business::models::{DNSQuery, DNSQueryResponse, QType};
+    use crate::reactor::tls;
+    use hyper::header::CONTENT_TYPE;
+    use hyper::server::conn::Http;
+    use hyper::service::service_fn;
+    use hyper::{Body, Method, Request, Response, StatusCode};
+    use std::convert::Infallible;
+    use std::env;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    // https://tools.ietf.org/html/rfc8484 4.1.1
+    // www.example.com. A,  with ID 0.
+    const QUERY: [u8; 33] = [
+        0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 3, 119, 119, 119, 7, 101, 120, 97, 109, 112, 108, 101,
+        3, 99, 111, 109, 0, 0, 1, 0, 1,
+    ];
+
+    #[tokio::test]
+    async fn test_https_read_query_get() {
+        // Arrange
+        let request = Request::get("/dns-query?dns=AAABAAABAAAAAAAAA3d3dwdleGFtcGxlA2NvbQAAAQAB")
+            .body(Body::empty())
+            .unwrap();
+        let missing = Request::get("/dns-query?ct=1").body(Body::empty()).unwrap();
+        let not_base64url = Request::get("/dns-query?dns=AAAB+AAB")
+            .body(Body::empty())
+            .unwrap();
+
+        // Act
+        let actual = read_query(request).await;
+        let missing = read_query(missing).await;
+        let not_base64url = read_query(not_base64url).await;
+
+        // Assert
+        assert_eq!(actual, Ok(QUERY.to_vec()));
+        assert_eq!(missing, Err(StatusCode::BAD_REQUEST));
+        assert_eq!(not_base64url, Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn test_https_read_query_post() {
+        // Arrange
+        let request = Request::post("/dns-query")
+            .header(CONTENT_TYPE, DNS_MESSAGE)
+            .body(Body::from(QUERY.to_vec()))
+            .unwrap();
+        let json = Request::post("/dns-query")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(QUERY.to_vec()))
+            .unwrap();
+        let oversized = Request::post("/dns-query")
+            .header(CONTENT_TYPE, DNS_MESSAGE)
+            .body(Body::from(vec![0; 70_000]))
+            .unwrap();
+        let put = Request::builder()
+            .method(Method::PUT)
+            .uri("/dns-query")
+            .body(Body::empty())
+            .unwrap();
+
+        // Act
+        let actual = read_query(request).await;
+        let json = read_query(json).await;
+        let oversized = read_query(oversized).await;
+        let put = read_query(put).await;
+
+        // Assert
+        assert_eq!(actual, Ok(QUERY.to_vec()));
+        assert_eq!(json, Err(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+        assert_eq!(oversized, Err(StatusCode::PAYLOAD_TOO_LARGE));
+        assert_eq!(put, Err(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    #[tokio::test]
+    async fn test_https_upstreams_request() {
+        // Arrange
+        let certificate =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let prefix = format!("rrdns-https-upstreams-{}", std::process::id());
+        let certificate_path = env::temp_dir().join(format!("{}.crt", prefix));
+        let private_key_path = env::temp_dir().join(format!("{}.key", prefix));
+        fs::write(&certificate_path, certificate.serialize_pem().unwrap()).unwrap();
+        fs::write(&private_key_path, certificate.serialize_private_key_pem()).unwrap();
+        let mut config = tls::server_config(&certificate_path, &private_key_path).unwrap();
+        config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+        let tls_config = tls::client_config(Some(&certificate_path)).unwrap();
+        fs::remove_file(&certificate_path).unwrap();
+        fs::remove_file(&private_key_path).unwrap();
+
+        // Stand-in upstream that echoes every query at /dns-query back.
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let stream = acceptor.accept(stream).await.unwrap();
+                    let service = service_fn(|request: Request<Body>| async move {
+                        let mut response = Response::new(Body::empty());
+                        if request.uri().path() == "/dns-query" {
+                            *response.body_mut() = Body::from(read_query(request).await.unwrap());
+                        } else {
+                            *response.status_mut() = StatusCode::NOT_FOUND;
+                        }
+                        Ok::<_, Infallible>(response)
+                    });
+                    let _ = Http::new().serve_connection(stream, service).await;
+                });
+            }
+        });
+
+        let upstreams = HttpsUpstreams::new(tls_config);
+        let (query, _) = DNSQuery::deserialize(&QUERY).unwrap();
+
+        // Act
+        let first = upstreams
+            .request(&query, addr, "localhost", "/dns-query")
+            .await;
+        let second = upstreams
+            .request(&query, addr, "localhost", "/dns-query")
+            .await;
+        let wrong_path = upstreams.request(&query, addr, "localhost", "/query").await;
+
+        // Assert
+        assert_eq!(first.unwrap(), QUERY.to_vec());
+        assert_eq!(second.unwrap(), QUERY.to_vec());
+        assert!(wrong_path.is_err());
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_https_read_resolve_query() {
+        // Arrange
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        // Act
+        let defaults = read_resolve_query(&get("/resolve?name=Example.com")).unwrap();
+        let flags =
+            read_resolve_query(&get("/resolve?name=example.com.&type=aaaa&cd=1&do=true")).unwrap();
+        let numeric = read_resolve_query(&get("/resolve?type=65&name=example.com")).unwrap();
+        let no_name = read_resolve_query(&get("/resolve?type=A"));
+        let bad_type = read_resolve_query(&get("/resolve?name=example.com&type=BOGUS"));
+        let encoded = read_resolve_query(&get("/resolve?name=www%2Eexample%2ecom")).unwrap();
+        let root = read_resolve_query(&get("/resolve?name=.")).unwrap();
+        let empty_name = read_resolve_query(&get("/resolve?name="));
+        let empty_label = read_resolve_query(&get("/resolve?name=www..example.com"));
+        let encoded_empty_label = read_resolve_query(&get("/resolve?name=www.%2Eexample.com"));
+        let long_label = format!("/resolve?name={}.com", "a".repeat(64));
+        let long_label = read_resolve_query(&get(&long_label));
+        let longest_label = format!("/resolve?name={}.com", "a".repeat(63));
+        let longest_label = read_resolve_query(&get(&longest_label)).unwrap();
+        let long_name = format!("/resolve?name={}", vec!["a".repeat(63); 4].join("."));
+        let long_name = read_resolve_query(&get(&long_name));
+        let bad_escape = read_resolve_query(&get("/resolve?name=example.com%2"));
+
+        // Assert
+        assert_eq!(defaults.questions[0].qname, "example.com.");
+        assert_eq!(defaults.questions[0].qtype, QType::A);
+        assert!(!defaults.header.is_checking_disabled);
+        assert!(!defaults.edns.unwrap().dnssec_ok);
+        assert_eq!(flags.questions[0].qtype, QType::AAAA);
+        assert!(flags.header.is_checking_disabled);
+        assert!(flags.edns.unwrap().dnssec_ok);
+        assert_eq!(numeric.questions[0].qtype, QType::Unknown(65));
+        assert_eq!(no_name.err(), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(bad_type.err(), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(encoded.questions[0].qname, "www.example.com.");
+        assert_eq!(root.questions[0].qname, ".");
+        assert_eq!(empty_name.err(), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(empty_label.err(), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(encoded_empty_label.err(), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(long_label.err(), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(longest_label.questions[0].qname.len(), 68);
+        assert_eq!(long_name.err(), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(bad_escape.err(), Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn test_https_json_response() {
+        // Arrange
+        // lafolle.ca. 300 A 1.2.3.4,  with RD and RA set.
+        let raw = vec![
+            0, 1, 129, 128, 0, 1, 0, 1, 0, 0, 0, 0, 7, 108, 97, 102, 111, 108, 108, 101, 2, 99, 97,
+            0, 0, 1, 0, 1, 192, 12, 0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 1, 2, 3, 4,
+        ];
+        let response = DNSQueryResponse::deserialize(&raw).unwrap();
+
+        // Act
+        let actual = serde_json::to_value(JsonResponse::from(&response)).unwrap();
+
+        // Assert
+        assert_eq!(actual["Status"], 0);
+        assert_eq!(actual["RD"], true);
+        assert_eq!(actual["RA"], true);
+        assert_eq!(actual["AD"], false);
+        assert_eq!(actual["Question"][0]["name"], "lafolle.ca.");
+        assert_eq!(actual["Question"][0]["type"], "A");
+        assert_eq!(actual["Answer"][0]["ttl"], 300);
+        assert_eq!(actual["Answer"][0]["type"]["A"], "1.2.3.4");
+        assert_eq!(actual["Authority"].as_array().unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,105 @@
+// https://tools.ietf.org/html/rfc1035 4.2.2
+// Messages sent over TCP are prefixed with a two byte length field.
+use crate::business::models::DNSQuery;
+use log::debug;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// Reads one length prefixed message.  Returns None when the peer closed the connection
+// cleanly before sending the length.
+pub async fn read_message<R>(reader: &mut R) -> Result<Option<Vec<u8>>, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut length_buf = [0u8; 2];
+    match reader.read_exact(&mut length_buf).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    let length = u16::from_be_bytes(length_buf) as usize;
+    let mut message = vec![0; length];
+    reader.read_exact(&mut message).await?;
+
+    Ok(Some(message))
+}
+
+pub async fn write_message<W>(writer: &mut W, message: &[u8]) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    if message.len() > u16::MAX as usize {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("message of {} bytes does not fit in a frame", message.len()),
+        ));
+    }
+
+    let mut frame = Vec::with_capacity(message.len() + 2);
+    frame.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    frame.extend_from_slice(message);
+    writer.write_all(&frame).await?;
+    writer.flush().await
+}
+
+// Sends query to peer over a fresh TCP connection and waits for the raw answer.
+pub async fn request(query: &DNSQuery, peer: SocketAddr) -> Result<Vec<u8>, Error> {
+    let mut stream = TcpStream::connect(peer).await?;
+    write_message(&mut stream, &query.serialize()).await?;
+    debug!("{} tcp: query written to addr={}", query.header.id, peer);
+
+    match read_message(&mut stream).await? {
+        Some(raw_response) => Ok(raw_response),
+        None => Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("{} closed the connection without answering", peer),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_message, write_message};
+
+    #[tokio::test]
+    async fn test_tcp_write_then_read_message() {
+        // Arrange
+        let message = vec![1, 2, 3, 4, 5];
+        let mut wire = vec![];
+
+        // Act
+        write_message(&mut wire, &message).await.unwrap();
+        let actual = read_message(&mut wire.as_slice()).await.unwrap();
+
+        // Assert
+        assert_eq!(wire[..2], [0, 5]);
+        assert_eq!(actual, Some(message));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_read_message_closed_connection() {
+        // Arrange
+        let wire: Vec<u8> = vec![];
+
+        // Act
+        let actual = read_message(&mut wire.as_slice()).await.unwrap();
+
+        // Assert
+        assert_eq!(actual, None);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_read_message_short_frame() {
+        // Arrange
+        let wire: Vec<u8> = vec![0, 5, 1, 2];
+
+        // Act
+        let actual = read_message(&mut wire.as_slice()).await;
+
+        // Assert
+        assert!(actual.is_err());
+    }
+}
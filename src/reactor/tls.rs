@@ -0,0 +1,513 @@
+// https://tools.ietf.org/html/rfc7858 3.3
+// DNS over TLS carries the length prefixed messages of DNS over TCP (see tcp.rs) inside a TLS
+// session,  so read_message and write_message work on TLS streams as they are.
+use super::tcp;
+use crate::business::models::DNSQuery;
+use crate::error::TlsConfigError;
+use log::{debug, error, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::timeout;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{ClientConfig, NoClientAuth, ServerConfig};
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::{server, TlsAcceptor, TlsConnector};
+
+type Connection = TlsStream<TcpStream>;
+
+// Idle connections kept open to a single upstream,  enough for the queries usually in flight.
+const MAX_IDLE_CONNECTIONS: usize = 4;
+
+// Server side TLS settings from a PEM certificate chain and its PKCS#8 or RSA private key.
+pub fn server_config(
+    certificate_path: &Path,
+    private_key_path: &Path,
+) -> Result<ServerConfig, TlsConfigError> {
+    let certificates = pemfile::certs(&mut fs::read(certificate_path)?.as_slice())
+        .map_err(|_| TlsConfigError::Certificate(certificate_path.display().to_string()))?;
+    if certificates.is_empty() {
+        return Err(TlsConfigError::Certificate(format!(
+            "no certificate in {}",
+            certificate_path.display()
+        )));
+    }
+
+    let contents = fs::read(private_key_path)?;
+    let mut private_keys = pemfile::pkcs8_private_keys(&mut contents.as_slice())
+        .map_err(|_| TlsConfigError::PrivateKey(private_key_path.display().to_string()))?;
+    if private_keys.is_empty() {
+        private_keys = pemfile::rsa_private_keys(&mut contents.as_slice())
+            .map_err(|_| TlsConfigError::PrivateKey(private_key_path.display().to_string()))?;
+    }
+    let private_key = match private_keys.into_iter().next() {
+        Some(private_key) => private_key,
+        None => {
+            return Err(TlsConfigError::PrivateKey(format!(
+                "no private key in {}",
+                private_key_path.display()
+            )))
+        }
+    };
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(certificates, private_key)
+        .map_err(|err| TlsConfigError::PrivateKey(err.to_string()))?;
+    Ok(config)
+}
+
+// Client side TLS settings that trust the PEM certificates in ca_path,  or the system's roots.
+pub fn client_config(ca_path: Option<&Path>) -> Result<ClientConfig, TlsConfigError> {
+    let mut config = ClientConfig::new();
+    match ca_path {
+        Some(ca_path) => {
+            let certificates = pemfile::certs(&mut fs::read(ca_path)?.as_slice())
+                .map_err(|_| TlsConfigError::Certificate(ca_path.display().to_string()))?;
+            if certificates.is_empty() {
+                return Err(TlsConfigError::Certificate(format!(
+                    "no certificate in {}",
+                    ca_path.display()
+                )));
+            }
+            for certificate in &certificates {
+                config
+                    .root_store
+                    .add(certificate)
+                    .map_err(|err| TlsConfigError::Certificate(format!("{:?}", err)))?;
+            }
+        }
+        None => {
+            config.root_store = match rustls_native_certs::load_native_certs() {
+                Ok(root_store) => root_store,
+                Err((Some(root_store), err)) => {
+                    warn!(
+                        "tls: some system certificates could not be loaded err={}",
+                        err
+                    );
+                    root_store
+                }
+                Err((None, err)) => return Err(TlsConfigError::Io(err)),
+            };
+        }
+    }
+    Ok(config)
+}
+
+// Serves DNS over TLS clients accepted on listener,  answer turns a raw query into the raw
+// response to send back,  if any.  Connections past max_connections are closed right away,  which
+// lets the client try another server instead of waiting.  Connections without a query for
+// idle_timeout are closed.
+pub async fn serve<F, R>(
+    mut listener: TcpListener,
+    acceptor: TlsAcceptor,
+    max_connections: usize,
+    idle_timeout: Duration,
+    answer: F,
+) where
+    F: Fn(Vec<u8>) -> R + Clone + Send + Sync + 'static,
+    R: Future<Output = Option<Vec<u8>>> + Send + 'static,
+{
+    let connections = Arc::new(Semaphore::new(max_connections));
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                error!("tls: accept error={}", err);
+                continue;
+            }
+        };
+        let permit = match connections.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                error!("tls: too many connections,  closing peer={}", peer);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let answer = answer.clone();
+        tokio::spawn(async move {
+            match timeout(idle_timeout, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => serve_connection(stream, peer, idle_timeout, answer).await,
+                Ok(Err(err)) => debug!("tls: handshake error={} peer={}", err, peer),
+                Err(_) => debug!("tls: handshake timeout peer={}", peer),
+            }
+            drop(permit);
+        });
+    }
+}
+
+// https://tools.ietf.org/html/rfc7858 3.3
+// Queries on a connection are answered concurrently,  each response is written as soon as it is
+// ready,  so a slow answer does not hold up the ones pipelined behind it.
+async fn serve_connection<F, R>(
+    stream: server::TlsStream<TcpStream>,
+    peer: SocketAddr,
+    idle_timeout: Duration,
+    answer: F,
+) where
+    F: Fn(Vec<u8>) -> R,
+    R: Future<Output = Option<Vec<u8>>> + Send + 'static,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let (response_tx, mut response_rx) = mpsc::channel::<Vec<u8>>(16);
+
+    let write_responses = tokio::spawn(async move {
+        while let Some(raw_response) = response_rx.recv().await {
+            if let Err(err) = tcp::write_message(&mut writer, &raw_response).await {
+                error!("tls: write error={} peer={}", err, peer);
+                return;
+            }
+        }
+        // Sends close_notify once the queries read have all been answered.
+        if let Err(err) = writer.shutdown().await {
+            debug!("tls: shutdown error={} peer={}", err, peer);
+        }
+    });
+
+    loop {
+        let buf = match timeout(idle_timeout, tcp::read_message(&mut reader)).await {
+            Ok(Ok(Some(buf))) => buf,
+            Ok(Ok(None)) => break,
+            Ok(Err(err)) => {
+                debug!("tls: read error={} peer={}", err, peer);
+                break;
+            }
+            Err(_) => {
+                debug!("tls: idle timeout peer={}", peer);
+                break;
+            }
+        };
+
+        let response = answer(buf);
+        let mut response_tx = response_tx.clone();
+        tokio::spawn(async move {
+            if let Some(raw_response) = response.await {
+                // Writer is gone when the connection broke,  the answer has nowhere to go.
+                let _ = response_tx.send(raw_response).await;
+            }
+        });
+    }
+
+    drop(response_tx);
+    let _ = write_responses.await;
+}
+
+// https://tools.ietf.org/html/rfc7858 3.4
+// Connections to DNS over TLS upstreams.  They are kept open between queries so that not every
+// query pays for a handshake,  one query at a time per connection.
+pub struct TlsUpstreams {
+    connector: TlsConnector,
+    idle: Mutex<HashMap<(SocketAddr, String), Vec<Connection>>>,
+}
+
+impl TlsUpstreams {
+    pub fn new(config: Arc<ClientConfig>) -> Self {
+        Self {
+            connector: TlsConnector::from(config),
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Sends query to peer,  whose certificate must be valid for server_name,  and waits for the
+    // raw answer.
+    pub async fn request(
+        &self,
+        query: &DNSQuery,
+        peer: SocketAddr,
+        server_name: &str,
+    ) -> Result<Vec<u8>, Error> {
+        let key = (peer, server_name.to_string());
+        let message = query.serialize();
+
+        // Upstream may have closed an idle connection since,  a fresh one is opened then.
+        let idle = self.idle.lock().unwrap().get_mut(&key).and_then(Vec::pop);
+        if let Some(mut stream) = idle {
+            match exchange(&mut stream, &message).await {
+                Ok(raw_response) => {
+                    self.release(key, stream);
+                    return Ok(raw_response);
+                }
+                Err(err) => debug!(
+                    "{} tls: idle connection to {} failed err={}",
+                    query.header.id, peer, err
+                ),
+            }
+        }
+
+        let dns_name = DNSNameRef::try_from_ascii_str(server_name).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("not a server name: {}", server_name),
+            )
+        })?;
+        let stream = TcpStream::connect(peer).await?;
+        let mut stream = self.connector.connect(dns_name, stream).await?;
+        debug!("{} tls: connected to {}", query.header.id, peer);
+        let raw_response = exchange(&mut stream, &message).await?;
+        self.release(key, stream);
+        Ok(raw_response)
+    }
+
+    fn release(&self, key: (SocketAddr, String), stream: Connection) {
+        let mut idle = self.idle.lock().unwrap();
+        let streams = idle.entry(key).or_default();
+        if streams.len() < MAX_IDLE_CONNECTIONS {
+            streams.push(stream);
+        }
+    }
+}
+
+async fn exchange(stream: &mut Connection, message: &[u8]) -> Result<Vec<u8>, Error> {
+    tcp::write_message(stream, message).await?;
+    match tcp::read_message(stream).await? {
+        Some(raw_response) => Ok(raw_response),
+        None => Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "connection closed without an answer",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{client_config, serve, server_config, TlsUpstreams};
+    use crate::business::models::DNSQuery;
+    use crate::error::TlsConfigError;
+    use crate::reactor::tcp::{read_message, write_message};
+    use std::env;
+    use std::fs;
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::delay_for;
+    use tokio_rustls::rustls::{Certificate, ClientConfig};
+    use tokio_rustls::webpki::DNSNameRef;
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    // Self-signed certificate for localhost,  written out as PEM files named after test.
+    fn self_signed(test: &str) -> (rcgen::Certificate, PathBuf, PathBuf) {
+        let certificate =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let prefix = format!("rrdns-{}-{}", test, std::process::id());
+        let certificate_path = env::temp_dir().join(format!("{}.crt", prefix));
+        let private_key_path = env::temp_dir().join(format!("{}.key", prefix));
+        fs::write(&certificate_path, certificate.serialize_pem().unwrap()).unwrap();
+        fs::write(&private_key_path, certificate.serialize_private_key_pem()).unwrap();
+        (certificate, certificate_path, private_key_path)
+    }
+
+    // DNS over TLS server echoing every query back,  the ones starting with 1 after a while,  and
+    // a connector trusting its certificate.
+    async fn echo_server(
+        test: &str,
+        max_connections: usize,
+        idle_timeout: Duration,
+    ) -> (SocketAddr, TlsConnector) {
+        let (certificate, certificate_path, private_key_path) = self_signed(test);
+        let config = server_config(&certificate_path, &private_key_path).unwrap();
+        fs::remove_file(&certificate_path).unwrap();
+        fs::remove_file(&private_key_path).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            listener,
+            TlsAcceptor::from(Arc::new(config)),
+            max_connections,
+            idle_timeout,
+            |buf: Vec<u8>| async move {
+                if buf[0] == 1 {
+                    delay_for(Duration::from_millis(200)).await;
+                }
+                Some(buf)
+            },
+        ));
+
+        let mut client_config = ClientConfig::new();
+        client_config
+            .root_store
+            .add(&Certificate(certificate.serialize_der().unwrap()))
+            .unwrap();
+        (addr, TlsConnector::from(Arc::new(client_config)))
+    }
+
+    async fn connect(
+        addr: SocketAddr,
+        connector: &TlsConnector,
+    ) -> std::io::Result<tokio_rustls::client::TlsStream<TcpStream>> {
+        let stream = TcpStream::connect(addr).await?;
+        connector
+            .connect(DNSNameRef::try_from_ascii_str("localhost").unwrap(), stream)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_tls_serve_pipelined_queries() {
+        // Arrange
+        let (addr, connector) = echo_server("pipelined", 4, Duration::from_secs(10)).await;
+        let mut stream = connect(addr, &connector).await.unwrap();
+
+        // Act
+        write_message(&mut stream, &[1, 2, 3]).await.unwrap();
+        write_message(&mut stream, &[4, 5]).await.unwrap();
+        let first = read_message(&mut stream).await.unwrap();
+        let second = read_message(&mut stream).await.unwrap();
+
+        // Assert
+        assert_eq!(
+            first,
+            Some(vec![4, 5]),
+            "slow answer does not hold up the next"
+        );
+        assert_eq!(second, Some(vec![1, 2, 3]));
+    }
+
+    #[tokio::test]
+    async fn test_tls_serve_connection_limit_and_idle_timeout() {
+        // Arrange
+        let (addr, connector) = echo_server("limit", 1, Duration::from_millis(300)).await;
+        let mut stream = connect(addr, &connector).await.unwrap();
+        write_message(&mut stream, &[4, 5]).await.unwrap();
+        assert_eq!(read_message(&mut stream).await.unwrap(), Some(vec![4, 5]));
+
+        // Act
+        let over_limit = connect(addr, &connector).await;
+        let idle = read_message(&mut stream).await;
+        let after_idle = connect(addr, &connector).await;
+
+        // Assert
+        assert!(over_limit.is_err(), "connection past the limit is closed");
+        assert_eq!(idle.unwrap(), None, "idle connection is closed");
+        assert!(after_idle.is_ok(), "closed connection frees its slot");
+    }
+
+    #[tokio::test]
+    async fn test_tls_server_config_round_trip() {
+        // Arrange
+        let (certificate, certificate_path, private_key_path) = self_signed("round-trip");
+        let config = server_config(&certificate_path, &private_key_path).unwrap();
+        fs::remove_file(&certificate_path).unwrap();
+        fs::remove_file(&private_key_path).unwrap();
+
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            while let Some(message) = read_message(&mut stream).await.unwrap() {
+                write_message(&mut stream, &message).await.unwrap();
+            }
+        });
+
+        let mut client_config = ClientConfig::new();
+        client_config
+            .root_store
+            .add(&Certificate(certificate.serialize_der().unwrap()))
+            .unwrap();
+        let connector = TlsConnector::from(Arc::new(client_config));
+
+        // Act
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = connector
+            .connect(DNSNameRef::try_from_ascii_str("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        write_message(&mut stream, &[1, 2, 3]).await.unwrap();
+        write_message(&mut stream, &[4, 5]).await.unwrap();
+        let first = read_message(&mut stream).await.unwrap();
+        let second = read_message(&mut stream).await.unwrap();
+
+        // Assert
+        assert_eq!(first, Some(vec![1, 2, 3]));
+        assert_eq!(second, Some(vec![4, 5]));
+    }
+
+    #[tokio::test]
+    async fn test_tls_upstreams_reuse_connection() {
+        // Arrange
+        let (_, certificate_path, private_key_path) = self_signed("upstreams");
+        let config = server_config(&certificate_path, &private_key_path).unwrap();
+        let tls_config = client_config(Some(&certificate_path)).unwrap();
+        fs::remove_file(&certificate_path).unwrap();
+        fs::remove_file(&private_key_path).unwrap();
+
+        // Stand-in upstream that echoes every query back.
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    if let Ok(mut stream) = acceptor.accept(stream).await {
+                        while let Ok(Some(message)) = read_message(&mut stream).await {
+                            write_message(&mut stream, &message).await.unwrap();
+                        }
+                    }
+                });
+            }
+        });
+
+        let upstreams = TlsUpstreams::new(Arc::new(tls_config));
+        // www.example.com. A
+        let raw_query = [
+            0, 7, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 3, 119, 119, 119, 7, 101, 120, 97, 109, 112, 108,
+            101, 3, 99, 111, 109, 0, 0, 1, 0, 1,
+        ];
+        let (query, _) = DNSQuery::deserialize(&raw_query).unwrap();
+
+        // Act
+        let first = upstreams.request(&query, addr, "localhost").await;
+        let second = upstreams.request(&query, addr, "localhost").await;
+        let wrong_name = upstreams.request(&query, addr, "dns.example").await;
+
+        // Assert
+        assert_eq!(first.unwrap(), raw_query.to_vec());
+        assert_eq!(second.unwrap(), raw_query.to_vec());
+        assert!(wrong_name.is_err(), "certificate is not valid for the name");
+        assert_eq!(
+            connections.load(Ordering::SeqCst),
+            2,
+            "one shared by the localhost queries,  one refused"
+        );
+    }
+
+    #[test]
+    fn test_tls_server_config_bad_files() {
+        // Arrange
+        let (_, certificate_path, private_key_path) = self_signed("bad-files");
+
+        // Act
+        let not_a_key = server_config(&certificate_path, &certificate_path);
+        let not_a_certificate = server_config(&private_key_path, &private_key_path);
+        let missing = server_config(&certificate_path, &private_key_path.with_extension("none"));
+        fs::remove_file(&certificate_path).unwrap();
+        fs::remove_file(&private_key_path).unwrap();
+
+        // Assert
+        assert!(matches!(not_a_key, Err(TlsConfigError::PrivateKey(_))));
+        assert!(matches!(
+            not_a_certificate,
+            Err(TlsConfigError::Certificate(_))
+        ));
+        assert!(matches!(missing, Err(TlsConfigError::Io(_))));
+    }
+}
@@ -0,0 +1,148 @@
+// https://tools.ietf.org/html/rfc5452 9.2
+// Every query goes out from a socket of its own on a random port,  so that an off-path attacker
+// has to guess the port on top of the 16 bit id.
+use log::{debug, error};
+use rand::Rng;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use tokio::net::udp::{RecvHalf, SendHalf};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+
+// Ports below are reserved for well known services.
+const PORT_RANGE_START: u16 = 1024;
+// Random ports can be taken,  after this many the system picks one.
+const BIND_ATTEMPTS: usize = 8;
+
+// Datagram received on the socket bound to port.
+#[derive(Debug)]
+pub struct Datagram {
+    pub port: u16,
+    pub from: SocketAddr,
+    pub data: Vec<u8>,
+}
+
+// Binds a socket on a random port able to reach peer,  addr is used for IPv4 peers.
+async fn bind(addr: IpAddr, peer: &SocketAddr) -> Result<UdpSocket, Error> {
+    let ip = match peer {
+        SocketAddr::V4(_) => addr,
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    for _ in 0..BIND_ATTEMPTS {
+        let port = rand::thread_rng().gen_range(PORT_RANGE_START, u16::MAX);
+        match UdpSocket::bind(SocketAddr::new(ip, port)).await {
+            Ok(socket) => return Ok(socket),
+            Err(err) if err.kind() == ErrorKind::AddrInUse => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    debug!("udp: no free random port after {} attempts", BIND_ATTEMPTS);
+    UdpSocket::bind(SocketAddr::new(ip, 0)).await
+}
+
+// Sends data to peer from a random port.  Returns the port,  the half to read the answer from and
+// the one to send data again from.
+pub async fn send(
+    addr: IpAddr,
+    peer: SocketAddr,
+    data: &[u8],
+) -> Result<(u16, RecvHalf, SendHalf), Error> {
+    let socket = bind(addr, &peer).await?;
+    let port = socket.local_addr()?.port();
+    let (recv_half, mut send_half) = socket.split();
+    send_half.send_to(data, &peer).await?;
+    Ok((port, recv_half, send_half))
+}
+
+// Hands every datagram received on socket to datagrams_tx until cancel_rx is dropped.
+pub async fn receive(
+    mut socket: RecvHalf,
+    port: u16,
+    recv_buffer_size: usize,
+    mut datagrams_tx: Sender<Datagram>,
+    mut cancel_rx: oneshot::Receiver<()>,
+) {
+    let mut buf = vec![0u8; recv_buffer_size];
+    loop {
+        tokio::select! {
+            result = socket.recv_from(&mut buf) => match result {
+                Ok((read_bytes_count, from)) => {
+                    let datagram = Datagram {
+                        port,
+                        from,
+                        data: buf[..read_bytes_count].to_vec(),
+                    };
+                    if datagrams_tx.send(datagram).await.is_err() {
+                        return;
+                    }
+                }
+                Err(err) => {
+                    error!("udp: recv error={} port={}", err, port);
+                    return;
+                }
+            },
+            _ = &mut cancel_rx => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bind, receive, send, Datagram};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use tokio::net::UdpSocket;
+    use tokio::sync::{mpsc, oneshot};
+
+    #[tokio::test]
+    async fn test_udp_bind_random_ports() {
+        // Arrange
+        let addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let peer: SocketAddr = "192.0.2.1:53".parse().unwrap();
+
+        // Act
+        let mut ports = vec![];
+        for _ in 0..8 {
+            ports.push(
+                bind(addr, &peer)
+                    .await
+                    .unwrap()
+                    .local_addr()
+                    .unwrap()
+                    .port(),
+            );
+        }
+
+        // Assert
+        assert!(ports.iter().all(|port| *port >= 1024));
+        ports.dedup();
+        assert!(ports.len() > 1);
+    }
+
+    #[tokio::test]
+    async fn test_udp_send_receive() {
+        // Arrange
+        let addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let mut peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        let (datagrams_tx, mut datagrams_rx) = mpsc::channel(1);
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+
+        // Act
+        let (port, recv_half, _send_half) = send(addr, peer_addr, &[1, 2]).await.unwrap();
+        let receiving = tokio::spawn(receive(recv_half, port, 512, datagrams_tx, cancel_rx));
+        let mut buf = [0u8; 512];
+        let (read_bytes_count, from) = peer.recv_from(&mut buf).await.unwrap();
+        peer.send_to(&[3, 4], from).await.unwrap();
+        let datagram: Datagram = datagrams_rx.recv().await.unwrap();
+        drop(cancel_tx);
+
+        // Assert
+        assert_eq!(&buf[..read_bytes_count], &[1, 2]);
+        assert_eq!(from.port(), port);
+        assert_eq!(datagram.port, port);
+        assert_eq!(datagram.from, peer_addr);
+        assert_eq!(datagram.data, vec![3, 4]);
+        receiving.await.unwrap();
+    }
+}
@@ -1,51 +1,139 @@
 use crate::business::models::{
-    DNSQuery, DNSQueryHeaderSection, DNSQueryResponse, DNSQuestionQuery, OpCode, QClass, QType,
-    RRSet, ResponseCode, Type,
+    DNSQuery, DNSQueryHeaderSection, DNSQueryResponse, DNSQuestionQuery, DSData, Edns, OpCode,
+    QClass, QType, RRSet, ResponseCode, Type,
 };
+use crate::config::{Config, ForwardTransport, Forwarder};
 use itertools::{all, any};
 use log::{debug, error, info, log_enabled, Level};
 use rand::prelude::*;
+use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
+use tokio::time::timeout;
 
 pub mod cache;
 use crate::business::models::ResourceRecord;
 use crate::error::FetchError;
 use crate::reactor::cmd::ReactorQuery;
-use crate::reactor::Reactor;
+use crate::reactor::{tls, Reactor};
 use async_recursion::async_recursion;
-use cache::{Cache, InMemoryCache, Store};
+use cache::{snapshot, Cache, InMemoryCache, NegativeCacheEntry, Store};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use std::path::Path;
 use tokio::sync::mpsc::Sender;
+use tokio_rustls::rustls::ClientConfig;
 
-mod zone;
+mod dnssec;
+use dnssec::ZoneKeys;
+
+pub mod master;
+
+mod authoritative;
+use authoritative::{Zone, Zones};
+
+pub mod zone;
 use zone::parent_zone;
 
+mod upstream;
+use upstream::UpstreamPool;
+
+mod infra;
+use infra::{interleave_families, is_lame_referral, InfraCache, Outcome};
+
+mod routes;
+use routes::{Route, ZoneRoutes};
+
 // https://tools.ietf.org/html/rfc1034 5
-// Resolver is not thread safe and needs to be accessed via mutext because
-// firstly, a socket is not thread safe and multiple threads writing to socket
-// would corrupt the kernet buffer and secondly cache is not thead safe as well.
+// Resolver is shared by every task answering queries:  sockets are owned by the reactor and the
+// cache locks its shards on its own.
 pub struct Resolver {
     reactor_tx: Sender<ReactorQuery>,
-    cache: Arc<Mutex<dyn Cache + Send>>,
+    cache: Arc<dyn Cache>,
+    edns_udp_payload_size: u16,
+    dnssec_validation: bool,
+    root_trust_anchors: Vec<DSData>,
+    // Outcome of walking the chain of trust down to a name,  until it expires.
+    zone_keys: Mutex<HashMap<String, (ZoneKeys, Instant)>>,
+    // Set in forwarding mode,  queries then go to these instead of being resolved from the root.
+    upstreams: Option<UpstreamPool>,
+    // Zones resolved by their own servers,  consulted before upstreams and the root.
+    routes: ZoneRoutes,
+    // Zones answered from their master files,  before anything else.
+    zones: Zones,
+    // How fast authorities answer,  and which are held down.
+    infra: InfraCache,
+    // Set when servers are asked in parallel,  see request_addrs_staggered.
+    query_stagger: Option<Duration>,
+    // Port name servers found by their NS records are asked on,  only tests change it.
+    authority_port: u16,
 }
 
 impl Resolver {
-    pub fn new() -> Self {
+    pub fn new(config: &Config) -> Self {
         let reactor_addr = "0.0.0.0";
-        let reactor_tx = Reactor::new(reactor_addr);
+        let tls_config = if config.forwarders.iter().any(Forwarder::is_encrypted) {
+            tls::client_config(config.forward_ca_path.as_deref())
+                .unwrap_or_else(|err| panic!("loading forwarder certificates: {}", err))
+        } else {
+            ClientConfig::new()
+        };
+        let reactor_tx = Reactor::spawn(
+            reactor_addr,
+            config.edns_udp_payload_size as usize,
+            tls_config,
+            config.qname_case_randomization,
+        );
 
         Self {
             reactor_tx,
-            cache: Arc::new(Mutex::new(InMemoryCache::new())),
+            cache: Arc::new(Resolver::warm_cache(config)),
+            edns_udp_payload_size: config.edns_udp_payload_size,
+            dnssec_validation: config.dnssec_validation,
+            root_trust_anchors: config.root_trust_anchors.clone(),
+            zone_keys: Mutex::new(HashMap::new()),
+            upstreams: if config.forwarders.is_empty() {
+                None
+            } else {
+                Some(UpstreamPool::new(
+                    &config.forwarders,
+                    config.forward_policy.clone(),
+                ))
+            },
+            routes: ZoneRoutes::new(&config.zone_routes, &config.forward_policy),
+            zones: Resolver::load_zones(config),
+            infra: InfraCache::new(),
+            query_stagger: config.query_stagger,
+            authority_port: 53,
         }
     }
 
-    #[async_recursion]
+    // https://tools.ietf.org/html/rfc4035 3.2.2
+    // Clients setting CD get the answer unvalidated.
     pub async fn resolve(&self, query: &DNSQuery) -> Result<DNSQueryResponse, FetchError> {
+        let result = self.lookup(query).await;
+        if !self.dnssec_validation || query.header.is_checking_disabled {
+            return result;
+        }
+        // https://tools.ietf.org/html/rfc7646 3
+        // Routed and local zones are usually internal ones,  not part of the tree signed from the
+        // root.
+        let qname = &query.questions[0].qname;
+        if self.routes.find(qname).is_some() || self.zones.find(qname).is_some() {
+            return result;
+        }
+        self.validate(query, result).await
+    }
+
+    #[async_recursion]
+    async fn lookup(&self, query: &DNSQuery) -> Result<DNSQueryResponse, FetchError> {
         let qtype = &query.questions[0].qtype;
         let qname = &query.questions[0].qname;
         info!("{} resolve: {} {:#?}", query.header.id, qname, qtype);
+        if let Some(zone) = self.zones.find(qname) {
+            return self.resolve_from_zone(query, zone).await;
+        }
         if let Some(response) = self.resolve_from_cache(query) {
             info!(
                 "{} resolve:in_cache: {} {:?}",
@@ -57,12 +145,29 @@ impl Resolver {
             "{} resolve:not_in_cache: {} {:?}",
             query.header.id, qname, qtype
         );
+        match self.routes.find(qname) {
+            Some(Route::Forward(upstreams)) => {
+                return self.resolve_from_upstreams(query, upstreams).await;
+            }
+            // Stub zones are walked like any other,  from the servers they were given,  see
+            // resolve_from_name_servers.
+            Some(Route::Stub(_)) => {}
+            None => {
+                if let Some(upstreams) = &self.upstreams {
+                    return self.resolve_from_upstreams(query, upstreams).await;
+                }
+            }
+        }
         let mut result = self.resolve_from_name_servers(query).await?;
 
         // Resolve cnames.
         let result = match result.contains_cnames() {
             Some(cname_records) => {
                 let mut cnames = vec![];
+                // https://tools.ietf.org/html/rfc4035 5.4
+                // Denials for the targets are kept,  validation needs them.
+                let mut denials = vec![];
+                let mut is_name_error = false;
                 for rr in &cname_records {
                     match &rr.r#type {
                         Type::CNAME(cname) => {
@@ -70,9 +175,24 @@ impl Resolver {
                             if !cname.ends_with(".") {
                                 cname = format!("{}.", cname);
                             }
-                            let query = self.build_query(cname.to_string(), QType::A);
-                            match self.resolve(&query).await {
-                                Ok(mut cname_result) => cnames.append(&mut cname_result.answers),
+                            let query = self.build_query(cname.to_string(), *qtype);
+                            match self.lookup(&query).await {
+                                Ok(mut cname_result) => {
+                                    if cname_result.answers.is_empty() {
+                                        denials.append(&mut cname_result.authority);
+                                    }
+                                    cnames.append(&mut cname_result.answers)
+                                }
+                                // https://tools.ietf.org/html/rfc6604 3
+                                // The response code is the one of the last name in the chain.
+                                Err(FetchError::QueryError(mut cname_result))
+                                    if cname_result.query.header.response_code
+                                        == ResponseCode::NameError =>
+                                {
+                                    is_name_error = true;
+                                    denials.append(&mut cname_result.authority);
+                                    cnames.append(&mut cname_result.answers);
+                                }
                                 Err(err) => error!(
                                     "resolving cname result for domain={} err={:?} dig={}",
                                     cname,
@@ -88,6 +208,12 @@ impl Resolver {
                 }
                 result.query.header.answers_count += cnames.len() as u16;
                 result.answers.append(&mut cnames);
+                result.query.header.ns_rr_count += denials.len() as u16;
+                result.authority.append(&mut denials);
+                if is_name_error {
+                    result.query.header.response_code = ResponseCode::NameError;
+                    return Err(FetchError::QueryError(result));
+                }
                 result
             }
             None => result,
@@ -99,8 +225,14 @@ impl Resolver {
     fn resolve_from_cache(&self, query: &DNSQuery) -> Option<Result<DNSQueryResponse, FetchError>> {
         let domain = &query.questions[0].qname;
         let qtype = &query.questions[0].qtype;
-        let mut cache = self.cache.lock().unwrap();
-        if let Some(answers) = cache.get(domain, qtype) {
+        if let Some(mut answers) = self.cache.get(domain, qtype) {
+            // Signatures are needed to validate the answer again.
+            if let Some(rrsigs) = self.cache.get(domain, &QType::RRSIG) {
+                answers.extend(rrsigs.into_iter().filter(|rr| match &rr.r#type {
+                    Type::RRSIG(rrsig) => rrsig.type_covered == *qtype,
+                    _ => false,
+                }));
+            }
             let mut query_of_response = query.clone();
             query_of_response.header.is_query = false;
             query_of_response.header.answers_count = answers.len() as u16;
@@ -112,21 +244,136 @@ impl Resolver {
                 additional: vec![],
             }));
         }
+        // https://tools.ietf.org/html/rfc2308 6
+        if let Some(entry) = self.cache.get_negative(domain, qtype) {
+            let authority = entry.remaining_authority();
+            let mut query_of_response = query.clone();
+            query_of_response.header.is_query = false;
+            query_of_response.header.response_code = entry.response_code.clone();
+            query_of_response.header.answers_count = 0;
+            query_of_response.header.ns_rr_count = authority.len() as u16;
+            query_of_response.header.is_authoritative_answer = false;
+            let response = DNSQueryResponse {
+                query: query_of_response,
+                answers: vec![],
+                authority,
+                additional: vec![],
+            };
+            if entry.response_code == ResponseCode::NoError {
+                return Some(Ok(response));
+            }
+            return Some(Err(FetchError::QueryError(response)));
+        }
         None
     }
 
+    // https://tools.ietf.org/html/rfc1034 4.3.2
+    // Referrals to child zones are followed for clients asking for recursion.
+    async fn resolve_from_zone(
+        &self,
+        query: &DNSQuery,
+        zone: &Zone,
+    ) -> Result<DNSQueryResponse, FetchError> {
+        let response = zone.answer(query);
+        info!(
+            "{} resolve:in_zone: {} {:?}",
+            query.header.id,
+            zone.origin(),
+            response.query.header.response_code
+        );
+        if response.query.header.response_code == ResponseCode::NameError {
+            return Err(FetchError::QueryError(response));
+        }
+        if !response.query.header.is_authoritative_answer && query.header.is_recursion_desired {
+            response
+                .additional
+                .iter()
+                .for_each(|rr| self.cache.insert2(rr));
+            return self
+                .resolve_from_authority(query, &response.authority)
+                .await;
+        }
+        Ok(response)
+    }
+
+    // Upstreams are recursive resolvers,  they follow CNAMEs themselves.
+    async fn resolve_from_upstreams(
+        &self,
+        query: &DNSQuery,
+        upstreams: &UpstreamPool,
+    ) -> Result<DNSQueryResponse, FetchError> {
+        let mut query = query.clone();
+        query.header.is_recursion_desired = true;
+        let mut last_err = FetchError::NoIPError(format!("{} no upstreams", query.header.id));
+        for upstream in upstreams.ordered(&self.infra) {
+            // Fresh id per upstream,  a late answer from the previous one must not be taken for
+            // this one's.
+            query.header.id = random();
+            let addrs = [upstream.addr];
+            let request = self.request_addrs(&query, &addrs, &upstream.transport, None);
+            // The reactor gives up on the upstream after its retransmissions or TCP_TIMEOUT and
+            // answers with FetchError::Timeout.
+            match request.await {
+                Ok(response) => return Ok(response),
+                // SERVFAIL and REFUSED say more about the upstream than about the name.
+                Err(FetchError::QueryError(response))
+                    if !matches!(
+                        response.query.header.response_code,
+                        ResponseCode::ServerFailure | ResponseCode::Refused
+                    ) =>
+                {
+                    return Err(FetchError::QueryError(response));
+                }
+                Err(err) => {
+                    info!(
+                        "{} upstream {} failed err={:?}",
+                        query.header.id, upstream, err
+                    );
+                    last_err = err;
+                }
+            }
+        }
+        Err(last_err)
+    }
+
     async fn resolve_from_name_servers(
         &self,
         query: &DNSQuery,
     ) -> Result<DNSQueryResponse, FetchError> {
-        let domain = &query.questions[0].qname;
+        // https://tools.ietf.org/html/rfc4035 3.1.4.1
+        // DS lives on the parent side of the zone cut.
+        let domain = &if query.questions[0].qtype == QType::DS && query.questions[0].qname != "." {
+            parent_zone(&query.questions[0].qname)
+        } else {
+            query.questions[0].qname.clone()
+        };
+        // https://tools.ietf.org/html/rfc7646 2.2
+        // Name servers of a stub zone are configured,  not looked up from the parent.
+        if let Some((zone, Route::Stub(servers))) = self.routes.find_zone(domain) {
+            let mut query = query.clone();
+            query.header.is_recursion_desired = false;
+            let response = self
+                .request_addrs(&query, servers, &ForwardTransport::Udp, Some(zone))
+                .await?;
+            // https://tools.ietf.org/html/rfc1034 4.3.2
+            // Zones delegated from the stub zone are walked from the referral,  its glue was
+            // cached with it.  Referrals back up were taken for lame by request_addrs.
+            if !response.query.header.is_authoritative_answer
+                && response.answers.is_empty()
+                && any(&response.authority, |rr| rr.r#type.to_qtype() == QType::NS)
+            {
+                return self
+                    .resolve_from_authority(&query, &without_dnssec_records(response.authority))
+                    .await;
+            }
+            return Ok(response);
+        }
         let (name_servers, is_grand_parent_ns) = self.fetch_name_servers(domain).await?;
         if is_grand_parent_ns {
             name_servers.iter().for_each(|rr| {
                 let mut new_rr = rr.clone();
                 new_rr.name = domain.to_string();
-                let mut cache = self.cache.lock().unwrap();
-                cache.insert2(&new_rr);
+                self.cache.insert2(&new_rr);
             });
         }
         if query.questions[0].qtype == QType::NS {
@@ -144,13 +391,13 @@ impl Resolver {
         self.resolve_from_authority(&query, &name_servers).await
     }
 
+    // https://tools.ietf.org/html/rfc9156 2
+    // Walks down from the root one label at a time:  servers of a zone are only asked for NS of
+    // the name one label below it,  the full query name only goes to the servers of its own zone.
     async fn fetch_name_servers(&self, domain: &str) -> Result<(RRSet, bool), FetchError> {
-        {
-            let mut cache = self.cache.lock().unwrap();
-            if let Some(name_servers) = cache.get(&domain, &QType::NS) {
-                info!("fetch_name_servers:in_cache: {}", domain);
-                return Ok((name_servers, false));
-            }
+        if let Some(name_servers) = self.cache.get(&domain, &QType::NS) {
+            info!("fetch_name_servers:in_cache: {}", domain);
+            return Ok((name_servers, false));
         }
         info!(
             "fetch_name_servers:not_in_cache: {} {:?}",
@@ -159,13 +406,41 @@ impl Resolver {
         );
 
         // Ask parent name servers for NS of "domain".
-        let parent_zone = parent_zone(domain);
-        let parent_ns_query = self.build_query(parent_zone, QType::NS);
-        let parent_ns_query_response = self.resolve(&parent_ns_query).await?;
+        let parent = parent_zone(domain);
+        let was_nonexistent = self.is_nonexistent(&parent);
+        let parent_ns_query = self.build_query(parent.clone(), QType::NS);
+        let parent_ns_query_response = match self.lookup(&parent_ns_query).await {
+            Ok(response) => response,
+            // https://tools.ietf.org/html/rfc9156 2.3
+            // Some servers answer NXDOMAIN or an error for empty non-terminals,  names with
+            // nothing but names below them.  Rather than failing,  the servers one level up are
+            // asked about "domain" itself,  once.  NXDOMAIN known from before,  or known for a
+            // name higher up,  is no such error:  nothing exists below it.
+            Err(FetchError::QueryError(response))
+                if parent != "."
+                    && !was_nonexistent
+                    && !self.is_nonexistent(&parent_zone(&parent))
+                    && matches!(
+                        response.query.header.response_code,
+                        ResponseCode::NameError
+                            | ResponseCode::ServerFailure
+                            | ResponseCode::Refused
+                    ) =>
+            {
+                let grand_parent = parent_zone(&parent);
+                info!(
+                    "fetch_name_servers:relaxed: {} {:?} for {}, asking {}",
+                    parent, response.query.header.response_code, domain, grand_parent
+                );
+                let grand_parent_ns_query = self.build_query(grand_parent, QType::NS);
+                self.lookup(&grand_parent_ns_query).await?
+            }
+            Err(err) => return Err(err),
+        };
         let parent_ns_records = if parent_ns_query_response.answers.len() > 0 {
-            parent_ns_query_response.answers
+            without_dnssec_records(parent_ns_query_response.answers)
         } else {
-            parent_ns_query_response.authority
+            without_dnssec_records(parent_ns_query_response.authority)
         };
 
         // TODO: Detect infinite recursion.  "dig @localhost bbc.com" triggers this,  specific
@@ -192,7 +467,9 @@ impl Resolver {
             .resolve_from_authority(&ns_query, &parent_ns_records)
             .await
         {
-            Ok(response) => {
+            Ok(mut response) => {
+                response.answers = without_dnssec_records(response.answers);
+                response.authority = without_dnssec_records(response.authority);
                 if response.answers.len() > 0 {
                     if all(response.answers.iter(), |rr| {
                         rr.r#type.to_qtype() == QType::NS
@@ -238,63 +515,67 @@ impl Resolver {
                 vec![]
             }
         );
-        // Get A record for authority server and make request.
+        // Zone the servers are authorities of,  lame ones refer back up from it.
+        let zone = ns_records
+            .iter()
+            .find(|rr| matches!(rr.r#type, Type::NS(_)))
+            .map(|rr| rr.name.as_str());
+        // Addresses of every name server in cache are asked together,  the fastest first.
         // Sometimes only subset of ns_records' A address will be in cache
+        let mut ip_records = vec![];
         for authority_server_record in ns_records {
             if let Type::NS(name_server) = &authority_server_record.r#type {
-                let ip_records = {
-                    let mut cache = self.cache.lock().unwrap();
-                    if let Some(ip4s) = cache.get(&name_server, &QType::A) {
-                        ip4s
-                    } else if let Some(ip6s) = cache.get(&name_server, &QType::AAAA) {
-                        ip6s
-                    } else {
-                        vec![]
+                ip_records.extend(self.cache.get(name_server, &QType::A).unwrap_or_default());
+                ip_records.extend(
+                    self.cache
+                        .get(name_server, &QType::AAAA)
+                        .unwrap_or_default(),
+                );
+            }
+        }
+        if !ip_records.is_empty() {
+            match self.request(&query, ip_records, zone).await {
+                Ok(response) => return Ok(response),
+                Err(err) => match err {
+                    FetchError::QueryError(err) => return Err(FetchError::QueryError(err)),
+                    FetchError::NetworkError(_err) => {}
+                    FetchError::ParseError(_err) => {}
+                    FetchError::Timeout(_err) => {}
+                    FetchError::InfiniteRecursionError(err) => {
+                        return Err(FetchError::InfiniteRecursionError(err))
                     }
-                };
-                if ip_records.len() == 0 {
-                    continue;
-                }
-                match self.request(&query, ip_records).await {
-                    Ok(response) => return Ok(response),
-                    Err(err) => match err {
-                        FetchError::QueryError(err) => return Err(FetchError::QueryError(err)),
-                        FetchError::NetworkError(_err) => continue,
-                        FetchError::InfiniteRecursionError(err) => {
-                            return Err(FetchError::InfiniteRecursionError(err))
-                        }
-                        FetchError::NoIPError(err) => return Err(FetchError::NoIPError(err)),
-                    },
-                };
-                //}
+                    // None of them answered,  the servers not in cache may.
+                    FetchError::NoIPError(_err) => {}
+                },
             };
         }
 
-        info!("no A/AAAA of NSs in cache, trying spawning query for them");
+        info!("no answer from NSs in cache, trying spawning query for the others");
         for authority_server_record in ns_records {
             // Build query to get A/AAAA record for NS.
             if let Type::NS(name_server) = &authority_server_record.r#type {
+                // Those were asked above.
+                if self.cache.get(name_server, &QType::A).is_some()
+                    || self.cache.get(name_server, &QType::AAAA).is_some()
+                {
+                    continue;
+                }
                 let a_query = self.build_query(name_server.clone(), QType::A);
-                if let Ok(_) = self.resolve(&a_query).await {
-                    let a_records;
-                    {
-                        let mut cache = self.cache.lock().unwrap();
-                        a_records = cache.get(&name_server, &QType::A);
-                    }
-                    if let Some(ip_records) = a_records {
-                        match self.request(&query, ip_records).await {
+                if let Ok(_) = self.lookup(&a_query).await {
+                    if let Some(ip_records) = self.cache.get(&name_server, &QType::A) {
+                        match self.request(&query, ip_records, zone).await {
                             Ok(response) => return Ok(response),
                             Err(err) => match err {
                                 FetchError::QueryError(err) => {
                                     return Err(FetchError::QueryError(err))
                                 }
                                 FetchError::NetworkError(_err) => continue,
+                                FetchError::ParseError(_err) => continue,
+                                FetchError::Timeout(_err) => continue,
                                 FetchError::InfiniteRecursionError(err) => {
                                     return Err(FetchError::InfiniteRecursionError(err));
                                 }
-                                FetchError::NoIPError(err) => {
-                                    return Err(FetchError::NoIPError(err))
-                                }
+                                FetchError::NoIPError(_err) => continue,
                             },
                         };
                     }
@@ -310,84 +591,297 @@ impl Resolver {
         )))
     }
 
+    // Authorities of zone are asked by how fast they answered before,  see InfraCache::order.
     async fn request(
         &self,
         query: &DNSQuery,
         ip_records: Vec<ResourceRecord>,
+        zone: Option<&str>,
     ) -> Result<DNSQueryResponse, FetchError> {
-        for rr in &ip_records {
-            let socket_server_addr: SocketAddr = match rr.r#type {
-                Type::A(ip4) => SocketAddr::new(IpAddr::V4(ip4), 53),
-                Type::AAAA(ip6) => SocketAddr::new(IpAddr::V6(ip6), 53),
+        let server_addrs: Vec<SocketAddr> = ip_records
+            .iter()
+            .map(|rr| match rr.r#type {
+                Type::A(ip4) => SocketAddr::new(IpAddr::V4(ip4), self.authority_port),
+                Type::AAAA(ip6) => SocketAddr::new(IpAddr::V6(ip6), self.authority_port),
                 _ => panic!(
                     "{} request: expected A or AAAA got {}",
                     query.header.id,
                     rr.r#type.to_qtype()
                 ),
-            };
-
-            let (tx_oneshot, rx_oneshot) = oneshot::channel();
-            let mut reactor_tx = self.reactor_tx.clone();
+            })
+            .collect();
+        let server_addrs = self.infra.order(&server_addrs, zone);
+        self.request_addrs(query, &server_addrs, &ForwardTransport::Udp, zone)
+            .await
+    }
 
-            let reactor_query = ReactorQuery {
-                query: query.clone(),
-                peer_addr: socket_server_addr,
-                respond_tx: tx_oneshot,
-            };
+    // Asks the servers one after the other until one answers,  or all at once staggered by
+    // query_stagger.  zone is the one the servers are authorities of,  None for upstreams.
+    async fn request_addrs(
+        &self,
+        query: &DNSQuery,
+        server_addrs: &[SocketAddr],
+        transport: &ForwardTransport,
+        zone: Option<&str>,
+    ) -> Result<DNSQueryResponse, FetchError> {
+        if let Some(stagger) = self.query_stagger {
+            if server_addrs.len() > 1 {
+                return self
+                    .request_addrs_staggered(query, server_addrs, transport, zone, stagger)
+                    .await;
+            }
+        }
+        let mut failures = Failures::default();
+        for socket_server_addr in server_addrs.iter().copied() {
+            match self
+                .request_addr(query, socket_server_addr, transport, zone)
+                .await
+            {
+                Attempt::Answered(response) => return Ok(response),
+                Attempt::Failed(err) => failures.add(err),
+                Attempt::Error(err) => return Err(err),
+            }
+        }
+        Err(failures.into_error(query, server_addrs.len()))
+    }
 
-            match reactor_tx.send(reactor_query).await {
-                Ok(_) => {
-                    match rx_oneshot.await {
-                        Ok(reactor_response_result) => {
-                            match reactor_response_result {
-                                Ok(reactor_response) => {
-                                    // Update cache.
-                                    self.update_cache(&reactor_response.response);
-                                    return Ok(reactor_response.response);
-                                }
-                                Err(err) => {
-                                    match err {
-                                        FetchError::NetworkError(err) => {
-                                            info!(
-                                                "{} NetworkError={} trying another ip",
-                                                query.header.id, err
-                                            );
-                                            continue;
-                                        }
-                                        FetchError::QueryError(err) => {
-                                            return Err(FetchError::QueryError(err));
-                                        }
-                                        FetchError::InfiniteRecursionError(err) => {
-                                            return Err(FetchError::InfiniteRecursionError(err));
-                                        }
-                                        FetchError::NoIPError(err) => {
-                                            return Err(FetchError::NoIPError(err));
-                                        }
-                                    };
-                                }
-                            }
-                        }
-                        Err(err) => {
-                            error!("{} Failed to receive response on oneshot channel from reactor: err={}", query.header.id, err);
+    // https://tools.ietf.org/html/rfc8305 5
+    // The next server is asked when the previous one failed or stagger passed without an answer,
+    // while the earlier ones are still waited for.  The first answer wins,  dropping the other
+    // queries cancels them.  Address families alternate so that a broken one costs at most every
+    // other stagger.
+    async fn request_addrs_staggered(
+        &self,
+        query: &DNSQuery,
+        server_addrs: &[SocketAddr],
+        transport: &ForwardTransport,
+        zone: Option<&str>,
+        stagger: Duration,
+    ) -> Result<DNSQueryResponse, FetchError> {
+        let interleaved = interleave_families(server_addrs);
+        let mut next_addrs = interleaved.iter().copied();
+        let mut pending = FuturesUnordered::new();
+        let mut failures = Failures::default();
+        loop {
+            if pending.is_empty() {
+                match next_addrs.next() {
+                    Some(addr) => pending.push(self.request_addr(query, addr, transport, zone)),
+                    None => break,
+                }
+            }
+            let attempt = if next_addrs.len() > 0 {
+                match timeout(stagger, pending.next()).await {
+                    Ok(attempt) => attempt,
+                    Err(_elapsed) => {
+                        if let Some(addr) = next_addrs.next() {
+                            debug!(
+                                "{} no answer after {:?} also asking {}",
+                                query.header.id, stagger, addr
+                            );
+                            pending.push(self.request_addr(query, addr, transport, zone));
                         }
+                        continue;
+                    }
+                }
+            } else {
+                pending.next().await
+            };
+            match attempt {
+                Some(Attempt::Answered(response)) => return Ok(response),
+                Some(Attempt::Failed(err)) => {
+                    failures.add(err);
+                    // No point waiting out the stagger for a server that already failed.
+                    if let Some(addr) = next_addrs.next() {
+                        pending.push(self.request_addr(query, addr, transport, zone));
                     }
                 }
-                Err(err) => error!(
-                    "{} unable to send to reactor error={}",
+                Some(Attempt::Error(err)) => return Err(err),
+                None => {}
+            }
+        }
+        Err(failures.into_error(query, server_addrs.len()))
+    }
+
+    // Asks one server,  telling the infrastructure cache how it went.
+    async fn request_addr(
+        &self,
+        query: &DNSQuery,
+        socket_server_addr: SocketAddr,
+        transport: &ForwardTransport,
+        zone: Option<&str>,
+    ) -> Attempt {
+        let (tx_oneshot, rx_oneshot) = oneshot::channel();
+        let mut reactor_tx = self.reactor_tx.clone();
+
+        // Advertise our buffer size so that authorities do not truncate needlessly.
+        let mut upstream_query = query.clone();
+        let mut edns = Edns::new(self.edns_udp_payload_size);
+        edns.dnssec_ok = self.dnssec_validation;
+        upstream_query.edns = Some(edns);
+
+        let reactor_query = ReactorQuery {
+            query: upstream_query,
+            peer_addr: socket_server_addr,
+            transport: transport.clone(),
+            respond_tx: tx_oneshot,
+        };
+
+        let started_at = Instant::now();
+        if let Err(err) = reactor_tx.send(reactor_query).await {
+            error!(
+                "{} unable to send to reactor error={}",
+                query.header.id, err
+            );
+            return Attempt::Failed(None);
+        }
+        let reactor_response_result = match rx_oneshot.await {
+            Ok(reactor_response_result) => reactor_response_result,
+            Err(err) => {
+                error!(
+                    "{} Failed to receive response on oneshot channel from reactor: err={}",
                     query.header.id, err
+                );
+                return Attempt::Failed(None);
+            }
+        };
+        match reactor_response_result {
+            Ok(reactor_response) => {
+                if zone.is_some_and(|zone| is_lame_referral(&reactor_response.response, zone)) {
+                    info!(
+                        "{} lame referral from {} trying another ip",
+                        query.header.id, socket_server_addr
+                    );
+                    self.infra.report(&socket_server_addr, zone, Outcome::Lame);
+                    return Attempt::Failed(None);
+                }
+                self.infra.report(
+                    &socket_server_addr,
+                    zone,
+                    Outcome::Answered(started_at.elapsed()),
+                );
+                // Update cache.
+                self.update_cache(&reactor_response.response);
+                Attempt::Answered(reactor_response.response)
+            }
+            Err(err) => match err {
+                FetchError::NetworkError(err) => {
+                    info!("{} NetworkError={} trying another ip", query.header.id, err);
+                    self.infra
+                        .report(&socket_server_addr, zone, Outcome::Failed);
+                    Attempt::Failed(None)
+                }
+                FetchError::ParseError(err) => {
+                    info!("{} ParseError={} trying another ip", query.header.id, err);
+                    self.infra
+                        .report(&socket_server_addr, zone, Outcome::Failed);
+                    Attempt::Failed(None)
+                }
+                FetchError::Timeout(err) => {
+                    info!("{} Timeout={} trying another ip", query.header.id, err);
+                    self.infra
+                        .report(&socket_server_addr, zone, Outcome::Failed);
+                    Attempt::Failed(Some(FetchError::Timeout(err)))
+                }
+                // https://tools.ietf.org/html/rfc4697 2.1
+                // Authorities refusing queries for their zone are lame.
+                FetchError::QueryError(err)
+                    if zone.is_some()
+                        && err.query.header.response_code == ResponseCode::Refused =>
+                {
+                    info!(
+                        "{} refused by {} trying another ip",
+                        query.header.id, socket_server_addr
+                    );
+                    self.infra.report(&socket_server_addr, zone, Outcome::Lame);
+                    Attempt::Failed(Some(FetchError::QueryError(err)))
+                }
+                // https://tools.ietf.org/html/rfc1035 7.2
+                // Another server may well answer what this one failed to.
+                FetchError::QueryError(err)
+                    if err.query.header.response_code == ResponseCode::ServerFailure =>
+                {
+                    info!(
+                        "{} server failure from {} trying another ip",
+                        query.header.id, socket_server_addr
+                    );
+                    self.infra
+                        .report(&socket_server_addr, zone, Outcome::Failed);
+                    Attempt::Failed(Some(FetchError::QueryError(err)))
+                }
+                FetchError::QueryError(err) => {
+                    self.infra.report(
+                        &socket_server_addr,
+                        zone,
+                        Outcome::Answered(started_at.elapsed()),
+                    );
+                    self.cache_negative_response(&err);
+                    Attempt::Error(FetchError::QueryError(err))
+                }
+                FetchError::InfiniteRecursionError(err) => {
+                    Attempt::Error(FetchError::InfiniteRecursionError(err))
+                }
+                FetchError::NoIPError(err) => Attempt::Error(FetchError::NoIPError(err)),
+            },
+        }
+    }
+
+    // Starts from the snapshot if there is one,  an unreadable snapshot only costs a cold start.
+    fn warm_cache(config: &Config) -> InMemoryCache {
+        let cache = InMemoryCache::new(config);
+        if let Some(path) = &config.cache_snapshot_path {
+            match snapshot::load(path) {
+                Ok(store) => info!(
+                    "restored {} records from {}",
+                    cache.restore(store),
+                    path.display()
                 ),
-            };
+                Err(err) => error!("not restoring cache from {}: {}", path.display(), err),
+            }
         }
+        cache
+    }
 
-        panic!(
-            "request: could not send request to {} ips",
-            ip_records.len()
-        );
+    // A zone that does not load stops rrdns from starting,  rather than answering without it.
+    fn load_zones(config: &Config) -> Zones {
+        Zones::new(
+            config
+                .zone_files
+                .iter()
+                .map(|zone_file| {
+                    let zone = master::load(&zone_file.path, &zone_file.origin)
+                        .and_then(|rrs| Zone::new(&zone_file.origin, rrs))
+                        .unwrap_or_else(|err| {
+                            panic!(
+                                "loading zone {} from {}: {}",
+                                zone_file.origin,
+                                zone_file.path.display(),
+                                err
+                            )
+                        });
+                    info!(
+                        "loaded zone {} with {} records",
+                        zone.origin(),
+                        zone.records_count()
+                    );
+                    zone
+                })
+                .collect(),
+        )
+    }
+
+    pub fn save_cache_snapshot(&self, path: &Path) {
+        match snapshot::save(&self.cache.clone_cache(), path) {
+            Ok(saved) => info!("saved {} records to {}", saved, path.display()),
+            Err(err) => error!("saving cache to {}: {}", path.display(), err),
+        }
     }
 
     pub fn clone_cache(&self) -> Store {
-        let cache = self.cache.lock().unwrap();
-        cache.clone_cache()
+        self.cache.clone_cache()
+    }
+
+    pub fn purge_expired_cache(&self) -> usize {
+        self.cache.purge_expired()
     }
 
     fn update_cache(&self, response: &DNSQueryResponse) {
@@ -395,10 +889,55 @@ impl Resolver {
         let authority_iter = response.authority.iter();
         let additional_iter = response.additional.iter();
 
-        let mut cache = self.cache.lock().unwrap();
         answers_iter
             .chain(authority_iter.chain(additional_iter))
-            .for_each(|rr| cache.insert2(&rr));
+            .for_each(|rr| self.cache.insert2(&rr));
+        self.cache_negative_response(response);
+    }
+
+    // https://tools.ietf.org/html/rfc2308 2
+    // NXDOMAIN,  or NOERROR without answers,  with a SOA in the authority section.  Referrals
+    // carry NS records instead of a SOA and are not negative answers.
+    fn cache_negative_response(&self, response: &DNSQueryResponse) {
+        let question = match response.query.questions.first() {
+            Some(question) => question,
+            None => return,
+        };
+        let response_code = response.query.header.response_code.clone();
+        // NS answers are made up from the servers of the enclosing zone,  see
+        // resolve_from_name_servers.  NXDOMAIN holds for every type though.
+        if !response.answers.is_empty()
+            || (question.qtype == QType::NS && response_code != ResponseCode::NameError)
+        {
+            return;
+        }
+        if !matches!(
+            response_code,
+            ResponseCode::NoError | ResponseCode::NameError
+        ) {
+            return;
+        }
+        if let Some(entry) = NegativeCacheEntry::new(response_code, response.authority.clone()) {
+            self.cache
+                .insert_negative(&question.qname, &question.qtype, entry);
+        }
+    }
+
+    // https://tools.ietf.org/html/rfc8020 2
+    // Cached NXDOMAIN for name or one of its ancestors:  nothing exists at or below name.
+    fn is_nonexistent(&self, name: &str) -> bool {
+        let mut name = name.to_string();
+        while name != "." {
+            if self
+                .cache
+                .get_negative(&name, &QType::NS)
+                .is_some_and(|entry| entry.response_code == ResponseCode::NameError)
+            {
+                return true;
+            }
+            name = parent_zone(&name);
+        }
+        false
     }
 
     // TODO: use builder pattern.
@@ -412,6 +951,8 @@ impl Resolver {
                 is_truncated: false,
                 is_authoritative_answer: false,
                 is_recursion_available: false,
+                is_authentic_data: false,
+                is_checking_disabled: false,
                 is_recursion_desired: true,
                 response_code: ResponseCode::NoError,
                 questions_count: 1,
@@ -425,6 +966,430 @@ impl Resolver {
                 qtype,
             }],
             additionals: vec![],
+            edns: None,
+        }
+    }
+}
+
+// What came of asking one server.
+enum Attempt {
+    Answered(DNSQueryResponse),
+    // Another server may answer,  err is kept in case none does.
+    Failed(Option<FetchError>),
+    // Any other server would say the same.
+    Error(FetchError),
+}
+
+// Why no server answered,  see request_addrs.
+#[derive(Default)]
+struct Failures {
+    timed_out: Option<String>,
+    // Refused or failed by the server.
+    rejected: Option<DNSQueryResponse>,
+}
+
+impl Failures {
+    fn add(&mut self, err: Option<FetchError>) {
+        match err {
+            Some(FetchError::Timeout(err)) => self.timed_out = Some(err),
+            Some(FetchError::QueryError(response)) => self.rejected = Some(response),
+            _ => {}
         }
     }
+
+    fn into_error(self, query: &DNSQuery, servers_count: usize) -> FetchError {
+        // Callers move on to other name servers after a timeout.
+        if let Some(err) = self.timed_out {
+            return FetchError::Timeout(err);
+        }
+        if let Some(response) = self.rejected {
+            return FetchError::QueryError(response);
+        }
+        FetchError::NoIPError(format!(
+            "{} request: no answer from {} ips",
+            query.header.id, servers_count
+        ))
+    }
+}
+
+// https://tools.ietf.org/html/rfc4035 3.1.4
+// Referrals from signed zones carry DS,  NSEC and RRSIG records next to the NS records.
+fn without_dnssec_records(records: RRSet) -> RRSet {
+    records
+        .into_iter()
+        .filter(|rr| {
+            !matches!(
+                rr.r#type.to_qtype(),
+                QType::DS | QType::RRSIG | QType::NSEC | QType::NSEC3
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Resolver;
+    use crate::business::models::{
+        Class, DNSQuery, DNSQueryResponse, DNSQuestionQuery, QType, RRSet, ResourceRecord,
+        ResponseCode, SOAData, Type,
+    };
+    use crate::config::{Config, ForwardTransport, ZoneRoute, ZoneRouteKind};
+    use crate::error::FetchError;
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use tokio::net::UdpSocket;
+
+    // Name server on a local port counting the queries it gets.  It answers them with rcode and
+    // no records,  or not at all when rcode is None.
+    async fn stand_in(rcode: Option<u8>) -> (SocketAddr, Arc<AtomicUsize>) {
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = queries.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (read_bytes_count, from) = socket.recv_from(&mut buf).await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                if let Some(rcode) = rcode {
+                    let mut response = buf[..read_bytes_count].to_vec();
+                    response[2] |= 0b1000_0000;
+                    response[3] = (response[3] & 0b1111_0000) | rcode;
+                    socket.send_to(&response, &from).await.unwrap();
+                }
+            }
+        });
+        (addr, queries)
+    }
+
+    fn staggered_resolver(stagger: Duration) -> Resolver {
+        Resolver::new(&Config {
+            query_stagger: Some(stagger),
+            ..Config::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_resolver_staggered_first_answer_wins() {
+        // Arrange
+        let (silent, silent_queries) = stand_in(None).await;
+        let (answering, answering_queries) = stand_in(Some(0)).await;
+        let resolver = staggered_resolver(Duration::from_millis(100));
+        let query = resolver.build_query("www.example.".to_string(), QType::A);
+
+        // Act
+        let started_at = Instant::now();
+        let response = resolver
+            .request_addrs(&query, &[silent, answering], &ForwardTransport::Udp, None)
+            .await;
+        let elapsed = started_at.elapsed();
+        // Past the first retransmission the silent server would have got.
+        tokio::time::delay_for(Duration::from_secs(1)).await;
+
+        // Assert
+        assert!(response.is_ok());
+        assert!(elapsed >= Duration::from_millis(100), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(400), "{:?}", elapsed);
+        assert_eq!(answering_queries.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            silent_queries.load(Ordering::SeqCst),
+            1,
+            "losing query is cancelled instead of retransmitted"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolver_staggered_failure_skips_stagger() {
+        // Arrange
+        let (refusing, refusing_queries) = stand_in(Some(5)).await;
+        let (answering, _) = stand_in(Some(0)).await;
+        let resolver = staggered_resolver(Duration::from_secs(5));
+        let query = resolver.build_query("www.example.".to_string(), QType::A);
+
+        // Act
+        let started_at = Instant::now();
+        let response = resolver
+            .request_addrs(
+                &query,
+                &[refusing, answering],
+                &ForwardTransport::Udp,
+                Some("example."),
+            )
+            .await;
+
+        // Assert
+        assert!(response.is_ok());
+        assert!(started_at.elapsed() < Duration::from_secs(1));
+        assert_eq!(refusing_queries.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_resolver_staggered_no_answer() {
+        // Arrange
+        let (refusing, _) = stand_in(Some(5)).await;
+        let (other_refusing, _) = stand_in(Some(5)).await;
+        let resolver = staggered_resolver(Duration::from_millis(100));
+        let query = resolver.build_query("www.example.".to_string(), QType::A);
+
+        // Act
+        let response = resolver
+            .request_addrs(
+                &query,
+                &[refusing, other_refusing],
+                &ForwardTransport::Udp,
+                Some("example."),
+            )
+            .await;
+
+        // Assert
+        match response {
+            Err(FetchError::QueryError(response)) => {
+                assert_eq!(response.query.header.response_code, ResponseCode::Refused)
+            }
+            _ => panic!("expected REFUSED"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolver_server_failure_tries_next_server() {
+        // Arrange
+        let (failing, failing_queries) = stand_in(Some(2)).await;
+        let (answering, answering_queries) = stand_in(Some(0)).await;
+        let resolver = Resolver::new(&Config::default());
+        let query = resolver.build_query("www.example.".to_string(), QType::A);
+
+        // Act
+        let response = resolver
+            .request_addrs(
+                &query,
+                &[failing, answering],
+                &ForwardTransport::Udp,
+                Some("example."),
+            )
+            .await;
+
+        // Assert
+        assert_eq!(
+            response.unwrap().query.header.response_code,
+            ResponseCode::NoError
+        );
+        assert_eq!(failing_queries.load(Ordering::SeqCst), 1);
+        assert_eq!(answering_queries.load(Ordering::SeqCst), 1);
+    }
+
+    fn record(name: &str, r#type: Type) -> ResourceRecord {
+        ResourceRecord {
+            name: name.to_string(),
+            class: Class::IN,
+            r#type,
+            ttl: 300,
+            rd_length: 0,
+        }
+    }
+
+    // What an authority replies to a question.
+    enum Reply {
+        Answer(ResponseCode, RRSet),
+        // NS records of a child zone and their addresses.
+        Referral(RRSet, RRSet),
+    }
+
+    // Authority of "test." on addr,  answer picks the reply to a question.  Names it was asked
+    // about are recorded.
+    async fn authority(
+        addr: &str,
+        answer: fn(&DNSQuestionQuery) -> Reply,
+    ) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        let mut socket = UdpSocket::bind(addr).await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let asked = Arc::new(Mutex::new(vec![]));
+        let names = asked.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (read_bytes_count, from) = socket.recv_from(&mut buf).await.unwrap();
+                let (mut query, _) = DNSQuery::deserialize(&buf[..read_bytes_count]).unwrap();
+                names.lock().unwrap().push(query.questions[0].qname.clone());
+                let (response_code, answers, referral, glue) = match answer(&query.questions[0]) {
+                    Reply::Answer(response_code, answers) => (response_code, answers, None, vec![]),
+                    Reply::Referral(ns, glue) => (ResponseCode::NoError, vec![], Some(ns), glue),
+                };
+                query.header.is_query = false;
+                query.header.is_authoritative_answer = referral.is_none();
+                query.header.response_code = response_code;
+                query.edns = None;
+                // https://tools.ietf.org/html/rfc2308 3
+                let authority = if let Some(ns) = referral {
+                    ns
+                } else if answers.is_empty() {
+                    vec![record(
+                        "test.",
+                        Type::SOA(SOAData {
+                            mname: "ns.test.".to_string(),
+                            rname: "hostmaster.test.".to_string(),
+                            serial: 1,
+                            refresh_in_secs: 3600,
+                            retry_in_secs: 600,
+                            expire_in_secs: 86400,
+                            minimum: 300,
+                        }),
+                    )]
+                } else {
+                    vec![]
+                };
+                let response = DNSQueryResponse {
+                    query,
+                    answers,
+                    authority,
+                    additional: glue,
+                };
+                socket.send_to(&response.serialize(), &from).await.unwrap();
+            }
+        });
+        (addr, asked)
+    }
+
+    // Resolver knowing addr as the name server of "test.".
+    fn resolver_for_test(addr: SocketAddr) -> Resolver {
+        let mut resolver = Resolver::new(&Config {
+            dnssec_validation: false,
+            ..Config::default()
+        });
+        resolver.authority_port = addr.port();
+        resolver
+            .cache
+            .insert2(&record("test.", Type::NS("ns.test.".to_string())));
+        match addr.ip() {
+            IpAddr::V4(ip) => resolver.cache.insert2(&record("ns.test.", Type::A(ip))),
+            IpAddr::V6(ip) => resolver.cache.insert2(&record("ns.test.", Type::AAAA(ip))),
+        }
+        resolver
+    }
+
+    #[tokio::test]
+    async fn test_resolver_relaxes_for_empty_non_terminal() {
+        // Arrange
+        // ent.test. only has names below it,  yet its server says it does not exist.
+        let (addr, asked) = authority("127.0.0.1:0", |question| match question.qname.as_str() {
+            "ent.test." => Reply::Answer(ResponseCode::NameError, vec![]),
+            "www.sub.ent.test." if question.qtype == QType::A => Reply::Answer(
+                ResponseCode::NoError,
+                vec![record(
+                    "www.sub.ent.test.",
+                    Type::A("192.0.2.1".parse().unwrap()),
+                )],
+            ),
+            _ => Reply::Answer(ResponseCode::NoError, vec![]),
+        })
+        .await;
+        let resolver = resolver_for_test(addr);
+        let query = resolver.build_query("www.sub.ent.test.".to_string(), QType::A);
+
+        // Act
+        let response = resolver.lookup(&query).await;
+
+        // Assert
+        assert_eq!(
+            response.unwrap().answers,
+            vec![record(
+                "www.sub.ent.test.",
+                Type::A("192.0.2.1".parse().unwrap())
+            )]
+        );
+        assert_eq!(
+            *asked.lock().unwrap(),
+            vec![
+                "ent.test.",
+                "sub.ent.test.",
+                "www.sub.ent.test.",
+                "www.sub.ent.test."
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolver_stops_below_nonexistent_name() {
+        // Arrange
+        let (addr, asked) = authority("127.0.0.1:0", |question| {
+            if question.qname.ends_with("nope.test.") {
+                Reply::Answer(ResponseCode::NameError, vec![])
+            } else {
+                Reply::Answer(ResponseCode::NoError, vec![])
+            }
+        })
+        .await;
+        let resolver = resolver_for_test(addr);
+        let query = resolver.build_query("www.a.b.nope.test.".to_string(), QType::A);
+        let other_query = resolver.build_query("www.c.nope.test.".to_string(), QType::A);
+
+        // Act
+        let response = resolver.lookup(&query).await;
+        let other_response = resolver.lookup(&other_query).await;
+
+        // Assert
+        // Relaxed once,  for b.nope.test.,  the names below are known not to exist.
+        assert_eq!(*asked.lock().unwrap(), vec!["nope.test.", "b.nope.test."]);
+        for response in [response, other_response] {
+            match response {
+                Err(FetchError::QueryError(response)) => {
+                    assert_eq!(response.query.header.response_code, ResponseCode::NameError)
+                }
+                _ => panic!("expected NXDOMAIN"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolver_follows_referrals_from_stub_zone() {
+        // Arrange
+        let (stub, _) = authority("127.0.0.1:0", |_| {
+            Reply::Referral(
+                vec![record(
+                    "child.test.",
+                    Type::NS("ns.child.test.".to_string()),
+                )],
+                vec![record(
+                    "ns.child.test.",
+                    Type::A("127.0.0.2".parse().unwrap()),
+                )],
+            )
+        })
+        .await;
+        let (_, child_asked) = authority(&format!("127.0.0.2:{}", stub.port()), |question| {
+            Reply::Answer(
+                ResponseCode::NoError,
+                vec![record(
+                    &question.qname,
+                    Type::A("192.0.2.2".parse().unwrap()),
+                )],
+            )
+        })
+        .await;
+        let mut resolver = Resolver::new(&Config {
+            dnssec_validation: false,
+            zone_routes: vec![ZoneRoute {
+                zone: "test.".to_string(),
+                kind: ZoneRouteKind::Stub,
+                servers: vec![stub],
+            }],
+            ..Config::default()
+        });
+        resolver.authority_port = stub.port();
+        let query = resolver.build_query("www.child.test.".to_string(), QType::A);
+
+        // Act
+        let response = resolver.lookup(&query).await;
+
+        // Assert
+        assert_eq!(
+            response.unwrap().answers,
+            vec![record(
+                "www.child.test.",
+                Type::A("192.0.2.2".parse().unwrap())
+            )]
+        );
+        assert_eq!(*child_asked.lock().unwrap(), vec!["www.child.test."]);
+    }
 }
@@ -0,0 +1,375 @@
+use super::zone::{in_zone, normalize, parent_zone};
+use crate::business::models::{
+    DNSQuery, DNSQueryResponse, QType, RRSet, ResourceRecord, ResponseCode, Type,
+};
+use crate::error::MasterFileError;
+use log::warn;
+use std::collections::{HashMap, HashSet};
+
+// CNAMEs followed within a zone before the chain is taken for a loop.
+const MAX_CNAME_CHAIN: usize = 8;
+
+// Zone rrdns answers for itself,  with the AA bit set.
+pub struct Zone {
+    origin: String,
+    records: HashMap<String, HashMap<QType, RRSet>>,
+    // Owners of records and every name between them and the origin.  Names in here without
+    // records are empty non-terminals,  which exist but hold no data.
+    names: HashSet<String>,
+}
+
+impl Zone {
+    // Records outside of origin are left out,  origin must have a SOA.
+    pub fn new(origin: &str, rrs: RRSet) -> Result<Self, MasterFileError> {
+        let origin = normalize(origin);
+        let mut records: HashMap<String, HashMap<QType, RRSet>> = HashMap::new();
+        let mut names = HashSet::new();
+        for mut rr in rrs {
+            rr.name = normalize(&rr.name);
+            if !in_zone(&rr.name, &origin) {
+                warn!("zone {}: ignoring {} outside of the zone", origin, rr.name);
+                continue;
+            }
+            let mut name = rr.name.clone();
+            while names.insert(name.clone()) && name != origin {
+                name = parent_zone(&name);
+            }
+            records
+                .entry(rr.name.clone())
+                .or_default()
+                .entry(rr.r#type.to_qtype())
+                .or_default()
+                .push(rr);
+        }
+        match records
+            .get(&origin)
+            .and_then(|rrsets| rrsets.get(&QType::SOA))
+        {
+            Some(soa) if soa.len() == 1 => Ok(Self {
+                origin,
+                records,
+                names,
+            }),
+            _ => Err(MasterFileError::Zone(format!(
+                "{} needs a single SOA at its apex",
+                origin
+            ))),
+        }
+    }
+
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+
+    pub fn records_count(&self) -> usize {
+        self.records
+            .values()
+            .flat_map(|rrsets| rrsets.values())
+            .map(|rrs| rrs.len())
+            .sum()
+    }
+
+    // https://tools.ietf.org/html/rfc1034 4.3.2
+    pub fn answer(&self, query: &DNSQuery) -> DNSQueryResponse {
+        let qtype = query.questions[0].qtype;
+        let mut name = normalize(&query.questions[0].qname);
+        let mut answers = vec![];
+        for _ in 0..MAX_CNAME_CHAIN {
+            if let Some(ns_records) = self.delegation(&name, &qtype) {
+                // A CNAME into a child zone is left for the client to follow.
+                if answers.is_empty() {
+                    return self.referral(query, ns_records);
+                }
+                break;
+            }
+            let mut rrsets = match self.lookup(&name) {
+                Some(rrsets) => rrsets,
+                // https://tools.ietf.org/html/rfc6604 2.1
+                // RCODE is about the last name in the CNAME chain.
+                None => {
+                    return self.response(query, ResponseCode::NameError, answers, true);
+                }
+            };
+            if qtype == QType::STAR && !rrsets.is_empty() {
+                rrsets
+                    .into_iter()
+                    .for_each(|(_, mut rrs)| answers.append(&mut rrs));
+                break;
+            }
+            if let Some(mut rrs) = rrsets.remove(&qtype) {
+                answers.append(&mut rrs);
+                break;
+            }
+            match rrsets.remove(&QType::CNAME) {
+                Some(mut cname_records) => {
+                    let target = match &cname_records[0].r#type {
+                        Type::CNAME(target) => normalize(target),
+                        _ => unreachable!(),
+                    };
+                    answers.append(&mut cname_records);
+                    if !in_zone(&target, &self.origin) {
+                        break;
+                    }
+                    name = target;
+                }
+                // https://tools.ietf.org/html/rfc2308 2.2
+                None => return self.response(query, ResponseCode::NoError, answers, true),
+            }
+        }
+        self.response(query, ResponseCode::NoError, answers, false)
+    }
+
+    // Records of name,  synthesized from a wildcard when name does not exist.  Empty for empty
+    // non-terminals,  None when there is no such name.
+    fn lookup(&self, name: &str) -> Option<HashMap<QType, RRSet>> {
+        if self.names.contains(name) {
+            return Some(self.records.get(name).cloned().unwrap_or_default());
+        }
+        // https://tools.ietf.org/html/rfc4592 3.3.1
+        // Wildcard is searched right below the closest encloser only.
+        let mut closest_encloser = parent_zone(name);
+        while !self.names.contains(&closest_encloser) {
+            closest_encloser = parent_zone(&closest_encloser);
+        }
+        let mut rrsets = self
+            .records
+            .get(&format!("*.{}", closest_encloser))?
+            .clone();
+        rrsets
+            .values_mut()
+            .flat_map(|rrs| rrs.iter_mut())
+            .for_each(|rr| rr.name = name.to_string());
+        Some(rrsets)
+    }
+
+    // https://tools.ietf.org/html/rfc1034 4.2.1
+    // NS records of the topmost zone cut between the origin and name.  DS of a child zone lives
+    // on this side of the cut.
+    fn delegation(&self, name: &str, qtype: &QType) -> Option<&RRSet> {
+        let mut names_below_origin = vec![];
+        let mut ancestor = name.to_string();
+        while ancestor != self.origin {
+            names_below_origin.push(ancestor.clone());
+            ancestor = parent_zone(&ancestor);
+        }
+        names_below_origin
+            .iter()
+            .rev()
+            .filter(|cut| !(*cut == name && *qtype == QType::DS))
+            .find_map(|cut| self.records.get(cut)?.get(&QType::NS))
+    }
+
+    // https://tools.ietf.org/html/rfc1034 4.3.2
+    // Referral carries the NS records of the child zone and the addresses of the ones in it.
+    fn referral(&self, query: &DNSQuery, ns_records: &RRSet) -> DNSQueryResponse {
+        let mut response = self.response(query, ResponseCode::NoError, vec![], false);
+        response.query.header.is_authoritative_answer = false;
+        response.authority = ns_records.clone();
+        response.additional = ns_records
+            .iter()
+            .filter_map(|rr| match &rr.r#type {
+                Type::NS(ns) => self.records.get(&normalize(ns)),
+                _ => None,
+            })
+            .flat_map(|rrsets| {
+                [QType::A, QType::AAAA]
+                    .iter()
+                    .filter_map(|qtype| rrsets.get(qtype))
+                    .flatten()
+                    .cloned()
+                    .collect::<RRSet>()
+            })
+            .collect();
+        response.query.header.ns_rr_count = response.authority.len() as u16;
+        response.query.header.additional_rr_count = response.additional.len() as u16;
+        response
+    }
+
+    fn response(
+        &self,
+        query: &DNSQuery,
+        response_code: ResponseCode,
+        answers: RRSet,
+        with_soa: bool,
+    ) -> DNSQueryResponse {
+        let mut query_of_response = query.clone();
+        query_of_response.header.is_query = false;
+        query_of_response.header.is_authoritative_answer = true;
+        query_of_response.header.response_code = response_code;
+        query_of_response.header.answers_count = answers.len() as u16;
+        // https://tools.ietf.org/html/rfc2308 3
+        // SOA of a negative answer has the TTL negative answers are cached for.
+        let authority: RRSet = if with_soa {
+            self.records[&self.origin][&QType::SOA]
+                .iter()
+                .map(|rr| match &rr.r#type {
+                    Type::SOA(soa) => ResourceRecord {
+                        ttl: rr.ttl.min(soa.minimum),
+                        ..rr.clone()
+                    },
+                    _ => rr.clone(),
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+        query_of_response.header.ns_rr_count = authority.len() as u16;
+        DNSQueryResponse {
+            query: query_of_response,
+            answers,
+            authority,
+            additional: vec![],
+        }
+    }
+}
+
+// Zones rrdns is authoritative for,  the one with the longest origin is picked for a name.
+pub struct Zones {
+    zones: Vec<Zone>,
+}
+
+impl Zones {
+    pub fn new(mut zones: Vec<Zone>) -> Self {
+        zones.sort_by_key(|zone| std::cmp::Reverse(zone.origin.len()));
+        Self { zones }
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Zone> {
+        let name = normalize(name);
+        self.zones.iter().find(|zone| in_zone(&name, &zone.origin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Zone, Zones};
+    use crate::business::models::{DNSQuery, QType, ResponseCode, Type};
+    use crate::error::MasterFileError;
+    use crate::resolver::master;
+
+    const ZONE: &str = "
+$TTL 3600
+@           SOA     ns1 hostmaster 1 7200 3600 604800 300
+            NS      ns1
+ns1         A       192.0.2.53
+www         CNAME   web
+web         A       192.0.2.80
+*.apps      A       192.0.2.81
+a.b.c       TXT     deep
+child       NS      ns.child
+child       DS      12345 8 2 abcdef
+ns.child    A       192.0.2.54
+";
+
+    fn zone() -> Zone {
+        Zone::new("example.com.", master::parse(ZONE, "example.com.").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_zone_answer_follows_cname() {
+        // Arrange
+        let zone = zone();
+
+        // Act
+        let response = zone.answer(&DNSQuery::for_test("WWW.example.com.", QType::A));
+
+        // Assert
+        assert!(response.query.header.is_authoritative_answer);
+        assert_eq!(response.query.header.response_code, ResponseCode::NoError);
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(response.answers[0].r#type.to_qtype(), QType::CNAME);
+        assert_eq!(response.answers[1].r#type, Type::A([192, 0, 2, 80].into()));
+    }
+
+    #[test]
+    fn test_zone_answer_negative_with_soa() {
+        // Arrange
+        let zone = zone();
+
+        // Act
+        let nxdomain = zone.answer(&DNSQuery::for_test("nope.example.com.", QType::A));
+        let nodata = zone.answer(&DNSQuery::for_test("web.example.com.", QType::AAAA));
+        let empty_non_terminal = zone.answer(&DNSQuery::for_test("b.c.example.com.", QType::A));
+
+        // Assert
+        assert_eq!(nxdomain.query.header.response_code, ResponseCode::NameError);
+        assert_eq!(nxdomain.authority[0].r#type.to_qtype(), QType::SOA);
+        assert_eq!(nxdomain.authority[0].ttl, 300);
+        assert_eq!(nodata.query.header.response_code, ResponseCode::NoError);
+        assert!(nodata.answers.is_empty());
+        assert_eq!(nodata.authority.len(), 1);
+        assert_eq!(
+            empty_non_terminal.query.header.response_code,
+            ResponseCode::NoError
+        );
+    }
+
+    #[test]
+    fn test_zone_answer_wildcard() {
+        // Arrange
+        let zone = zone();
+
+        // Act
+        let response = zone.answer(&DNSQuery::for_test("x.y.apps.example.com.", QType::A));
+
+        // Assert
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].name, "x.y.apps.example.com.");
+        assert_eq!(response.answers[0].r#type, Type::A([192, 0, 2, 81].into()));
+    }
+
+    #[test]
+    fn test_zone_answer_referral() {
+        // Arrange
+        let zone = zone();
+
+        // Act
+        let referral = zone.answer(&DNSQuery::for_test("www.child.example.com.", QType::A));
+        let ds = zone.answer(&DNSQuery::for_test("child.example.com.", QType::DS));
+
+        // Assert
+        assert!(!referral.query.header.is_authoritative_answer);
+        assert!(referral.answers.is_empty());
+        assert_eq!(
+            referral.authority[0].r#type,
+            Type::NS("ns.child.example.com.".to_string())
+        );
+        assert_eq!(referral.additional[0].name, "ns.child.example.com.");
+        assert!(ds.query.header.is_authoritative_answer);
+        assert_eq!(ds.answers[0].r#type.to_qtype(), QType::DS);
+    }
+
+    #[test]
+    fn test_zone_needs_soa() {
+        assert!(matches!(
+            Zone::new(
+                "example.com.",
+                master::parse("www 60 A 192.0.2.1", "example.com.").unwrap()
+            ),
+            Err(MasterFileError::Zone(_))
+        ));
+    }
+
+    #[test]
+    fn test_zones_find_longest_origin() {
+        // Arrange
+        let zones = Zones::new(vec![
+            zone(),
+            Zone::new(
+                "child.example.com.",
+                master::parse("@ 60 SOA ns hostmaster 1 2 3 4 5", "child.example.com.").unwrap(),
+            )
+            .unwrap(),
+        ]);
+
+        // Act
+        let child = zones.find("www.child.example.com.");
+        let parent = zones.find("www.example.com");
+        let outside = zones.find("example.org.");
+
+        // Assert
+        assert_eq!(child.unwrap().origin(), "child.example.com.");
+        assert_eq!(parent.unwrap().origin(), "example.com.");
+        assert!(outside.is_none());
+    }
+}
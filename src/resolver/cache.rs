@@ -1,15 +1,58 @@
-use crate::business::models::{Class, QType, ResourceRecord, Type};
+use super::master;
+use super::zone::normalize;
+use crate::business::models::{QType, RRSet, ResourceRecord, ResponseCode, Type};
+use crate::config::{Config, Eviction};
+use lazy_static::lazy_static;
 use log::{debug, info};
 use md5;
+use prometheus::{register_int_counter_vec, register_int_gauge, IntCounterVec, IntGauge};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use std::sync::{Mutex, MutexGuard};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-pub trait Cache {
-    fn get(&mut self, domain: &str, qtype: &QType) -> Option<Vec<ResourceRecord>>;
-    fn insert2(&mut self, resource_record: &ResourceRecord);
+mod eviction;
+use eviction::{new_policy, CacheKey, EvictionPolicy};
+
+pub mod snapshot;
+
+lazy_static! {
+    static ref RRDNS_CACHE_ENTRIES: IntGauge = register_int_gauge!(
+        "rrdns_cache_entries",
+        "Number of RRsets and negative answers in the cache"
+    )
+    .unwrap();
+    static ref RRDNS_CACHE_BYTES: IntGauge =
+        register_int_gauge!("rrdns_cache_bytes", "Estimated memory used by the cache").unwrap();
+    static ref RRDNS_CACHE_EVICTIONS: IntCounterVec = register_int_counter_vec!(
+        "rrdns_cache_evictions",
+        "Number of cache entries removed because they expired or the cache was full",
+        &["reason"]
+    )
+    .unwrap();
+}
+
+// Shared by every task resolving queries,  implementations do their own locking.
+pub trait Cache: Send + Sync {
+    fn get(&self, domain: &str, qtype: &QType) -> Option<Vec<ResourceRecord>>;
+    fn insert2(&self, resource_record: &ResourceRecord);
     fn clone_cache(&self) -> HashMap<String, HashMap<QType, CRRSet>>;
+
+    // https://tools.ietf.org/html/rfc2308 5
+    // NXDOMAIN entries answer every qtype of the name,  NODATA entries only their own.
+    fn get_negative(&self, domain: &str, qtype: &QType) -> Option<NegativeCacheEntry>;
+    fn insert_negative(&self, domain: &str, qtype: &QType, entry: NegativeCacheEntry);
+
+    // Removes expired records,  returns how many entries went away with them.
+    fn purge_expired(&self) -> usize;
+
+    // Adds the records of a snapshot,  keeping when they were refreshed.  Returns how many records
+    // the snapshot had.
+    fn restore(&self, store: Store) -> usize;
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,26 +74,118 @@ pub type CRRSet = Vec<CachedResourceRecord>;
 
 pub type Store = HashMap<String, HashMap<QType, CRRSet>>;
 
+// https://tools.ietf.org/html/rfc2308 5
+// Values of one to three hours have been found to work well.
+const MAX_NEGATIVE_TTL: u32 = 3 * 60 * 60;
+
+// https://tools.ietf.org/html/rfc2308 5
+// NXDOMAIN or NODATA answer together with the authority section it came with,  so that the SOA
+// (and the NSEC records proving the denial) can be handed out again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NegativeCacheEntry {
+    pub response_code: ResponseCode,
+    pub authority: RRSet,
+    ttl: u32,
+    last_refreshed_at: u32, // secs since epoch
+}
+
+impl NegativeCacheEntry {
+    // None unless the authority section carries the SOA that negative answers are cached by.
+    pub fn new(response_code: ResponseCode, authority: RRSet) -> Option<Self> {
+        let ttl = authority.iter().find_map(|rr| match &rr.r#type {
+            Type::SOA(soa) => Some(rr.ttl.min(soa.minimum)),
+            _ => None,
+        })?;
+        Some(NegativeCacheEntry {
+            response_code,
+            authority,
+            ttl: ttl.min(MAX_NEGATIVE_TTL),
+            last_refreshed_at: get_secs_since_epoch(),
+        })
+    }
+
+    // Authority records with their TTLs counted down to what is left of the entry.
+    pub fn remaining_authority(&self) -> RRSet {
+        let elapsed = get_secs_since_epoch().saturating_sub(self.last_refreshed_at);
+        let remaining = self.ttl.saturating_sub(elapsed);
+        self.authority
+            .iter()
+            .map(|rr| {
+                let mut rr = rr.clone();
+                rr.ttl = rr.ttl.min(remaining);
+                rr
+            })
+            .collect()
+    }
+
+    fn is_expired(&self) -> bool {
+        get_secs_since_epoch().saturating_sub(self.last_refreshed_at) > self.ttl
+    }
+
+    fn size(&self) -> usize {
+        size_of::<NegativeCacheEntry>() + self.authority.iter().map(record_size).sum::<usize>()
+    }
+}
+
+// Names are spread over this many independently locked shards.
+const SHARD_COUNT: usize = 16;
+
+// Every name lives in one shard,  so that lookups of different names rarely wait on the same
+// lock.  Limits are split evenly between the shards.
 pub struct InMemoryCache {
+    shards: Vec<Mutex<Shard>>,
+}
+
+// Entries are RRsets and negative answers.  Once there are more than max_entries of them,  or
+// they take more than max_bytes,  the eviction policy picks which ones go.
+struct Shard {
+    // https://tools.ietf.org/html/rfc4343 3
+    // Owner names are keyed in lower case,  answers to mixed case queries land on the same entries.
     store: Store,
+    negative_store: HashMap<String, HashMap<QType, NegativeCacheEntry>>,
+    // Root hints,  never evicted.
+    pinned: HashSet<CacheKey>,
+    eviction: Box<dyn EvictionPolicy + Send>,
+    max_entries: usize,
+    max_bytes: usize,
+    entries: usize,
+    bytes: usize,
 }
 
 impl InMemoryCache {
-    pub fn new() -> InMemoryCache {
-        let root_rrs: Vec<CachedResourceRecord> = InMemoryCache::load_root_name_servers();
-
-        let mut store = HashMap::new();
-        for crr in root_rrs.into_iter() {
-            let owner = &crr.rr.name.to_lowercase();
-            let qtype = crr.rr.r#type.to_qtype();
+    pub fn new(config: &Config) -> InMemoryCache {
+        let cache = InMemoryCache {
+            shards: (0..SHARD_COUNT)
+                .map(|_| {
+                    Mutex::new(Shard::new(
+                        config.cache_max_entries.div_ceil(SHARD_COUNT),
+                        config.cache_max_bytes.div_ceil(SHARD_COUNT),
+                        &config.cache_eviction,
+                    ))
+                })
+                .collect(),
+        };
 
-            let type_map = store.entry(owner.clone()).or_insert_with(HashMap::new);
-            type_map.entry(qtype).or_insert_with(Vec::new).push(crr);
+        for crr in InMemoryCache::load_root_name_servers() {
+            let mut shard = cache.shard(&crr.rr.name);
+            shard.pinned.insert(CacheKey::Positive(
+                normalize(&crr.rr.name),
+                crr.rr.r#type.to_qtype(),
+            ));
+            shard.insert2(&crr.rr);
         }
 
-        debug!("InMemoryCache: {:#?}", store);
+        debug!("InMemoryCache: {:#?}", cache.clone_cache());
 
-        InMemoryCache { store }
+        cache
+    }
+
+    fn shard(&self, domain: &str) -> MutexGuard<'_, Shard> {
+        let mut hasher = DefaultHasher::new();
+        normalize(domain).hash(&mut hasher);
+        self.shards[hasher.finish() as usize % SHARD_COUNT]
+            .lock()
+            .unwrap()
     }
 
     fn check_root_file_integrity() -> String {
@@ -68,134 +203,307 @@ impl InMemoryCache {
 
     fn load_root_name_servers() -> Vec<CachedResourceRecord> {
         let contents = InMemoryCache::check_root_file_integrity();
-
-        let root_rrs: Vec<CachedResourceRecord> = contents
-            .split('\n')
-            .filter_map(|line| -> Option<CachedResourceRecord> {
-                // Ignore comments
-                if line.starts_with(';') {
-                    return None;
-                }
-
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                match parts[..] {
-                    [name, ttl, rtype, value] if rtype == "NS" => {
-                        let ttl: u32 = ttl.to_string().parse().unwrap();
-                        Some(CachedResourceRecord {
-                            rr: ResourceRecord {
-                                name: name.to_string().to_lowercase(),
-                                r#type: Type::NS(value.to_string().to_lowercase()),
-                                class: Class::IN,
-                                ttl,
-                                rd_length: compute_label_length(value),
-                            },
-                            last_refreshed_at: get_secs_since_epoch(),
-                        })
-                    }
-                    [name, ttl, rtype, value] if rtype == "A" => {
-                        let ttl: u32 = ttl.to_string().parse().unwrap();
-                        Some(CachedResourceRecord {
-                            rr: ResourceRecord {
-                                name: name.to_string().to_lowercase(),
-                                r#type: Type::A(value.parse().unwrap()),
-                                class: Class::IN,
-                                ttl,
-                                rd_length: 4,
-                            },
-                            last_refreshed_at: get_secs_since_epoch(),
-                        })
-                    }
-                    [name, ttl, rtype, value] if rtype == "AAAA" => {
-                        let ttl: u32 = ttl.to_string().parse().unwrap();
-                        Some(CachedResourceRecord {
-                            rr: ResourceRecord {
-                                name: name.to_string().to_lowercase(),
-                                r#type: Type::AAAA(value.parse().unwrap()),
-                                class: Class::IN,
-                                ttl,
-                                rd_length: 16,
-                            },
-                            last_refreshed_at: get_secs_since_epoch(),
-                        })
-                    }
-                    _ => panic!("Root name servers in invalid format."),
-                }
+        master::parse(&contents, ".")
+            .unwrap_or_else(|err| panic!("Root name servers in invalid format: {}", err))
+            .into_iter()
+            .map(|rr| CachedResourceRecord {
+                rr,
+                last_refreshed_at: get_secs_since_epoch(),
             })
-            .collect();
-        root_rrs
+            .collect()
     }
 }
 
 impl Cache for InMemoryCache {
-    fn get(&mut self, domain: &str, qtype: &QType) -> Option<Vec<ResourceRecord>> {
-        if let Some(owner) = self.store.get(domain) {
-            if let Some(cached_rrs) = owner.get(qtype) {
-                // TODO: remove expired entries.
-                let result: Vec<ResourceRecord> = cached_rrs
-                    .iter()
-                    .filter_map(|crr| {
-                        if crr.is_expired() {
-                            None
-                        } else {
-                            Some(crr.rr.clone())
-                        }
-                    })
-                    .collect();
-                if result.len() == 0 {
-                    return None;
+    fn get(&self, domain: &str, qtype: &QType) -> Option<Vec<ResourceRecord>> {
+        self.shard(domain).get(domain, qtype)
+    }
+
+    fn insert2(&self, resource_record: &ResourceRecord) {
+        self.shard(&resource_record.name).insert2(resource_record)
+    }
+
+    fn clone_cache(&self) -> Store {
+        let mut store = Store::new();
+        for shard in &self.shards {
+            store.extend(shard.lock().unwrap().store.clone());
+        }
+        store
+    }
+
+    fn get_negative(&self, domain: &str, qtype: &QType) -> Option<NegativeCacheEntry> {
+        self.shard(domain).get_negative(domain, qtype)
+    }
+
+    fn insert_negative(&self, domain: &str, qtype: &QType, entry: NegativeCacheEntry) {
+        self.shard(domain).insert_negative(domain, qtype, entry)
+    }
+
+    fn purge_expired(&self) -> usize {
+        let (mut purged, mut entries, mut bytes) = (0, 0, 0);
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            purged += shard.purge_expired();
+            entries += shard.entries;
+            bytes += shard.bytes;
+        }
+        info!(
+            "purged {} expired entries,  cache has {} entries taking {} bytes",
+            purged, entries, bytes
+        );
+        purged
+    }
+
+    fn restore(&self, store: Store) -> usize {
+        let mut restored = 0;
+        for (domain, owner) in store {
+            let mut shard = self.shard(&domain);
+            for crr in owner.into_values().flatten() {
+                shard.insert(&crr.rr, crr.last_refreshed_at);
+                restored += 1;
+            }
+        }
+        restored
+    }
+}
+
+impl Shard {
+    fn new(max_entries: usize, max_bytes: usize, eviction: &Eviction) -> Shard {
+        Shard {
+            store: HashMap::new(),
+            negative_store: HashMap::new(),
+            pinned: HashSet::new(),
+            eviction: new_policy(eviction),
+            max_entries,
+            max_bytes,
+            entries: 0,
+            bytes: 0,
+        }
+    }
+
+    fn grow(&mut self, entries: usize, bytes: usize) {
+        self.entries += entries;
+        self.bytes += bytes;
+        RRDNS_CACHE_ENTRIES.add(entries as i64);
+        RRDNS_CACHE_BYTES.add(bytes as i64);
+    }
+
+    fn shrink(&mut self, entries: usize, bytes: usize) {
+        self.entries -= entries;
+        self.bytes -= bytes;
+        RRDNS_CACHE_ENTRIES.sub(entries as i64);
+        RRDNS_CACHE_BYTES.sub(bytes as i64);
+    }
+
+    fn remove_entry(&mut self, key: &CacheKey) {
+        self.eviction.remove(key);
+        let freed = match key {
+            CacheKey::Positive(domain, qtype) => {
+                let owner = match self.store.get_mut(domain) {
+                    Some(owner) => owner,
+                    None => return,
+                };
+                let cached_rrs = match owner.remove(qtype) {
+                    Some(cached_rrs) => cached_rrs,
+                    None => return,
+                };
+                if owner.is_empty() {
+                    self.store.remove(domain);
+                }
+                cached_rrs.iter().map(|crr| record_size(&crr.rr)).sum()
+            }
+            CacheKey::Negative(domain, qtype) => {
+                let entries = match self.negative_store.get_mut(domain) {
+                    Some(entries) => entries,
+                    None => return,
+                };
+                let entry = match entries.remove(qtype) {
+                    Some(entry) => entry,
+                    None => return,
+                };
+                if entries.is_empty() {
+                    self.negative_store.remove(domain);
                 }
-                return Some(result);
+                entry.size()
             }
+        };
+        self.shrink(1, freed);
+    }
+
+    // Evicts until another entry taking bytes fits in the limits.
+    fn make_room(&mut self, bytes: usize) {
+        while self.entries + 1 > self.max_entries || self.bytes + bytes > self.max_bytes {
+            let key = match self.eviction.victim() {
+                Some(key) => key,
+                None => break,
+            };
+            debug!("evicting: {:?}", key);
+            self.remove_entry(&key);
+            RRDNS_CACHE_EVICTIONS.with_label_values(&["full"]).inc();
         }
+    }
+
+    // Returns the number of records dropped and the bytes they took.
+    fn drop_expired(cached_rrs: &mut CRRSet) -> (usize, usize) {
+        let (mut dropped, mut freed) = (0, 0);
+        cached_rrs.retain(|crr| {
+            if crr.is_expired() {
+                dropped += 1;
+                freed += record_size(&crr.rr);
+                return false;
+            }
+            true
+        });
+        (dropped, freed)
+    }
 
-        None
+    fn get(&mut self, domain: &str, qtype: &QType) -> Option<Vec<ResourceRecord>> {
+        let domain = normalize(domain);
+        let key = CacheKey::Positive(domain.clone(), *qtype);
+        let cached_rrs = self.store.get_mut(&domain)?.get_mut(qtype)?;
+        let (_, freed) = Shard::drop_expired(cached_rrs);
+        let result: Vec<ResourceRecord> = cached_rrs.iter().map(|crr| crr.rr.clone()).collect();
+        self.shrink(0, freed);
+        if result.is_empty() {
+            self.remove_entry(&key);
+            RRDNS_CACHE_EVICTIONS.with_label_values(&["expired"]).inc();
+            return None;
+        }
+        if !self.pinned.contains(&key) {
+            self.eviction.touch(&key);
+        }
+        Some(result)
     }
 
-    // Duplicates are ignored.
     fn insert2(&mut self, resource_record: &ResourceRecord) {
-        let domain = if !resource_record.name.ends_with('.') {
-            format!("{}.", resource_record.name)
-        } else {
-            resource_record.name.clone()
+        self.insert(resource_record, get_secs_since_epoch());
+    }
+
+    // Duplicates are ignored,  also when their owner names differ in case only.
+    fn insert(&mut self, resource_record: &ResourceRecord, last_refreshed_at: u32) {
+        let domain = normalize(&resource_record.name);
+        let resource_record = &ResourceRecord {
+            name: domain.clone(),
+            ..resource_record.clone()
         };
         let qtype = resource_record.r#type.to_qtype();
         debug!("caching: {} {:?}", domain, resource_record);
 
+        let key = CacheKey::Positive(domain.clone(), qtype);
+        let is_pinned = self.pinned.contains(&key);
+        let is_cached = self
+            .store
+            .get(&domain)
+            .and_then(|qmap| qmap.get(&qtype))
+            .is_some_and(|cached_rrs| cached_rrs.iter().any(|crr| crr.rr == *resource_record));
+        if !is_cached && !is_pinned {
+            self.make_room(record_size(resource_record));
+        }
+
         let qmap = self
             .store
             .entry(domain.clone())
             .or_insert_with(HashMap::new);
 
+        let is_new_rrset = !qmap.contains_key(&qtype);
         let cached_rrs = qmap.entry(qtype).or_insert_with(Vec::new);
 
         if cached_rrs
             .iter()
-            .find(|crr| crr.rr.name == *domain && crr.rr.r#type.to_qtype() == qtype)
+            .find(|crr| crr.rr == *resource_record)
             .is_none()
         {
             cached_rrs.append(&mut vec![CachedResourceRecord {
                 rr: resource_record.clone(),
-                last_refreshed_at: get_secs_since_epoch(),
+                last_refreshed_at,
             }]);
+            self.grow(is_new_rrset as usize, record_size(resource_record));
         } else {
         }
+
+        if !is_pinned {
+            self.eviction.touch(&key);
+        }
     }
 
-    fn clone_cache(&self) -> Store {
-        self.store.clone()
+    fn get_negative(&mut self, domain: &str, qtype: &QType) -> Option<NegativeCacheEntry> {
+        let domain = normalize(domain);
+        let expired: Vec<CacheKey> = self
+            .negative_store
+            .get(&domain)?
+            .iter()
+            .filter(|(_, entry)| entry.is_expired())
+            .map(|(qtype, _)| CacheKey::Negative(domain.clone(), *qtype))
+            .collect();
+        for key in &expired {
+            self.remove_entry(key);
+        }
+        RRDNS_CACHE_EVICTIONS
+            .with_label_values(&["expired"])
+            .inc_by(expired.len() as i64);
+
+        let entries = self.negative_store.get(&domain)?;
+        let (qtype, entry) = match entries.get_key_value(qtype) {
+            Some(found) => found,
+            None => entries
+                .iter()
+                .find(|(_, entry)| entry.response_code == ResponseCode::NameError)?,
+        };
+        let entry = entry.clone();
+        self.eviction.touch(&CacheKey::Negative(domain, *qtype));
+        Some(entry)
+    }
+
+    fn insert_negative(&mut self, domain: &str, qtype: &QType, entry: NegativeCacheEntry) {
+        let domain = normalize(domain);
+        debug!(
+            "caching negative: {} {:?} {:?}",
+            domain, qtype, entry.response_code
+        );
+        let key = CacheKey::Negative(domain.clone(), *qtype);
+        self.remove_entry(&key);
+        self.make_room(entry.size());
+        self.grow(1, entry.size());
+        self.negative_store
+            .entry(domain)
+            .or_default()
+            .insert(*qtype, entry);
+        self.eviction.touch(&key);
     }
-}
 
-/*
- * label = www.google.com
- * encoding = 3 | w | w | w | 6 | g | o | o | g | l | e | 3 | c | o | m.
- * rd_length = len(parts[0]) + len(parts[1]) ... + len(parts[n]) + n
- */
-fn compute_label_length(label: &str) -> u16 {
-    if label == "." {
-        return 0;
+    fn purge_expired(&mut self) -> usize {
+        let mut freed = 0;
+        let mut emptied = vec![];
+        for (domain, owner) in self.store.iter_mut() {
+            for (qtype, cached_rrs) in owner.iter_mut() {
+                let (_, bytes) = Shard::drop_expired(cached_rrs);
+                freed += bytes;
+                if cached_rrs.is_empty() {
+                    emptied.push(CacheKey::Positive(domain.clone(), *qtype));
+                }
+            }
+        }
+        for (domain, entries) in self.negative_store.iter() {
+            for (qtype, entry) in entries.iter() {
+                if entry.is_expired() {
+                    emptied.push(CacheKey::Negative(domain.clone(), *qtype));
+                }
+            }
+        }
+        self.shrink(0, freed);
+        for key in &emptied {
+            self.remove_entry(key);
+        }
+        RRDNS_CACHE_EVICTIONS
+            .with_label_values(&["expired"])
+            .inc_by(emptied.len() as i64);
+        emptied.len()
     }
-    label.split('.').fold(0, |acc, part| acc + part.len() + 1) as u16
+}
+
+// Rough number of bytes a cached record takes.
+fn record_size(rr: &ResourceRecord) -> usize {
+    size_of::<CachedResourceRecord>() + rr.name.len() + rr.r#type.serialize_rdata().len()
 }
 
 fn get_secs_since_epoch() -> u32 {
@@ -209,95 +517,138 @@ fn get_secs_since_epoch() -> u32 {
 mod tests {
 
     use super::{
-        compute_label_length, get_secs_since_epoch, Cache, CachedResourceRecord, InMemoryCache,
-        ResourceRecord,
+        get_secs_since_epoch, record_size, Cache, CacheKey, CachedResourceRecord, InMemoryCache,
+        NegativeCacheEntry, ResourceRecord, Shard, Store,
     };
-    use crate::business::models::{Class, QType, Type};
+    use crate::business::models::{Class, QType, ResponseCode, SOAData, Type};
+    use crate::config::{Config, Eviction};
     use std::net::Ipv4Addr;
+    use std::sync::Arc;
+    use std::thread;
 
     #[test]
-    fn test_compute_label_length_all_is_well() {
+    fn test_cache_with_root_a_filter() {
         // Arrange
-        let label = "www.google.com";
-        let expected_length: u16 = 1 + 3 + 1 + 6 + 1 + 3;
+        let cache = InMemoryCache::new(&Config::default());
 
         // Act
-        let actual_length = compute_label_length(label);
+        let actual_item = cache.get("a.root-servers.net.", &QType::A);
 
         // Assert
-        assert_eq!(expected_length, actual_length);
+        assert_eq!(actual_item.unwrap().len(), 1);
     }
 
     #[test]
-    fn test_compute_label_length_root() {
+    fn test_cache_with_root_aaaa_filter() {
         // Arrange
-        let label = ".";
-        let expected_length: u16 = 0;
+        let cache = InMemoryCache::new(&Config::default());
 
         // Act
-        let actual_length = compute_label_length(label);
+        let actual_item = cache.get("a.root-servers.net.", &QType::AAAA);
 
         // Assert
-        assert_eq!(expected_length, actual_length);
+        assert_eq!(actual_item.unwrap().len(), 1);
     }
 
     #[test]
-    fn test_cache_with_root_a_filter() {
+    fn test_cache_with_root_ns_filter() {
         // Arrange
-        let mut cache = InMemoryCache::new();
+        let cache = InMemoryCache::new(&Config::default());
 
         // Act
-        let actual_item = cache.get("a.root-servers.net.", &QType::A);
+        let actual_item = cache.get(".", &QType::NS);
 
         // Assert
-        assert_eq!(actual_item.unwrap().len(), 1);
+        assert_eq!(actual_item.unwrap().len(), 13);
     }
 
     #[test]
-    fn test_cache_with_root_aaaa_filter() {
+    fn test_cache_insert_and_get_item_from_cache() {
         // Arrange
-        let mut cache = InMemoryCache::new();
+        let cache = InMemoryCache::new(&Config::default());
 
         // Act
-        let actual_item = cache.get("a.root-servers.net.", &QType::AAAA);
+        let resource_record = &get_resource_records()[0];
+        let owner1 = &resource_record.name;
+        cache.insert2(&resource_record);
+        cache.insert2(&resource_record); // Duplicate must not be inserted.
 
         // Assert
+        let actual_item = cache.get(owner1, &QType::A);
+        assert!(!actual_item.is_none());
         assert_eq!(actual_item.unwrap().len(), 1);
     }
 
     #[test]
-    fn test_cache_with_root_ns_filter() {
+    fn test_cache_folds_case_of_owner_names() {
         // Arrange
-        let mut cache = InMemoryCache::new();
+        let cache = InMemoryCache::new(&Config::default());
+        // Authority of a referral to a query sent with its case mixed,  the owner is compressed
+        // against the question.
+        let referral = ResourceRecord {
+            name: String::from("KaRaNrY.cOm."),
+            class: Class::IN,
+            r#type: Type::NS(String::from("ns1.karanry.com.")),
+            ttl: 300,
+            rd_length: 0,
+        };
+        let mut other_case = referral.clone();
+        other_case.name = String::from("kArAnRy.CoM.");
 
         // Act
-        let actual_item = cache.get(".", &QType::NS);
+        cache.insert2(&referral);
+        cache.insert2(&other_case);
+        cache.insert_negative(
+            "WWW.KaRaNrY.cOm.",
+            &QType::AAAA,
+            NegativeCacheEntry::new(ResponseCode::NameError, vec![get_soa_record(300, 300)])
+                .unwrap(),
+        );
 
         // Assert
-        assert_eq!(actual_item.unwrap().len(), 13);
+        assert_eq!(
+            cache.get("karanry.com.", &QType::NS).map(|rrs| rrs.len()),
+            Some(1)
+        );
+        assert!(cache.get("KARANRY.COM", &QType::NS).is_some());
+        assert!(cache.get_negative("www.karanry.com.", &QType::A).is_some());
+        assert_eq!(
+            cache
+                .clone_cache()
+                .keys()
+                .filter(|name| name.contains("aran"))
+                .count(),
+            1
+        );
     }
 
     #[test]
-    fn test_cache_insert_and_get_item_from_cache() {
+    fn test_cache_insert_and_get_unknown_type() {
         // Arrange
-        let mut cache = InMemoryCache::new();
+        let cache = InMemoryCache::new(&Config::default());
+        let resource_record = ResourceRecord {
+            name: String::from("karanry.com."),
+            class: Class::IN,
+            r#type: Type::Unknown {
+                code: 65,
+                rdata: vec![0, 1, 0],
+            },
+            ttl: 300,
+            rd_length: 3,
+        };
 
         // Act
-        let resource_record = &get_resource_records()[0];
-        let owner1 = &resource_record.name;
         cache.insert2(&resource_record);
-        cache.insert2(&resource_record); // Duplicate must not be inserted.
 
         // Assert
-        let actual_item = cache.get(owner1, &QType::A);
-        assert!(!actual_item.is_none());
-        assert_eq!(actual_item.unwrap().len(), 1);
+        let actual_item = cache.get("karanry.com.", &QType::Unknown(65));
+        assert_eq!(actual_item.unwrap(), vec![resource_record]);
     }
 
     #[test]
     fn test_cache_missing_qtype_in_cache() {
         // Arrange
-        let mut cache = InMemoryCache::new();
+        let cache = InMemoryCache::new(&Config::default());
 
         // Act
         let actual = cache.get("a.root-servers.net.", &QType::TXT);
@@ -309,7 +660,7 @@ mod tests {
     #[test]
     fn test_cache_missing_owner_in_cache() {
         // Arrange
-        let mut cache = InMemoryCache::new();
+        let cache = InMemoryCache::new(&Config::default());
 
         // Act
         let actual = cache.get("non-existing-owner", &QType::TXT);
@@ -318,6 +669,253 @@ mod tests {
         assert!(actual.is_none());
     }
 
+    #[test]
+    fn test_negative_cache_entry_ttl_from_soa() {
+        // Arrange
+        let authority = vec![get_soa_record(3600, 300)];
+
+        // Act
+        let entry = NegativeCacheEntry::new(ResponseCode::NoError, authority).unwrap();
+
+        // Assert
+        assert_eq!(entry.ttl, 300);
+        assert_eq!(entry.remaining_authority()[0].ttl, 300);
+    }
+
+    #[test]
+    fn test_negative_cache_entry_needs_soa() {
+        assert!(NegativeCacheEntry::new(ResponseCode::NameError, get_resource_records()).is_none());
+    }
+
+    #[test]
+    fn test_cache_negative_nodata_only_for_its_qtype() {
+        // Arrange
+        let cache = InMemoryCache::new(&Config::default());
+        let entry =
+            NegativeCacheEntry::new(ResponseCode::NoError, vec![get_soa_record(60, 60)]).unwrap();
+
+        // Act
+        cache.insert_negative("karanry.com", &QType::AAAA, entry.clone());
+
+        // Assert
+        assert_eq!(
+            cache.get_negative("karanry.com.", &QType::AAAA),
+            Some(entry)
+        );
+        assert!(cache.get_negative("karanry.com.", &QType::A).is_none());
+    }
+
+    #[test]
+    fn test_cache_negative_nxdomain_for_every_qtype() {
+        // Arrange
+        let cache = InMemoryCache::new(&Config::default());
+        let entry =
+            NegativeCacheEntry::new(ResponseCode::NameError, vec![get_soa_record(60, 60)]).unwrap();
+
+        // Act
+        cache.insert_negative("nope.karanry.com.", &QType::A, entry.clone());
+
+        // Assert
+        assert_eq!(
+            cache.get_negative("nope.karanry.com.", &QType::MX),
+            Some(entry)
+        );
+    }
+
+    #[test]
+    fn test_cache_negative_expired_entry() {
+        // Arrange
+        let cache = InMemoryCache::new(&Config::default());
+        let mut entry =
+            NegativeCacheEntry::new(ResponseCode::NameError, vec![get_soa_record(60, 60)]).unwrap();
+        entry.last_refreshed_at -= 61;
+
+        // Act
+        cache.insert_negative("nope.karanry.com.", &QType::A, entry);
+
+        // Assert
+        assert!(cache.get_negative("nope.karanry.com.", &QType::A).is_none());
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used_rrset() {
+        // Arrange
+        let mut cache = Shard::new(3, usize::MAX, &Eviction::Lru);
+        let root = get_soa_record(60, 60);
+        cache
+            .pinned
+            .insert(CacheKey::Positive(root.name.clone(), QType::SOA));
+        cache.insert2(&root);
+        let records = get_a_records(&["a.karanry.com.", "b.karanry.com.", "c.karanry.com."]);
+        cache.insert2(&records[0]);
+        cache.insert2(&records[1]);
+        cache.get("a.karanry.com.", &QType::A);
+
+        // Act
+        cache.insert2(&records[2]);
+
+        // Assert
+        assert!(cache.get("a.karanry.com.", &QType::A).is_some());
+        assert!(cache.get("b.karanry.com.", &QType::A).is_none());
+        assert!(cache.get("c.karanry.com.", &QType::A).is_some());
+        assert!(cache.get("karanry.com.", &QType::SOA).is_some());
+    }
+
+    #[test]
+    fn test_cache_evicts_least_frequently_used_rrset() {
+        // Arrange
+        let mut cache = Shard::new(2, usize::MAX, &Eviction::Lfu);
+        let records = get_a_records(&["a.karanry.com.", "b.karanry.com.", "c.karanry.com."]);
+        cache.insert2(&records[0]);
+        cache.insert2(&records[1]);
+        cache.get("a.karanry.com.", &QType::A);
+        cache.get("b.karanry.com.", &QType::A);
+        cache.get("b.karanry.com.", &QType::A);
+
+        // Act
+        cache.insert2(&records[2]);
+
+        // Assert
+        assert!(cache.get("a.karanry.com.", &QType::A).is_none());
+        assert!(cache.get("b.karanry.com.", &QType::A).is_some());
+    }
+
+    #[test]
+    fn test_cache_evicts_over_byte_budget() {
+        // Arrange
+        let records = get_a_records(&["a.karanry.com.", "b.karanry.com."]);
+        let mut cache = Shard::new(usize::MAX, record_size(&records[0]), &Eviction::Lru);
+        cache.insert2(&records[0]);
+
+        // Act
+        cache.insert2(&records[1]);
+
+        // Assert
+        assert!(cache.get("a.karanry.com.", &QType::A).is_none());
+        assert!(cache.get("b.karanry.com.", &QType::A).is_some());
+        assert!(cache.bytes <= cache.max_bytes);
+    }
+
+    #[test]
+    fn test_cache_purge_expired() {
+        // Arrange
+        let mut cache = Shard::new(usize::MAX, usize::MAX, &Eviction::Lru);
+        let records = get_a_records(&["a.karanry.com.", "b.karanry.com."]);
+        cache.insert2(&records[0]);
+        cache.insert2(&records[1]);
+        let entry =
+            NegativeCacheEntry::new(ResponseCode::NameError, vec![get_soa_record(60, 60)]).unwrap();
+        cache.insert_negative("nope.karanry.com.", &QType::A, entry);
+        cache
+            .store
+            .get_mut("a.karanry.com.")
+            .unwrap()
+            .get_mut(&QType::A)
+            .unwrap()[0]
+            .last_refreshed_at -= 301;
+        cache
+            .negative_store
+            .get_mut("nope.karanry.com.")
+            .unwrap()
+            .get_mut(&QType::A)
+            .unwrap()
+            .last_refreshed_at -= 61;
+
+        // Act
+        let purged = cache.purge_expired();
+
+        // Assert
+        assert_eq!(purged, 2);
+        assert_eq!(cache.entries, 1);
+        assert_eq!(cache.bytes, record_size(&records[1]));
+        assert!(!cache.store.contains_key("a.karanry.com."));
+        assert!(cache.negative_store.is_empty());
+    }
+
+    #[test]
+    fn test_cache_shared_between_threads() {
+        // Arrange
+        let cache = Arc::new(InMemoryCache::new(&Config::default()));
+        let names: Vec<String> = (0..64).map(|i| format!("{}.karanry.com.", i)).collect();
+
+        // Act
+        let writers: Vec<_> = names
+            .chunks(16)
+            .map(|chunk| {
+                let cache = cache.clone();
+                let records = get_a_records(&chunk.iter().map(String::as_str).collect::<Vec<_>>());
+                thread::spawn(move || records.iter().for_each(|rr| cache.insert2(rr)))
+            })
+            .collect();
+        writers
+            .into_iter()
+            .for_each(|writer| writer.join().unwrap());
+
+        // Assert
+        for name in &names {
+            assert_eq!(cache.get(name, &QType::A).unwrap().len(), 1);
+        }
+        assert_eq!(cache.clone_cache().len(), names.len() + 14);
+    }
+
+    #[test]
+    fn test_cache_restore_keeps_refresh_time() {
+        // Arrange
+        let cache = InMemoryCache::new(&Config::default());
+        let mut store = Store::new();
+        let crr = CachedResourceRecord {
+            rr: get_a_records(&["karanry.com."]).remove(0),
+            last_refreshed_at: get_secs_since_epoch() - 100,
+        };
+        store
+            .entry("karanry.com.".to_string())
+            .or_default()
+            .insert(QType::A, vec![crr.clone(), crr]);
+
+        // Act
+        let restored = cache.restore(store);
+
+        // Assert
+        assert_eq!(restored, 2);
+        let cached_rrs = &cache.clone_cache()["karanry.com."][&QType::A];
+        assert_eq!(cached_rrs.len(), 1);
+        assert_eq!(
+            cached_rrs[0].last_refreshed_at,
+            get_secs_since_epoch() - 100
+        );
+    }
+
+    fn get_a_records(names: &[&str]) -> Vec<ResourceRecord> {
+        names
+            .iter()
+            .map(|name| ResourceRecord {
+                name: name.to_string(),
+                class: Class::IN,
+                r#type: Type::A(Ipv4Addr::new(23, 23, 23, 23)),
+                ttl: 300,
+                rd_length: 4,
+            })
+            .collect()
+    }
+
+    fn get_soa_record(ttl: u32, minimum: u32) -> ResourceRecord {
+        ResourceRecord {
+            name: String::from("karanry.com."),
+            class: Class::IN,
+            r#type: Type::SOA(SOAData {
+                mname: String::from("ns1.karanry.com."),
+                rname: String::from("hostmaster.karanry.com."),
+                serial: 2020010101,
+                refresh_in_secs: 7200,
+                retry_in_secs: 3600,
+                expire_in_secs: 1209600,
+                minimum,
+            }),
+            ttl,
+            rd_length: 0,
+        }
+    }
+
     fn get_resource_records() -> Vec<ResourceRecord> {
         vec![
             ResourceRecord {
@@ -0,0 +1,132 @@
+use crate::business::models::QType;
+use crate::config::Eviction;
+use std::collections::{BTreeMap, HashMap};
+
+// RRset or negative answer the cache holds,  the unit that gets evicted.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CacheKey {
+    Positive(String, QType),
+    Negative(String, QType),
+}
+
+// Picks the entry to evict when the cache is over its limits.
+pub trait EvictionPolicy {
+    // Entry was inserted or read.
+    fn touch(&mut self, key: &CacheKey);
+    fn remove(&mut self, key: &CacheKey);
+    fn victim(&self) -> Option<CacheKey>;
+}
+
+pub fn new_policy(eviction: &Eviction) -> Box<dyn EvictionPolicy + Send> {
+    match eviction {
+        Eviction::Lru => Box::new(Lru::default()),
+        Eviction::Lfu => Box::new(Lfu::default()),
+    }
+}
+
+// Least recently used entry goes first.
+#[derive(Default)]
+pub struct Lru {
+    tick: u64,
+    ticks: HashMap<CacheKey, u64>,
+    order: BTreeMap<u64, CacheKey>,
+}
+
+impl EvictionPolicy for Lru {
+    fn touch(&mut self, key: &CacheKey) {
+        self.tick += 1;
+        if let Some(tick) = self.ticks.insert(key.clone(), self.tick) {
+            self.order.remove(&tick);
+        }
+        self.order.insert(self.tick, key.clone());
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(tick) = self.ticks.remove(key) {
+            self.order.remove(&tick);
+        }
+    }
+
+    fn victim(&self) -> Option<CacheKey> {
+        self.order.values().next().cloned()
+    }
+}
+
+// Least frequently used entry goes first,  the least recently used one among equals.
+#[derive(Default)]
+pub struct Lfu {
+    tick: u64,
+    uses: HashMap<CacheKey, (u64, u64)>,
+    order: BTreeMap<(u64, u64), CacheKey>,
+}
+
+impl EvictionPolicy for Lfu {
+    fn touch(&mut self, key: &CacheKey) {
+        self.tick += 1;
+        let count = match self.uses.get(key) {
+            Some(uses) => {
+                self.order.remove(uses);
+                uses.0 + 1
+            }
+            None => 1,
+        };
+        self.uses.insert(key.clone(), (count, self.tick));
+        self.order.insert((count, self.tick), key.clone());
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(uses) = self.uses.remove(key) {
+            self.order.remove(&uses);
+        }
+    }
+
+    fn victim(&self) -> Option<CacheKey> {
+        self.order.values().next().cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheKey, EvictionPolicy, Lfu, Lru};
+    use crate::business::models::QType;
+
+    fn key(domain: &str) -> CacheKey {
+        CacheKey::Positive(domain.to_string(), QType::A)
+    }
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        // Arrange
+        let mut lru = Lru::default();
+        lru.touch(&key("a."));
+        lru.touch(&key("b."));
+        lru.touch(&key("c."));
+
+        // Act
+        lru.touch(&key("a."));
+        lru.remove(&key("b."));
+
+        // Assert
+        assert_eq!(lru.victim(), Some(key("c.")));
+    }
+
+    #[test]
+    fn test_lfu_evicts_least_frequently_used() {
+        // Arrange
+        let mut lfu = Lfu::default();
+        lfu.touch(&key("a."));
+        lfu.touch(&key("a."));
+        lfu.touch(&key("b."));
+        lfu.touch(&key("b."));
+        lfu.touch(&key("c."));
+
+        // Act
+        let first = lfu.victim();
+        lfu.remove(&key("c."));
+        let second = lfu.victim();
+
+        // Assert
+        assert_eq!(first, Some(key("c.")));
+        assert_eq!(second, Some(key("a.")));
+    }
+}
@@ -0,0 +1,178 @@
+use super::{get_secs_since_epoch, Store};
+use crate::error::SnapshotError;
+use md5;
+use serde_json;
+use std::fs;
+use std::path::Path;
+
+// First line of a snapshot,  followed by the version and the md5 of the rest of the file:
+//   rrdns-cache-snapshot 1 5d41402abc4b2a76b9719d911017c592
+//   {"com.":{"NS":[...]}, ...}
+const MAGIC: &str = "rrdns-cache-snapshot";
+const VERSION: u32 = 1;
+
+// Records that expired are left out.  The file is written next to path and renamed over it,  so
+// a crash while saving leaves the previous snapshot intact.
+pub fn save(store: &Store, path: &Path) -> Result<usize, SnapshotError> {
+    let mut live = Store::new();
+    let mut saved = 0;
+    for (domain, owner) in store {
+        for (qtype, cached_rrs) in owner {
+            let cached_rrs: Vec<_> = cached_rrs
+                .iter()
+                .filter(|crr| !crr.is_expired())
+                .cloned()
+                .collect();
+            if !cached_rrs.is_empty() {
+                saved += cached_rrs.len();
+                live.entry(domain.clone())
+                    .or_default()
+                    .insert(*qtype, cached_rrs);
+            }
+        }
+    }
+
+    let body =
+        serde_json::to_string(&live).map_err(|err| SnapshotError::Format(err.to_string()))?;
+    let contents = format!("{} {} {:x}\n{}", MAGIC, VERSION, md5::compute(&body), body);
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(saved)
+}
+
+// Records that expired while rrdns was down are dropped.  Refresh times in the future,  left by a
+// clock that was ahead,  are moved back to now so that records do not outlive their TTL.
+pub fn load(path: &Path) -> Result<Store, SnapshotError> {
+    let contents = fs::read_to_string(path)?;
+    parse(&contents)
+}
+
+fn parse(contents: &str) -> Result<Store, SnapshotError> {
+    let (header, body) = match contents.find('\n') {
+        Some(end_of_header) => (&contents[..end_of_header], &contents[end_of_header + 1..]),
+        None => return Err(SnapshotError::Format("missing header".to_string())),
+    };
+    let checksum = match header.split(' ').collect::<Vec<&str>>()[..] {
+        [magic, version, checksum] if magic == MAGIC && version == VERSION.to_string() => checksum,
+        _ => return Err(SnapshotError::Version(header.to_string())),
+    };
+    if format!("{:x}", md5::compute(body)) != checksum {
+        return Err(SnapshotError::Checksum);
+    }
+
+    let mut store: Store =
+        serde_json::from_str(body).map_err(|err| SnapshotError::Format(err.to_string()))?;
+    let now = get_secs_since_epoch();
+    for owner in store.values_mut() {
+        for cached_rrs in owner.values_mut() {
+            cached_rrs.iter_mut().for_each(|crr| {
+                crr.last_refreshed_at = crr.last_refreshed_at.min(now);
+            });
+            cached_rrs.retain(|crr| !crr.is_expired());
+        }
+        owner.retain(|_, cached_rrs| !cached_rrs.is_empty());
+    }
+    store.retain(|_, owner| !owner.is_empty());
+    Ok(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_secs_since_epoch, parse, save, Store};
+    use crate::business::models::{Class, QType, ResourceRecord, Type};
+    use crate::error::SnapshotError;
+    use crate::resolver::cache::CachedResourceRecord;
+    use std::env;
+    use std::fs;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_snapshot_round_trip_drops_expired_records() {
+        // Arrange
+        let path = env::temp_dir().join(format!("rrdns-snapshot-{}.json", std::process::id()));
+        let now = get_secs_since_epoch();
+        let mut store = Store::new();
+        store
+            .entry("karanry.com.".to_string())
+            .or_default()
+            .insert(QType::A, vec![cached_a_record(300, now - 10)]);
+        store
+            .entry("old.karanry.com.".to_string())
+            .or_default()
+            .insert(QType::A, vec![cached_a_record(300, now - 301)]);
+
+        // Act
+        let saved = save(&store, &path).unwrap();
+        let loaded = parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // Assert
+        assert_eq!(saved, 1);
+        assert_eq!(loaded.len(), 1);
+        let cached_rrs = &loaded["karanry.com."][&QType::A];
+        assert_eq!(cached_rrs[0].last_refreshed_at, now - 10);
+    }
+
+    #[test]
+    fn test_snapshot_refresh_time_from_the_future() {
+        // Arrange
+        let now = get_secs_since_epoch();
+        let body =
+            serde_json::to_string(&single_record_store(cached_a_record(300, now + 3600))).unwrap();
+        let contents = format!("rrdns-cache-snapshot 1 {:x}\n{}", md5::compute(&body), body);
+
+        // Act
+        let loaded = parse(&contents).unwrap();
+
+        // Assert
+        assert!(loaded["karanry.com."][&QType::A][0].last_refreshed_at <= get_secs_since_epoch());
+    }
+
+    #[test]
+    fn test_snapshot_checksum_mismatch() {
+        // Arrange
+        let body = serde_json::to_string(&single_record_store(cached_a_record(300, 0))).unwrap();
+        let contents = format!(
+            "rrdns-cache-snapshot 1 {:x}\n{}",
+            md5::compute(&body),
+            body.replace("23", "24")
+        );
+
+        // Act
+        let actual = parse(&contents);
+
+        // Assert
+        assert!(matches!(actual, Err(SnapshotError::Checksum)));
+    }
+
+    #[test]
+    fn test_snapshot_unknown_version() {
+        assert!(matches!(
+            parse("rrdns-cache-snapshot 2 00\n{}"),
+            Err(SnapshotError::Version(_))
+        ));
+    }
+
+    fn single_record_store(crr: CachedResourceRecord) -> Store {
+        let mut store = Store::new();
+        store
+            .entry("karanry.com.".to_string())
+            .or_default()
+            .insert(QType::A, vec![crr]);
+        store
+    }
+
+    fn cached_a_record(ttl: u32, last_refreshed_at: u32) -> CachedResourceRecord {
+        CachedResourceRecord {
+            rr: ResourceRecord {
+                name: String::from("karanry.com."),
+                class: Class::IN,
+                r#type: Type::A(Ipv4Addr::new(23, 23, 23, 23)),
+                ttl,
+                rd_length: 4,
+            },
+            last_refreshed_at,
+        }
+    }
+}
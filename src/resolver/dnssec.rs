@@ -0,0 +1,1586 @@
+// https://tools.ietf.org/html/rfc4035 5
+// Validation of responses against the chain of trust that starts at the root trust anchors.
+use super::Resolver;
+use crate::business::models::{
+    write_labels, DNSKEYData, DNSQuery, DNSQueryResponse, DSData, NSEC3Data, NSECData, QType,
+    RRSIGData, RRSet, ResourceRecord, ResponseCode, Type,
+};
+use crate::error::FetchError;
+use log::{debug, info};
+use ring::digest;
+use ring::signature::{
+    RsaPublicKeyComponents, UnparsedPublicKey, ECDSA_P256_SHA256_FIXED, ED25519,
+    RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+};
+use std::cmp::Ordering;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// https://www.iana.org/assignments/dns-sec-alg-numbers
+const ALGORITHM_RSASHA256: u8 = 8;
+const ALGORITHM_ECDSAP256SHA256: u8 = 13;
+const ALGORITHM_ED25519: u8 = 15;
+
+// https://www.iana.org/assignments/ds-rr-types
+const DIGEST_SHA1: u8 = 1;
+const DIGEST_SHA256: u8 = 2;
+const DIGEST_SHA384: u8 = 4;
+
+// https://tools.ietf.org/html/rfc4034 2.1.1
+const DNSKEY_FLAG_ZONE: u16 = 0x0100;
+const DNSKEY_PROTOCOL: u8 = 3;
+
+// https://tools.ietf.org/html/rfc5155 3.1.2.1
+const NSEC3_FLAG_OPT_OUT: u8 = 0x01;
+const NSEC3_HASH_SHA1: u8 = 1;
+
+// https://tools.ietf.org/html/rfc9276 3.2
+// Zones using more NSEC3 iterations than this are treated as insecure.
+const NSEC3_MAX_ITERATIONS: u16 = 150;
+
+// Bogus zones are not looked at again for this long.
+// https://tools.ietf.org/html/rfc4035 4.7
+const BOGUS_TTL: u32 = 60;
+
+// https://tools.ietf.org/html/rfc4035 4.3
+#[derive(Debug, Clone, PartialEq)]
+pub enum Security {
+    Secure,
+    Insecure,
+    Bogus(String),
+}
+
+impl Security {
+    // Security of a response made of parts with the given securities.
+    fn and(self, other: Security) -> Security {
+        match (self, other) {
+            (Security::Bogus(reason), _) | (_, Security::Bogus(reason)) => Security::Bogus(reason),
+            (Security::Insecure, _) | (_, Security::Insecure) => Security::Insecure,
+            (Security::Secure, Security::Secure) => Security::Secure,
+        }
+    }
+}
+
+// What the chain of trust says about the zone a name lives in.
+#[derive(Debug, Clone)]
+pub enum ZoneKeys {
+    // The closest enclosing zone is signed and its DNSKEY RRset is trusted.
+    Secure { zone: String, keys: RRSet },
+    // Name is at or below a delegation that has no DS.
+    Insecure,
+    Bogus(String),
+}
+
+impl Resolver {
+    // Sets AD on responses that validated and turns bogus ones into SERVFAIL.
+    pub(super) async fn validate(
+        &self,
+        query: &DNSQuery,
+        result: Result<DNSQueryResponse, FetchError>,
+    ) -> Result<DNSQueryResponse, FetchError> {
+        let (mut response, is_name_error) = match result {
+            Ok(response) => (response, false),
+            Err(FetchError::QueryError(response))
+                if response.query.header.response_code == ResponseCode::NameError =>
+            {
+                (response, true)
+            }
+            result => return result,
+        };
+
+        match self
+            .response_security(query, &response, is_name_error)
+            .await
+        {
+            Security::Secure => response.query.header.is_authentic_data = true,
+            Security::Insecure => response.query.header.is_authentic_data = false,
+            Security::Bogus(reason) => {
+                info!(
+                    "{} validate: bogus {} reason={}",
+                    query.header.id,
+                    query.to_dig(),
+                    reason
+                );
+                return Err(FetchError::QueryError(response.server_failure()));
+            }
+        }
+
+        if is_name_error {
+            Err(FetchError::QueryError(response))
+        } else {
+            Ok(response)
+        }
+    }
+
+    async fn response_security(
+        &self,
+        query: &DNSQuery,
+        response: &DNSQueryResponse,
+        is_name_error: bool,
+    ) -> Security {
+        let qname = &query.questions[0].qname;
+        let qtype = query.questions[0].qtype;
+
+        let mut security = Security::Secure;
+        let mut expanded = vec![];
+        for (owner, rrset) in rrsets(&response.answers) {
+            let (rrset_security, wildcard_labels) =
+                self.rrset_security(&owner, &rrset, &response.answers).await;
+            security = security.and(rrset_security);
+            if let Some(labels) = wildcard_labels {
+                expanded.push((owner, labels));
+            }
+        }
+
+        // https://tools.ietf.org/html/rfc4035 5.3.4
+        // A signed wildcard could be replayed for any name below it,  the authority has to prove
+        // that no closer name exists.
+        if !expanded.is_empty() {
+            security = security.and(self.nsec_security(&response.authority).await);
+            for (owner, labels) in expanded {
+                security = security.and(wildcard_proof(&owner, labels, &response.authority));
+            }
+        }
+
+        // https://tools.ietf.org/html/rfc4035 5.4
+        // The name at the end of the CNAME chain has to be proven to have no data of qtype,  or
+        // not to exist,  as qname itself would.
+        let target = cname_target(qname, &response.answers);
+        let answered = response
+            .answers
+            .iter()
+            .any(|rr| names_equal(&rr.name, &target) && rr.r#type.to_qtype() == qtype);
+        if !answered && qtype != QType::CNAME {
+            let denial_security = self
+                .denial_security(&target, qtype, is_name_error, &response.authority)
+                .await;
+            security = security.and(denial_security);
+        }
+
+        security
+    }
+
+    // https://tools.ietf.org/html/rfc4035 5.4
+    async fn denial_security(
+        &self,
+        qname: &str,
+        qtype: QType,
+        is_name_error: bool,
+        authority: &[ResourceRecord],
+    ) -> Security {
+        // https://tools.ietf.org/html/rfc4035 2.2
+        // NS RRsets of referrals are not signed by the parent side,  they are left out.
+        let denial_rrsets: Vec<(String, Vec<&ResourceRecord>)> = rrsets(authority)
+            .into_iter()
+            .filter(|(_, rrset)| {
+                let rrset_qtype = rrset[0].r#type.to_qtype();
+                rrset_qtype == QType::SOA
+                    || rrset_qtype == QType::NSEC
+                    || rrset_qtype == QType::NSEC3
+            })
+            .collect();
+        if denial_rrsets.is_empty() {
+            return match self.zone_keys(qname).await {
+                ZoneKeys::Secure { .. } => Security::Bogus(format!(
+                    "negative answer for {} {} is not signed",
+                    qname, qtype
+                )),
+                ZoneKeys::Insecure => Security::Insecure,
+                ZoneKeys::Bogus(reason) => Security::Bogus(reason),
+            };
+        }
+
+        let mut security = Security::Secure;
+        for (owner, rrset) in denial_rrsets {
+            security = security.and(self.rrset_security(&owner, &rrset, authority).await.0);
+        }
+        if security != Security::Secure {
+            return security;
+        }
+
+        match (is_name_error, existence(qname, authority)) {
+            (true, Existence::NameError) => Security::Secure,
+            // https://tools.ietf.org/html/rfc6840 4.1
+            // The NSEC of a delegation comes from the parent side,  it only speaks for DS.
+            (false, Existence::Types(types)) if is_delegation(&types) && qtype != QType::DS => {
+                Security::Bogus(format!(
+                    "delegation NSEC of {} cannot deny {}",
+                    qname, qtype
+                ))
+            }
+            (false, Existence::Types(types))
+                if !types.contains(&qtype) && !types.contains(&QType::CNAME) =>
+            {
+                Security::Secure
+            }
+            (_, Existence::OptOut) | (_, Existence::Unverifiable) => Security::Insecure,
+            _ => Security::Bogus(format!(
+                "denial of existence of {} {} is not proven",
+                qname, qtype
+            )),
+        }
+    }
+
+    // Security of the NSEC and NSEC3 RRsets in authority.
+    async fn nsec_security(&self, authority: &[ResourceRecord]) -> Security {
+        let mut security = Security::Secure;
+        for (owner, rrset) in rrsets(authority) {
+            let rrset_qtype = rrset[0].r#type.to_qtype();
+            if rrset_qtype == QType::NSEC || rrset_qtype == QType::NSEC3 {
+                security = security.and(self.rrset_security(&owner, &rrset, authority).await.0);
+            }
+        }
+        security
+    }
+
+    // https://tools.ietf.org/html/rfc4035 5.3
+    // Also gives the labels of the RRSIG that verified rrset when it says rrset was expanded from
+    // a wildcard.
+    async fn rrset_security(
+        &self,
+        owner: &str,
+        rrset: &[&ResourceRecord],
+        section: &[ResourceRecord],
+    ) -> (Security, Option<u8>) {
+        let qtype = rrset[0].r#type.to_qtype();
+        let rrsigs: Vec<&RRSIGData> = section
+            .iter()
+            .filter(|rr| names_equal(&rr.name, owner))
+            .filter_map(|rr| match &rr.r#type {
+                Type::RRSIG(rrsig) if rrsig.type_covered == qtype => Some(rrsig),
+                _ => None,
+            })
+            .collect();
+
+        if rrsigs.is_empty() {
+            let security = match self.zone_keys(owner).await {
+                ZoneKeys::Secure { zone, .. } => {
+                    Security::Bogus(format!("{} {} is not signed but {} is", owner, qtype, zone))
+                }
+                ZoneKeys::Insecure => Security::Insecure,
+                ZoneKeys::Bogus(reason) => Security::Bogus(reason),
+            };
+            return (security, None);
+        }
+
+        let mut reason = String::new();
+        for rrsig in rrsigs {
+            match self.zone_keys(&rrsig.signer_name).await {
+                ZoneKeys::Secure { zone, keys } => {
+                    if !names_equal(&zone, &rrsig.signer_name) {
+                        reason = format!(
+                            "{} {} is signed by {} which is not a zone",
+                            owner, qtype, rrsig.signer_name
+                        );
+                        continue;
+                    }
+                    match verify_rrset(rrset, owner, rrsig, &keys, now()) {
+                        Ok(()) if (rrsig.labels as usize) < label_count(owner) => {
+                            return (Security::Secure, Some(rrsig.labels))
+                        }
+                        Ok(()) => return (Security::Secure, None),
+                        Err(err) => reason = format!("{} {}: {}", owner, qtype, err),
+                    }
+                }
+                ZoneKeys::Insecure => return (Security::Insecure, None),
+                ZoneKeys::Bogus(zone_reason) => reason = zone_reason,
+            }
+        }
+        (Security::Bogus(reason), None)
+    }
+
+    // Walks the chain of trust from the root down to the closest enclosing zone of name.
+    pub(super) async fn zone_keys(&self, name: &str) -> ZoneKeys {
+        let name = normalize(name);
+        if let Some(zone_keys) = self.cached_zone_keys(&name) {
+            return zone_keys;
+        }
+
+        let mut zone_keys = match self.cached_zone_keys(".") {
+            Some(zone_keys) => zone_keys,
+            None => {
+                let anchors = self.root_trust_anchors.iter().collect::<Vec<&DSData>>();
+                let (zone_keys, ttl) = self.trusted_dnskeys(".", &anchors).await;
+                self.cache_zone_keys(".", &zone_keys, ttl);
+                zone_keys
+            }
+        };
+
+        for child in descendants(&name) {
+            zone_keys = match self.cached_zone_keys(&child) {
+                Some(cached) => cached,
+                None => match zone_keys {
+                    ZoneKeys::Secure { zone, keys } => {
+                        let (child_keys, ttl) = self.child_zone_keys(&child, zone, keys).await;
+                        self.cache_zone_keys(&child, &child_keys, ttl);
+                        child_keys
+                    }
+                    other => return other,
+                },
+            };
+        }
+
+        zone_keys
+    }
+
+    // https://tools.ietf.org/html/rfc4035 5.2
+    // Keys of child given the trusted keys of the zone right above it.
+    async fn child_zone_keys(&self, child: &str, zone: String, keys: RRSet) -> (ZoneKeys, u32) {
+        let ds_query = self.build_query(child.to_string(), QType::DS);
+        let response = match self.lookup(&ds_query).await {
+            Ok(response) | Err(FetchError::QueryError(response)) => response,
+            Err(err) => {
+                return (
+                    ZoneKeys::Bogus(format!("DS of {} could not be fetched: {:?}", child, err)),
+                    BOGUS_TTL,
+                )
+            }
+        };
+
+        let ds_rrset: Vec<&ResourceRecord> = response
+            .answers
+            .iter()
+            .filter(|rr| rr.r#type.to_qtype() == QType::DS && names_equal(&rr.name, child))
+            .collect();
+        if !ds_rrset.is_empty() {
+            if let Err(err) = verify_signed_rrset(&ds_rrset, child, &response.answers, &keys) {
+                return (
+                    ZoneKeys::Bogus(format!("DS of {}: {}", child, err)),
+                    BOGUS_TTL,
+                );
+            }
+            let ds: Vec<&DSData> = ds_rrset
+                .iter()
+                .filter_map(|rr| match &rr.r#type {
+                    Type::DS(ds) => Some(ds),
+                    _ => None,
+                })
+                .collect();
+            return self.trusted_dnskeys(child, &ds).await;
+        }
+
+        // No DS,  the parent has to prove it.
+        let ttl = min_ttl(&response.authority).unwrap_or(BOGUS_TTL);
+        for (owner, rrset) in rrsets(&response.authority) {
+            let rrset_qtype = rrset[0].r#type.to_qtype();
+            if rrset_qtype != QType::NSEC && rrset_qtype != QType::NSEC3 {
+                continue;
+            }
+            if let Err(err) = verify_signed_rrset(&rrset, &owner, &response.authority, &keys) {
+                return (
+                    ZoneKeys::Bogus(format!("no DS of {}: {}", child, err)),
+                    BOGUS_TTL,
+                );
+            }
+        }
+        match existence(child, &response.authority) {
+            Existence::Types(types) if types.contains(&QType::DS) => (
+                ZoneKeys::Bogus(format!("DS of {} exists but was not returned", child)),
+                BOGUS_TTL,
+            ),
+            Existence::Types(types) if is_delegation(&types) => {
+                debug!("zone_keys: {} is an insecure delegation", child);
+                (ZoneKeys::Insecure, ttl)
+            }
+            // Not a zone cut,  names below child still belong to zone.
+            Existence::Types(_) | Existence::NameError => (ZoneKeys::Secure { zone, keys }, ttl),
+            Existence::OptOut | Existence::Unverifiable => (ZoneKeys::Insecure, ttl),
+            Existence::Unproven => (
+                ZoneKeys::Bogus(format!("absence of DS of {} is not proven", child)),
+                BOGUS_TTL,
+            ),
+        }
+    }
+
+    // DNSKEY RRset of zone,  trusted when it is signed by a key that one of ds points at.
+    async fn trusted_dnskeys(&self, zone: &str, ds: &[&DSData]) -> (ZoneKeys, u32) {
+        let ds: Vec<&&DSData> = ds
+            .iter()
+            .filter(|ds| {
+                is_supported_algorithm(ds.algorithm) && is_supported_digest(ds.digest_type)
+            })
+            .collect();
+        // https://tools.ietf.org/html/rfc4035 5.2
+        if ds.is_empty() {
+            return (ZoneKeys::Insecure, BOGUS_TTL);
+        }
+
+        let dnskey_query = self.build_query(zone.to_string(), QType::DNSKEY);
+        let response = match self.lookup(&dnskey_query).await {
+            Ok(response) => response,
+            Err(err) => {
+                return (
+                    ZoneKeys::Bogus(format!(
+                        "DNSKEY of {} could not be fetched: {:?}",
+                        zone, err
+                    )),
+                    BOGUS_TTL,
+                )
+            }
+        };
+        let dnskey_rrset: Vec<&ResourceRecord> = response
+            .answers
+            .iter()
+            .filter(|rr| rr.r#type.to_qtype() == QType::DNSKEY && names_equal(&rr.name, zone))
+            .collect();
+
+        for dnskey_rr in &dnskey_rrset {
+            let dnskey = match &dnskey_rr.r#type {
+                Type::DNSKEY(dnskey) => dnskey,
+                _ => continue,
+            };
+            if !ds.iter().any(|ds| ds_matches(zone, dnskey, ds)) {
+                continue;
+            }
+            let key = vec![(*dnskey_rr).clone()];
+            if verify_signed_rrset(&dnskey_rrset, zone, &response.answers, &key).is_ok() {
+                let keys: RRSet = dnskey_rrset.iter().map(|rr| (*rr).clone()).collect();
+                let ttl = min_ttl(&keys).unwrap_or(BOGUS_TTL);
+                return (
+                    ZoneKeys::Secure {
+                        zone: normalize(zone),
+                        keys,
+                    },
+                    ttl,
+                );
+            }
+        }
+
+        (
+            ZoneKeys::Bogus(format!(
+                "no DNSKEY of {} is signed with a key in its DS",
+                zone
+            )),
+            BOGUS_TTL,
+        )
+    }
+
+    fn cached_zone_keys(&self, name: &str) -> Option<ZoneKeys> {
+        let zone_keys = self.zone_keys.lock().unwrap();
+        match zone_keys.get(name) {
+            Some((zone_keys, expires_at)) if *expires_at > Instant::now() => {
+                Some(zone_keys.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn cache_zone_keys(&self, name: &str, keys: &ZoneKeys, ttl: u32) {
+        let expires_at = Instant::now() + Duration::from_secs(ttl as u64);
+        let mut zone_keys = self.zone_keys.lock().unwrap();
+        zone_keys.insert(name.to_string(), (keys.clone(), expires_at));
+    }
+}
+
+// Verifies rrset with any RRSIG over it in section made by one of keys.
+fn verify_signed_rrset(
+    rrset: &[&ResourceRecord],
+    owner: &str,
+    section: &[ResourceRecord],
+    keys: &[ResourceRecord],
+) -> Result<(), String> {
+    let qtype = rrset[0].r#type.to_qtype();
+    let mut result = Err(format!("{} {} is not signed", owner, qtype));
+    for rr in section.iter().filter(|rr| names_equal(&rr.name, owner)) {
+        if let Type::RRSIG(rrsig) = &rr.r#type {
+            if rrsig.type_covered != qtype {
+                continue;
+            }
+            result = verify_rrset(rrset, owner, rrsig, keys, now());
+            if result.is_ok() {
+                return result;
+            }
+        }
+    }
+    result
+}
+
+// https://tools.ietf.org/html/rfc4035 5.3.1
+// Checks rrsig over rrset,  owned by owner,  against the DNSKEY records in keys.  now is in secs
+// since epoch.
+pub fn verify_rrset(
+    rrset: &[&ResourceRecord],
+    owner: &str,
+    rrsig: &RRSIGData,
+    keys: &[ResourceRecord],
+    now: u32,
+) -> Result<(), String> {
+    if !is_subdomain(owner, &rrsig.signer_name) {
+        return Err(format!("signer {} is not above owner", rrsig.signer_name));
+    }
+    if rrsig.labels as usize > label_count(owner) {
+        return Err(format!("RRSIG labels {} is too large", rrsig.labels));
+    }
+    // https://tools.ietf.org/html/rfc1982
+    if (now.wrapping_sub(rrsig.inception) as i32) < 0 {
+        return Err("RRSIG is not valid yet".to_string());
+    }
+    if (rrsig.expiration.wrapping_sub(now) as i32) < 0 {
+        return Err("RRSIG expired".to_string());
+    }
+
+    let message = signed_data(rrset, owner, rrsig);
+    let mut result = Err(format!("no DNSKEY with key tag {}", rrsig.key_tag));
+    for key_rr in keys
+        .iter()
+        .filter(|rr| names_equal(&rr.name, &rrsig.signer_name))
+    {
+        let dnskey = match &key_rr.r#type {
+            Type::DNSKEY(dnskey) => dnskey,
+            _ => continue,
+        };
+        if dnskey.algorithm != rrsig.algorithm
+            || dnskey.protocol != DNSKEY_PROTOCOL
+            || dnskey.flags & DNSKEY_FLAG_ZONE == 0
+            || key_tag(dnskey) != rrsig.key_tag
+        {
+            continue;
+        }
+        result = verify_signature(
+            rrsig.algorithm,
+            &dnskey.public_key,
+            &message,
+            &rrsig.signature,
+        );
+        if result.is_ok() {
+            return result;
+        }
+    }
+    result
+}
+
+// https://tools.ietf.org/html/rfc4034 3.1.8.1
+fn signed_data(rrset: &[&ResourceRecord], owner: &str, rrsig: &RRSIGData) -> Vec<u8> {
+    // https://tools.ietf.org/html/rfc4035 5.3.2
+    // Records expanded from a wildcard are signed with the wildcard as owner.
+    let owner = normalize(owner);
+    let owner = if (rrsig.labels as usize) < label_count(&owner) {
+        let labels = labels(&owner);
+        format!(
+            "*.{}.",
+            labels[labels.len() - rrsig.labels as usize..].join(".")
+        )
+    } else {
+        owner
+    };
+    let owner_wire = write_labels(&owner);
+
+    let mut rdatas: Vec<Vec<u8>> = rrset.iter().map(|rr| rr.r#type.canonical_rdata()).collect();
+    rdatas.sort();
+    rdatas.dedup();
+
+    let mut result = rrsig.serialize_without_signature();
+    for rdata in rdatas {
+        result.extend_from_slice(&owner_wire);
+        result.extend_from_slice(&rrsig.type_covered.to_u16().to_be_bytes());
+        result.extend_from_slice(&rrset[0].class.to_u16().to_be_bytes());
+        result.extend_from_slice(&rrsig.original_ttl.to_be_bytes());
+        result.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        result.extend_from_slice(&rdata);
+    }
+    result
+}
+
+fn verify_signature(
+    algorithm: u8,
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), String> {
+    let verified = match algorithm {
+        ALGORITHM_RSASHA256 => {
+            let (e, n) = rsa_public_key(public_key).ok_or("malformed RSA public key")?;
+            RsaPublicKeyComponents { n, e }.verify(
+                &RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+                message,
+                signature,
+            )
+        }
+        ALGORITHM_ECDSAP256SHA256 => {
+            // https://tools.ietf.org/html/rfc6605 4
+            let mut key = vec![0x04];
+            key.extend_from_slice(public_key);
+            UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, key).verify(message, signature)
+        }
+        ALGORITHM_ED25519 => {
+            UnparsedPublicKey::new(&ED25519, public_key).verify(message, signature)
+        }
+        _ => return Err(format!("algorithm {} is not supported", algorithm)),
+    };
+    verified.map_err(|_| "signature does not verify".to_string())
+}
+
+// https://tools.ietf.org/html/rfc3110 2
+// Returns (exponent, modulus) without leading zeros.
+fn rsa_public_key(public_key: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (exponent_length, rest) = match public_key.first()? {
+        0 => (
+            u16::from_be_bytes([*public_key.get(1)?, *public_key.get(2)?]) as usize,
+            &public_key[3..],
+        ),
+        length => (*length as usize, &public_key[1..]),
+    };
+    if exponent_length == 0 || rest.len() <= exponent_length {
+        return None;
+    }
+    let (exponent, modulus) = rest.split_at(exponent_length);
+    Some((
+        without_leading_zeros(exponent),
+        without_leading_zeros(modulus),
+    ))
+}
+
+fn without_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn is_supported_algorithm(algorithm: u8) -> bool {
+    algorithm == ALGORITHM_RSASHA256
+        || algorithm == ALGORITHM_ECDSAP256SHA256
+        || algorithm == ALGORITHM_ED25519
+}
+
+fn is_supported_digest(digest_type: u8) -> bool {
+    digest_type == DIGEST_SHA1 || digest_type == DIGEST_SHA256 || digest_type == DIGEST_SHA384
+}
+
+// https://tools.ietf.org/html/rfc4034 appendix B
+pub fn key_tag(dnskey: &DNSKEYData) -> u16 {
+    let mut accumulator: u32 = 0;
+    for (i, octet) in dnskey.serialize().iter().enumerate() {
+        accumulator += if i & 1 == 1 {
+            *octet as u32
+        } else {
+            (*octet as u32) << 8
+        };
+    }
+    accumulator += (accumulator >> 16) & 0xffff;
+    (accumulator & 0xffff) as u16
+}
+
+// https://tools.ietf.org/html/rfc4034 5.1.4
+pub fn ds_matches(owner: &str, dnskey: &DNSKEYData, ds: &DSData) -> bool {
+    if ds.key_tag != key_tag(dnskey) || ds.algorithm != dnskey.algorithm {
+        return false;
+    }
+    let algorithm = match ds.digest_type {
+        DIGEST_SHA1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        DIGEST_SHA256 => &digest::SHA256,
+        DIGEST_SHA384 => &digest::SHA384,
+        _ => return false,
+    };
+    let mut context = digest::Context::new(algorithm);
+    context.update(&write_labels(&normalize(owner)));
+    context.update(&dnskey.serialize());
+    context.finish().as_ref() == ds.digest.as_slice()
+}
+
+// What the NSEC or NSEC3 records of a negative answer say about a name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Existence {
+    // Name exists,  or is matched by a wildcard,  with these types.
+    Types(Vec<QType>),
+    // Neither name nor a wildcard that could match it exist.
+    NameError,
+    // Name is inside an NSEC3 opt-out span,  an unsigned delegation may hide there.
+    OptOut,
+    // NSEC3 parameters are too expensive to check.
+    Unverifiable,
+    Unproven,
+}
+
+// https://tools.ietf.org/html/rfc4035 5.4 and https://tools.ietf.org/html/rfc5155 8
+pub fn existence(name: &str, authority: &[ResourceRecord]) -> Existence {
+    let nsecs: Vec<(&str, &NSECData)> = authority
+        .iter()
+        .filter_map(|rr| match &rr.r#type {
+            Type::NSEC(nsec) => Some((rr.name.as_str(), nsec)),
+            _ => None,
+        })
+        .collect();
+    if !nsecs.is_empty() {
+        return nsec_existence(name, &nsecs);
+    }
+
+    let nsec3s: Vec<(&str, &NSEC3Data)> = authority
+        .iter()
+        .filter_map(|rr| match &rr.r#type {
+            Type::NSEC3(nsec3) if nsec3.hash_algorithm == NSEC3_HASH_SHA1 => {
+                Some((rr.name.as_str(), nsec3))
+            }
+            _ => None,
+        })
+        .collect();
+    if !nsec3s.is_empty() {
+        return nsec3_existence(name, &nsec3s);
+    }
+
+    Existence::Unproven
+}
+
+fn nsec_existence(name: &str, nsecs: &[(&str, &NSECData)]) -> Existence {
+    if let Some((_, nsec)) = nsecs.iter().find(|(owner, _)| names_equal(owner, name)) {
+        return Existence::Types(nsec.types.clone());
+    }
+
+    let covering = nsecs
+        .iter()
+        .find(|(owner, nsec)| covers(owner, &nsec.next_domain_name, name));
+    let (owner, nsec) = match covering {
+        Some(covering) => covering,
+        None => return Existence::Unproven,
+    };
+    // https://tools.ietf.org/html/rfc4035 3.1.3.2
+    // An empty non-terminal has names below it but no NSEC of its own.
+    if is_subdomain(&nsec.next_domain_name, name) {
+        return Existence::Types(vec![]);
+    }
+
+    let closest_encloser = [
+        common_ancestor(name, owner),
+        common_ancestor(name, &nsec.next_domain_name),
+    ]
+    .iter()
+    .max_by_key(|ancestor| label_count(ancestor))
+    .cloned()
+    .unwrap();
+    let wildcard = wildcard_of(&closest_encloser);
+    if let Some((_, nsec)) = nsecs
+        .iter()
+        .find(|(owner, _)| names_equal(owner, &wildcard))
+    {
+        return Existence::Types(nsec.types.clone());
+    }
+    if nsecs
+        .iter()
+        .any(|(owner, nsec)| covers(owner, &nsec.next_domain_name, &wildcard))
+    {
+        return Existence::NameError;
+    }
+    Existence::Unproven
+}
+
+// https://tools.ietf.org/html/rfc5155 8.3
+fn nsec3_existence(name: &str, nsec3s: &[(&str, &NSEC3Data)]) -> Existence {
+    let (_, params) = nsec3s[0];
+    if params.iterations > NSEC3_MAX_ITERATIONS {
+        return Existence::Unverifiable;
+    }
+    let hash = |name: &str| nsec3_hash(name, &params.salt, params.iterations);
+    let matching = |hashed: &[u8]| {
+        nsec3s
+            .iter()
+            .find(|(owner, _)| nsec3_owner_hash(owner).as_deref() == Some(hashed))
+            .map(|(_, nsec3)| *nsec3)
+    };
+    let covering = |hashed: &[u8]| {
+        nsec3s
+            .iter()
+            .find(|(owner, nsec3)| match nsec3_owner_hash(owner) {
+                Some(owner_hash) => covers_hash(&owner_hash, &nsec3.next_hashed_owner_name, hashed),
+                None => false,
+            })
+            .map(|(_, nsec3)| *nsec3)
+    };
+
+    if let Some(nsec3) = matching(&hash(name)) {
+        return Existence::Types(nsec3.types.clone());
+    }
+
+    // Closest encloser proof.
+    let mut next_closer = normalize(name);
+    for closest_encloser in ancestors(name) {
+        if matching(&hash(&closest_encloser)).is_none() {
+            next_closer = closest_encloser;
+            continue;
+        }
+        let next_closer_nsec3 = match covering(&hash(&next_closer)) {
+            Some(nsec3) => nsec3,
+            None => return Existence::Unproven,
+        };
+        if next_closer_nsec3.flags & NSEC3_FLAG_OPT_OUT != 0 {
+            return Existence::OptOut;
+        }
+        let wildcard = wildcard_of(&closest_encloser);
+        if let Some(nsec3) = matching(&hash(&wildcard)) {
+            return Existence::Types(nsec3.types.clone());
+        }
+        if covering(&hash(&wildcard)).is_some() {
+            return Existence::NameError;
+        }
+        return Existence::Unproven;
+    }
+    Existence::Unproven
+}
+
+// https://tools.ietf.org/html/rfc4035 5.3.4 and https://tools.ietf.org/html/rfc5155 8.8
+// Whether authority proves that owner was rightly expanded from the wildcard whose RRSIG has
+// labels labels:  an NSEC covers owner,  or an NSEC3 covers the next closer name.
+fn wildcard_proof(owner: &str, labels: u8, authority: &[ResourceRecord]) -> Security {
+    let covered = authority.iter().any(|rr| match &rr.r#type {
+        Type::NSEC(nsec) => covers(&rr.name, &nsec.next_domain_name, owner),
+        _ => false,
+    });
+    if covered {
+        return Security::Secure;
+    }
+
+    let nsec3s: Vec<(&str, &NSEC3Data)> = authority
+        .iter()
+        .filter_map(|rr| match &rr.r#type {
+            Type::NSEC3(nsec3) if nsec3.hash_algorithm == NSEC3_HASH_SHA1 => {
+                Some((rr.name.as_str(), nsec3))
+            }
+            _ => None,
+        })
+        .collect();
+    if let Some((_, params)) = nsec3s.first() {
+        if params.iterations > NSEC3_MAX_ITERATIONS {
+            return Security::Insecure;
+        }
+        let next_closer = closest_labels(owner, labels as usize + 1);
+        let hashed = nsec3_hash(&next_closer, &params.salt, params.iterations);
+        let covered =
+            nsec3s
+                .iter()
+                .any(|(nsec3_owner, nsec3)| match nsec3_owner_hash(nsec3_owner) {
+                    Some(owner_hash) => {
+                        covers_hash(&owner_hash, &nsec3.next_hashed_owner_name, &hashed)
+                    }
+                    None => false,
+                });
+        if covered {
+            return Security::Secure;
+        }
+    }
+
+    Security::Bogus(format!(
+        "{} is expanded from a wildcard but the absence of a closer match is not proven",
+        owner
+    ))
+}
+
+// https://tools.ietf.org/html/rfc5155 5
+pub fn nsec3_hash(name: &str, salt: &[u8], iterations: u16) -> Vec<u8> {
+    let mut hashed = write_labels(&normalize(name));
+    for _ in 0..=iterations {
+        let mut context = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+        context.update(&hashed);
+        context.update(salt);
+        hashed = context.finish().as_ref().to_vec();
+    }
+    hashed
+}
+
+// First label of an NSEC3 owner is the base32hex encoded hash.
+fn nsec3_owner_hash(owner: &str) -> Option<Vec<u8>> {
+    decode_base32hex(labels(owner).first()?)
+}
+
+// https://tools.ietf.org/html/rfc4648 7
+fn decode_base32hex(encoded: &str) -> Option<Vec<u8>> {
+    let mut result = vec![];
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in encoded.chars() {
+        let value = c.to_digit(32)?;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(result)
+}
+
+// True when name sorts strictly between owner and next.  The last NSEC of a zone wraps around
+// to the apex.
+fn covers(owner: &str, next: &str, name: &str) -> bool {
+    let after_owner = canonical_cmp(owner, name) == Ordering::Less;
+    let before_next = canonical_cmp(name, next) == Ordering::Less;
+    if canonical_cmp(owner, next) == Ordering::Less {
+        after_owner && before_next
+    } else {
+        after_owner || before_next
+    }
+}
+
+fn covers_hash(owner: &[u8], next: &[u8], hashed: &[u8]) -> bool {
+    if owner < next {
+        owner < hashed && hashed < next
+    } else {
+        owner < hashed || hashed < next
+    }
+}
+
+// https://tools.ietf.org/html/rfc4034 6.1
+pub fn canonical_cmp(a: &str, b: &str) -> Ordering {
+    let a = normalize(a);
+    let b = normalize(b);
+    labels(&a)
+        .iter()
+        .rev()
+        .map(|label| label.as_bytes())
+        .cmp(labels(&b).iter().rev().map(|label| label.as_bytes()))
+}
+
+// https://tools.ietf.org/html/rfc4035 5.4
+// Types of a delegation point as the parent sees it:  NS without the SOA of a zone apex.
+fn is_delegation(types: &[QType]) -> bool {
+    types.contains(&QType::NS) && !types.contains(&QType::SOA)
+}
+
+// Name the CNAME records in answers lead to from name,  name itself when there are none.
+fn cname_target(name: &str, answers: &[ResourceRecord]) -> String {
+    let mut target = normalize(name);
+    // Every CNAME is followed at most once,  loops end there.
+    for _ in 0..answers.len() {
+        let next = answers.iter().find_map(|rr| match &rr.r#type {
+            Type::CNAME(cname) if names_equal(&rr.name, &target) => Some(normalize(cname)),
+            _ => None,
+        });
+        match next {
+            Some(next) => target = next,
+            None => break,
+        }
+    }
+    target
+}
+
+// Groups records into RRsets by owner and type,  RRSIGs are left out.
+fn rrsets(records: &[ResourceRecord]) -> Vec<(String, Vec<&ResourceRecord>)> {
+    let mut result: Vec<(String, Vec<&ResourceRecord>)> = vec![];
+    for rr in records {
+        let qtype = rr.r#type.to_qtype();
+        if qtype == QType::RRSIG {
+            continue;
+        }
+        let owner = normalize(&rr.name);
+        match result
+            .iter_mut()
+            .find(|(o, rrset)| *o == owner && rrset[0].r#type.to_qtype() == qtype)
+        {
+            Some((_, rrset)) => rrset.push(rr),
+            None => result.push((owner, vec![rr])),
+        }
+    }
+    result
+}
+
+fn min_ttl(records: &[ResourceRecord]) -> Option<u32> {
+    records.iter().map(|rr| rr.ttl).min()
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_secs() as u32
+}
+
+// Lower case and fully qualified.
+fn normalize(name: &str) -> String {
+    let name = name.to_lowercase();
+    if name.ends_with('.') {
+        name
+    } else {
+        format!("{}.", name)
+    }
+}
+
+fn names_equal(a: &str, b: &str) -> bool {
+    normalize(a) == normalize(b)
+}
+
+fn labels(name: &str) -> Vec<&str> {
+    name.split('.').filter(|label| !label.is_empty()).collect()
+}
+
+// https://tools.ietf.org/html/rfc4034 3.1.3
+fn label_count(name: &str) -> usize {
+    let labels = labels(name);
+    match labels.first() {
+        Some(&"*") => labels.len() - 1,
+        _ => labels.len(),
+    }
+}
+
+fn is_subdomain(name: &str, ancestor: &str) -> bool {
+    let name = normalize(name);
+    let ancestor = normalize(ancestor);
+    ancestor == "." || name == ancestor || name.ends_with(&format!(".{}", ancestor))
+}
+
+// Ancestors of name,  closest first,  ending with the root.
+fn ancestors(name: &str) -> Vec<String> {
+    let name = normalize(name);
+    let labels = labels(&name);
+    (1..=labels.len())
+        .map(|i| {
+            if i == labels.len() {
+                ".".to_string()
+            } else {
+                format!("{}.", labels[i..].join("."))
+            }
+        })
+        .collect()
+}
+
+// Names from the top level domain down to name itself.
+fn descendants(name: &str) -> Vec<String> {
+    let name = normalize(name);
+    if name == "." {
+        return vec![];
+    }
+    let mut result: Vec<String> = ancestors(&name).into_iter().rev().skip(1).collect();
+    result.push(name);
+    result
+}
+
+fn common_ancestor(a: &str, b: &str) -> String {
+    let a = normalize(a);
+    let b = normalize(b);
+    let common: Vec<&str> = labels(&a)
+        .iter()
+        .rev()
+        .zip(labels(&b).iter().rev())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| *x)
+        .collect();
+    if common.is_empty() {
+        return ".".to_string();
+    }
+    let mut common = common;
+    common.reverse();
+    format!("{}.", common.join("."))
+}
+
+// Name made of the last count labels of name.
+fn closest_labels(name: &str, count: usize) -> String {
+    let name = normalize(name);
+    let labels = labels(&name);
+    if count == 0 {
+        return ".".to_string();
+    }
+    format!(
+        "{}.",
+        labels[labels.len().saturating_sub(count)..].join(".")
+    )
+}
+
+fn wildcard_of(name: &str) -> String {
+    if name == "." {
+        "*.".to_string()
+    } else {
+        format!("*.{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        canonical_cmp, ds_matches, existence, key_tag, nsec3_hash, signed_data, verify_rrset,
+        Existence, Security, ZoneKeys,
+    };
+    use crate::business::models::{
+        Class, DNSKEYData, DNSQuery, DNSQueryResponse, DSData, NSEC3Data, NSECData, QType,
+        RRSIGData, ResourceRecord, Type,
+    };
+    use crate::config::Config;
+    use crate::resolver::Resolver;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use std::cmp::Ordering;
+    use std::net::Ipv4Addr;
+
+    // 2030-01-01 and 2000-01-01.
+    const EXPIRATION: u32 = 1893456000;
+    const INCEPTION: u32 = 946684800;
+    const NOW: u32 = 1700000000;
+
+    fn hex(encoded: &str) -> Vec<u8> {
+        (0..encoded.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&encoded[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn record(name: &str, r#type: Type) -> ResourceRecord {
+        ResourceRecord {
+            name: name.to_string(),
+            r#type,
+            class: Class::IN,
+            ttl: 3600,
+            rd_length: 0,
+        }
+    }
+
+    fn dnskey(zone: &str, algorithm: u8, public_key: Vec<u8>) -> ResourceRecord {
+        record(
+            zone,
+            Type::DNSKEY(DNSKEYData {
+                flags: 256,
+                protocol: 3,
+                algorithm,
+                public_key,
+            }),
+        )
+    }
+
+    fn rrsig(type_covered: QType, algorithm: u8, labels: u8, key: &ResourceRecord) -> RRSIGData {
+        let key_tag = match &key.r#type {
+            Type::DNSKEY(dnskey) => key_tag(dnskey),
+            _ => panic!("not a DNSKEY"),
+        };
+        RRSIGData {
+            type_covered,
+            algorithm,
+            labels,
+            original_ttl: 3600,
+            expiration: EXPIRATION,
+            inception: INCEPTION,
+            key_tag,
+            signer_name: key.name.clone(),
+            signature: vec![],
+        }
+    }
+
+    fn nsec(owner: &str, next: &str, types: Vec<QType>) -> ResourceRecord {
+        record(
+            owner,
+            Type::NSEC(NSECData {
+                next_domain_name: next.to_string(),
+                types,
+            }),
+        )
+    }
+
+    fn nsec3(owner: &str, next: Vec<u8>, flags: u8, types: Vec<QType>) -> ResourceRecord {
+        record(
+            owner,
+            Type::NSEC3(NSEC3Data {
+                hash_algorithm: 1,
+                flags,
+                iterations: 12,
+                salt: vec![0xaa, 0xbb, 0xcc, 0xdd],
+                next_hashed_owner_name: next,
+                types,
+            }),
+        )
+    }
+
+    #[test]
+    fn test_dnssec_key_tag_and_ds_digest() {
+        // Arrange
+        // https://tools.ietf.org/html/rfc4034 5.4
+        let dnskey = DNSKEYData {
+            flags: 256,
+            protocol: 3,
+            algorithm: 5,
+            public_key: hex(concat!(
+                "01039e8a247418e318903b215a848acfd5f37f026bd4062db26c774c690968d5",
+                "d56df8bfda91e6f36d9a279888f41333357c5e6029990d10fdf5663062a51276",
+                "3326980a615ddbf17a05ddfcce7e5fb3abcca05a31b0957452d4521e83870789",
+                "063115bf97f6c308ccf57cdc9ce7fe10f6ed1bd0cc0660038c50dcdb0feb963c",
+                "2f17",
+            )),
+        };
+        let ds = DSData {
+            key_tag: 60485,
+            algorithm: 5,
+            digest_type: 1,
+            digest: hex("2bb183af5f22588179a53b0a98631fad1a292118"),
+        };
+
+        // Act
+        let actual_key_tag = key_tag(&dnskey);
+        let actual_matches = ds_matches("dskey.example.com.", &dnskey, &ds);
+
+        // Assert
+        assert_eq!(actual_key_tag, 60485);
+        assert!(actual_matches);
+        assert!(!ds_matches("other.example.com.", &dnskey, &ds));
+    }
+
+    #[test]
+    fn test_dnssec_verify_rrset_rsasha256() {
+        // Arrange
+        // Signed outside of rrdns with a 1024 bit key.
+        let key = dnskey(
+            "example.net.",
+            8,
+            hex(concat!(
+                "03010001956efba09650239904ae8524ba0d57bae243a5ccadd709a3bb1a5e63",
+                "5522fd8d86c2f769af11d0088e1cfae45932c61e0da279d285c069fb51d9395c",
+                "1517f4f239043c601a5cf5d0cb70824acc97f14aea48a70f7514a7b00a655817",
+                "d1cb3dae8c9f40f541f2d843e62466abb5a1dcfbf887c54130620a15dac5a47b",
+                "c3b25b89",
+            )),
+        );
+        let mut signature = rrsig(QType::A, 8, 3, &key);
+        signature.signature = hex(concat!(
+            "20b4763ac763c0c7534b5b8ea55f9f08679a2773bf81f17b575f10ac1c805afc",
+            "472c0982183d6ace2884478f64e77bfd2eeaaef44f660633f69d9948ca1fbc77",
+            "775458a5474266d0f320c9ca5f4ce6d64709a8d73d1b6edf5c67914977cf22db",
+            "a7bf05d461b3413a54c6c2aebbea7b44f0d152fd41c8f3c35ea582f180981b27",
+        ));
+        // Out of canonical order and in mixed case on purpose.
+        let a92 = record("WWW.Example.net.", Type::A(Ipv4Addr::new(192, 0, 2, 92)));
+        let a91 = record("www.example.net.", Type::A(Ipv4Addr::new(192, 0, 2, 91)));
+
+        // Act
+        let actual = verify_rrset(&[&a92, &a91], "www.example.net.", &signature, &[key], NOW);
+
+        // Assert
+        assert_eq!(actual, Ok(()));
+    }
+
+    #[test]
+    fn test_dnssec_verify_rrset_ecdsap256_rejects_tampering() {
+        // Arrange
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap();
+        let key = dnskey("example.", 13, key_pair.public_key().as_ref()[1..].to_vec());
+        let a = record("www.example.", Type::A(Ipv4Addr::new(192, 0, 2, 1)));
+        let mut signature = rrsig(QType::A, 13, 2, &key);
+        let message = signed_data(&[&a], "www.example.", &signature);
+        signature.signature = key_pair.sign(&rng, &message).unwrap().as_ref().to_vec();
+        let tampered = record("www.example.", Type::A(Ipv4Addr::new(192, 0, 2, 2)));
+
+        // Act
+        let actual = verify_rrset(
+            &[&a],
+            "www.example.",
+            &signature,
+            std::slice::from_ref(&key),
+            NOW,
+        );
+        let actual_tampered = verify_rrset(&[&tampered], "www.example.", &signature, &[key], NOW);
+
+        // Assert
+        assert_eq!(actual, Ok(()));
+        assert!(actual_tampered.is_err());
+    }
+
+    #[test]
+    fn test_dnssec_verify_rrset_ed25519_wildcard() {
+        // Arrange
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let key = dnskey("example.", 15, key_pair.public_key().as_ref().to_vec());
+        let wildcard = record("*.example.", Type::A(Ipv4Addr::new(192, 0, 2, 1)));
+        let mut signature = rrsig(QType::A, 15, 1, &key);
+        signature.signature = key_pair
+            .sign(&signed_data(&[&wildcard], "*.example.", &signature))
+            .as_ref()
+            .to_vec();
+        let expanded = record("anything.example.", Type::A(Ipv4Addr::new(192, 0, 2, 1)));
+
+        // Act
+        let actual = verify_rrset(&[&expanded], "anything.example.", &signature, &[key], NOW);
+
+        // Assert
+        assert_eq!(actual, Ok(()));
+    }
+
+    #[test]
+    fn test_dnssec_verify_rrset_expired() {
+        // Arrange
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let key = dnskey("example.", 15, key_pair.public_key().as_ref().to_vec());
+        let a = record("example.", Type::A(Ipv4Addr::new(192, 0, 2, 1)));
+        let mut signature = rrsig(QType::A, 15, 1, &key);
+        signature.signature = key_pair
+            .sign(&signed_data(&[&a], "example.", &signature))
+            .as_ref()
+            .to_vec();
+
+        // Act
+        let actual = verify_rrset(&[&a], "example.", &signature, &[key], EXPIRATION + 1);
+
+        // Assert
+        assert_eq!(actual, Err("RRSIG expired".to_string()));
+    }
+
+    #[test]
+    fn test_dnssec_nsec3_hash() {
+        // https://tools.ietf.org/html/rfc5155 appendix A
+        let salt = [0xaa, 0xbb, 0xcc, 0xdd];
+        assert_eq!(
+            nsec3_hash("example.", &salt, 12),
+            hex("065368abeed7ec6e9feba96b8c8bc3e8b791f716")
+        );
+        assert_eq!(
+            nsec3_hash("a.example.", &salt, 12),
+            hex("196dd8c3306783a8190f52c262d2b7e5e836e7f5")
+        );
+    }
+
+    #[test]
+    fn test_dnssec_canonical_order() {
+        // https://tools.ietf.org/html/rfc4034 6.1
+        let ordered = [
+            "example.",
+            "a.example.",
+            "yljkjljk.a.example.",
+            "Z.a.example.",
+            "zABC.a.EXAMPLE.",
+            "z.example.",
+            "*.z.example.",
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(
+                canonical_cmp(pair[0], pair[1]),
+                Ordering::Less,
+                "{:?}",
+                pair
+            );
+        }
+    }
+
+    #[test]
+    fn test_dnssec_existence_nsec() {
+        // Arrange
+        let authority = vec![
+            nsec("example.", "a.example.", vec![QType::SOA, QType::NS]),
+            nsec("a.example.", "c.example.", vec![QType::A, QType::NSEC]),
+        ];
+
+        // Act
+        let actual_name_error = existence("b.example.", &authority);
+        let actual_no_data = existence("a.example.", &authority);
+
+        // Assert
+        assert_eq!(actual_name_error, Existence::NameError);
+        assert_eq!(
+            actual_no_data,
+            Existence::Types(vec![QType::A, QType::NSEC])
+        );
+    }
+
+    #[test]
+    fn test_dnssec_existence_nsec_wildcard_not_covered() {
+        // Arrange
+        let authority = vec![nsec("a.example.", "c.example.", vec![QType::A])];
+
+        // Act
+        let actual = existence("b.example.", &authority);
+
+        // Assert
+        assert_eq!(actual, Existence::Unproven);
+    }
+
+    #[test]
+    fn test_dnssec_existence_nsec3() {
+        // Arrange
+        // Closest encloser example. matches,  everything else is covered by the second record.
+        let apex = nsec3(
+            "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom.example.",
+            hex("065368abeed7ec6e9feba96b8c8bc3e8b791f717"),
+            0,
+            vec![QType::NS, QType::SOA],
+        );
+        let rest = |flags| {
+            nsec3(
+                "0p9mhaveqvm6t7vbl5lop2u3t2rp3ton.example.",
+                hex("065368abeed7ec6e9feba96b8c8bc3e8b791f716"),
+                flags,
+                vec![QType::A],
+            )
+        };
+
+        // Act
+        let actual_name_error = existence("b.example.", &[apex.clone(), rest(0)]);
+        let actual_opt_out = existence("b.example.", &[apex.clone(), rest(1)]);
+        let actual_no_data = existence("example.", &[apex, rest(0)]);
+
+        // Assert
+        assert_eq!(actual_name_error, Existence::NameError);
+        assert_eq!(actual_opt_out, Existence::OptOut);
+        assert_eq!(
+            actual_no_data,
+            Existence::Types(vec![QType::NS, QType::SOA])
+        );
+    }
+
+    // Zone example. signed with a fresh key,  trusted by resolver.
+    struct SignedZone {
+        key_pair: Ed25519KeyPair,
+        key: ResourceRecord,
+    }
+
+    impl SignedZone {
+        fn new() -> Self {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+            let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+            let key = dnskey("example.", 15, key_pair.public_key().as_ref().to_vec());
+            SignedZone { key_pair, key }
+        }
+
+        // Names are looked up in the zone,  so that the chain of trust is not walked for them.
+        fn trust(&self, resolver: &Resolver, names: &[&str]) {
+            let zone_keys = ZoneKeys::Secure {
+                zone: "example.".to_string(),
+                keys: vec![self.key.clone()],
+            };
+            resolver.cache_zone_keys("example.", &zone_keys, 3600);
+            for name in names {
+                resolver.cache_zone_keys(name, &zone_keys, 3600);
+            }
+        }
+
+        // rrset followed by its RRSIG,  signed as owned by signed_owner.
+        fn sign(&self, rrset: Vec<ResourceRecord>, signed_owner: &str) -> Vec<ResourceRecord> {
+            let type_covered = rrset[0].r#type.to_qtype();
+            let labels = signed_owner.trim_start_matches("*.").split('.').count() as u8 - 1;
+            let mut signature = rrsig(type_covered, 15, labels, &self.key);
+            let signed: Vec<ResourceRecord> = rrset
+                .iter()
+                .map(|rr| record(signed_owner, rr.r#type.clone()))
+                .collect();
+            let signed: Vec<&ResourceRecord> = signed.iter().collect();
+            signature.signature = self
+                .key_pair
+                .sign(&signed_data(&signed, signed_owner, &signature))
+                .as_ref()
+                .to_vec();
+            let owner = rrset[0].name.clone();
+            let mut records = rrset;
+            records.push(record(&owner, Type::RRSIG(signature)));
+            records
+        }
+    }
+
+    fn response(query: &DNSQuery, answers: Vec<ResourceRecord>) -> DNSQueryResponse {
+        DNSQueryResponse {
+            query: query.clone(),
+            answers,
+            authority: vec![],
+            additional: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dnssec_wildcard_answer_needs_proof() {
+        // Arrange
+        let zone = SignedZone::new();
+        let resolver = Resolver::new(&Config::default());
+        zone.trust(&resolver, &[]);
+        let query = DNSQuery::for_test("anything.example.", QType::A);
+        let answers = zone.sign(
+            vec![record(
+                "anything.example.",
+                Type::A(Ipv4Addr::new(192, 0, 2, 1)),
+            )],
+            "*.example.",
+        );
+        let mut proven = response(&query, answers.clone());
+        proven.authority = zone.sign(
+            vec![nsec("a.example.", "b.example.", vec![QType::A])],
+            "a.example.",
+        );
+        let mut elsewhere = response(&query, answers.clone());
+        elsewhere.authority = zone.sign(
+            vec![nsec("b.example.", "c.example.", vec![QType::A])],
+            "b.example.",
+        );
+        let stripped = response(&query, answers);
+
+        // Act
+        let actual_proven = resolver.response_security(&query, &proven, false).await;
+        let actual_elsewhere = resolver.response_security(&query, &elsewhere, false).await;
+        let actual_stripped = resolver.response_security(&query, &stripped, false).await;
+
+        // Assert
+        assert_eq!(actual_proven, Security::Secure);
+        assert!(matches!(actual_elsewhere, Security::Bogus(_)));
+        assert!(matches!(actual_stripped, Security::Bogus(_)));
+    }
+
+    #[tokio::test]
+    async fn test_dnssec_cname_target_needs_denial() {
+        // Arrange
+        let zone = SignedZone::new();
+        let resolver = Resolver::new(&Config::default());
+        zone.trust(&resolver, &["gone.example."]);
+        let query = DNSQuery::for_test("www.example.", QType::A);
+        let answers = zone.sign(
+            vec![record(
+                "www.example.",
+                Type::CNAME("gone.example.".to_string()),
+            )],
+            "www.example.",
+        );
+        let mut proven = response(&query, answers.clone());
+        proven.authority = zone.sign(
+            vec![nsec("gone.example.", "z.example.", vec![QType::AAAA])],
+            "gone.example.",
+        );
+        let stripped = response(&query, answers);
+
+        // Act
+        let actual_proven = resolver.response_security(&query, &proven, false).await;
+        let actual_stripped = resolver.response_security(&query, &stripped, false).await;
+
+        // Assert
+        assert_eq!(actual_proven, Security::Secure);
+        assert!(matches!(actual_stripped, Security::Bogus(_)));
+    }
+
+    #[tokio::test]
+    async fn test_dnssec_delegation_nsec_denies_only_ds() {
+        // Arrange
+        let zone = SignedZone::new();
+        let resolver = Resolver::new(&Config::default());
+        zone.trust(&resolver, &["child.example."]);
+        let authority = zone.sign(
+            vec![nsec(
+                "child.example.",
+                "z.example.",
+                vec![QType::NS, QType::NSEC, QType::RRSIG],
+            )],
+            "child.example.",
+        );
+        let a_query = DNSQuery::for_test("child.example.", QType::A);
+        let mut a_response = response(&a_query, vec![]);
+        a_response.authority = authority.clone();
+        let ds_query = DNSQuery::for_test("child.example.", QType::DS);
+        let mut ds_response = response(&ds_query, vec![]);
+        ds_response.authority = authority;
+
+        // Act
+        let actual_a = resolver
+            .response_security(&a_query, &a_response, false)
+            .await;
+        let actual_ds = resolver
+            .response_security(&ds_query, &ds_response, false)
+            .await;
+
+        // Assert
+        assert!(matches!(actual_a, Security::Bogus(_)));
+        assert_eq!(actual_ds, Security::Secure);
+    }
+
+    #[tokio::test]
+    async fn test_dnssec_unsigned_ns_answer_is_bogus() {
+        // Arrange
+        let zone = SignedZone::new();
+        let resolver = Resolver::new(&Config::default());
+        zone.trust(&resolver, &[]);
+        let query = DNSQuery::for_test("example.", QType::NS);
+        let answers = vec![record("example.", Type::NS("ns.example.".to_string()))];
+
+        // Act
+        let actual = resolver
+            .response_security(&query, &response(&query, answers), false)
+            .await;
+
+        // Assert
+        assert!(matches!(actual, Security::Bogus(_)));
+    }
+}
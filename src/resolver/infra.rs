@@ -0,0 +1,414 @@
+use super::zone::{in_zone, normalize};
+use crate::business::models::{DNSQueryResponse, Type};
+use rand::random;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Round trip time assumed for servers not asked yet,  those measured faster are asked first.
+const UNKNOWN_RTT: Duration = Duration::from_millis(376);
+// Counted as the round trip time of a server that failed to answer,  so that it sinks below the
+// ones that do.
+const FAILURE_RTT: Duration = Duration::from_secs(3);
+// Share of queries sent to a random server first,  so that slow servers get measured again.
+const EXPLORATION_RATE: f64 = 0.05;
+
+// Servers failing this many times in a row are held down,  lame ones right away for the zone they
+// are lame for.
+const HOLD_DOWN_FAILURES: u32 = 3;
+const HOLD_DOWN: Duration = Duration::from_secs(300);
+
+// Servers not heard of for this long are forgotten once there are MAX_SERVERS of them.
+const MAX_SERVERS: usize = 10_000;
+const SERVER_TTL: Duration = Duration::from_secs(900);
+
+// What came of asking a server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Answered(Duration),
+    // Timed out,  failed the query,  or the query could not be sent or its answer read.
+    Failed,
+    // https://tools.ietf.org/html/rfc4697 2.1
+    // Refused,  or referred back up the tree:  the server does not serve the zone it was asked
+    // about,  though it may well serve others.
+    Lame,
+}
+
+// What is known about the authorities,  by address,  shared by every resolution.  Zones are the
+// ones the servers were asked as authorities of,  None for upstreams.
+pub struct InfraCache {
+    servers: Mutex<HashMap<IpAddr, Server>>,
+}
+
+struct Server {
+    // https://tools.ietf.org/html/rfc6298 2
+    srtt: Duration,
+    // Failures since the last answer.
+    failures: u32,
+    held_down_until: Option<Instant>,
+    // Zones the server is lame for,  and until when it is held down for them.
+    lame_zones: HashMap<String, Instant>,
+    updated_at: Instant,
+}
+
+impl Server {
+    fn is_held_down(&self, zone: Option<&str>, now: Instant) -> bool {
+        self.held_down_until.is_some_and(|until| now < until)
+            || zone
+                .and_then(|zone| self.lame_zones.get(zone))
+                .is_some_and(|until| now < *until)
+    }
+}
+
+impl InfraCache {
+    pub fn new() -> Self {
+        Self {
+            servers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Every address once,  the one to ask first at the front.  Held down servers go last,  they
+    // are only asked when all the others failed.
+    pub fn order(&self, addrs: &[SocketAddr], zone: Option<&str>) -> Vec<SocketAddr> {
+        let explore = if random::<f64>() < EXPLORATION_RATE {
+            Some(random())
+        } else {
+            None
+        };
+        self.order_exploring(addrs, zone, explore, Instant::now())
+    }
+
+    // explore picks the server moved to the front,  among the ones not held down.
+    fn order_exploring(
+        &self,
+        addrs: &[SocketAddr],
+        zone: Option<&str>,
+        explore: Option<usize>,
+        now: Instant,
+    ) -> Vec<SocketAddr> {
+        let zone = zone.map(normalize);
+        let servers = self.servers.lock().unwrap();
+        let key = |addr: &SocketAddr| match servers.get(&addr.ip()) {
+            Some(server) => (server.is_held_down(zone.as_deref(), now), server.srtt),
+            None => (false, UNKNOWN_RTT),
+        };
+        let mut addrs = addrs.to_vec();
+        addrs.sort_by_key(key);
+
+        if let Some(explore) = explore {
+            let available = addrs.iter().filter(|addr| !key(addr).0).count();
+            if available > 1 {
+                let addr = addrs.remove(explore % available);
+                addrs.insert(0, addr);
+            }
+        }
+        addrs
+    }
+
+    pub fn report(&self, addr: &SocketAddr, zone: Option<&str>, outcome: Outcome) {
+        self.report_at(addr, zone, outcome, Instant::now())
+    }
+
+    fn report_at(&self, addr: &SocketAddr, zone: Option<&str>, outcome: Outcome, now: Instant) {
+        let mut servers = self.servers.lock().unwrap();
+        if servers.len() >= MAX_SERVERS && !servers.contains_key(&addr.ip()) {
+            servers.retain(|_, server| now.duration_since(server.updated_at) < SERVER_TTL);
+            if servers.len() >= MAX_SERVERS {
+                return;
+            }
+        }
+
+        let zone = zone.map(normalize);
+        // Being lame for a zone says nothing of how the server does for the others,  nor does it
+        // tell anything about a forwarder.
+        let outcome = match (outcome, &zone) {
+            (Outcome::Lame, None) => Outcome::Failed,
+            (outcome, _) => outcome,
+        };
+        let sample = match outcome {
+            Outcome::Answered(rtt) => Some(rtt),
+            Outcome::Failed => Some(FAILURE_RTT),
+            Outcome::Lame => None,
+        };
+        let server = servers.entry(addr.ip()).or_insert(Server {
+            srtt: sample.unwrap_or(UNKNOWN_RTT),
+            failures: 0,
+            held_down_until: None,
+            lame_zones: HashMap::new(),
+            updated_at: now,
+        });
+        if let Some(sample) = sample {
+            // https://tools.ietf.org/html/rfc6298 2.3
+            // SRTT <- 7/8 * SRTT + 1/8 * R'
+            server.srtt = server.srtt * 7 / 8 + sample / 8;
+        }
+        server.updated_at = now;
+        server.lame_zones.retain(|_, until| now < *until);
+        match outcome {
+            Outcome::Answered(_) => {
+                server.failures = 0;
+                server.held_down_until = None;
+                if let Some(zone) = zone {
+                    server.lame_zones.remove(&zone);
+                }
+            }
+            Outcome::Failed => {
+                server.failures += 1;
+                if server.failures >= HOLD_DOWN_FAILURES {
+                    server.held_down_until = Some(now + HOLD_DOWN);
+                }
+            }
+            Outcome::Lame => {
+                if let Some(zone) = zone {
+                    server.lame_zones.insert(zone, now + HOLD_DOWN);
+                }
+            }
+        }
+    }
+}
+
+// https://tools.ietf.org/html/rfc8305 4
+// Alternates IPv6 and IPv4 addresses,  starting with the family of the first one and otherwise
+// keeping their order.
+pub fn interleave_families(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let (mut first, mut second): (Vec<SocketAddr>, Vec<SocketAddr>) = match addrs.first() {
+        Some(addr) => addrs
+            .iter()
+            .partition(|other| other.is_ipv6() == addr.is_ipv6()),
+        None => return vec![],
+    };
+    first.reverse();
+    second.reverse();
+    let mut interleaved = Vec::with_capacity(addrs.len());
+    while let Some(addr) = first.pop() {
+        interleaved.push(addr);
+        interleaved.extend(second.pop());
+    }
+    interleaved.extend(second.into_iter().rev());
+    interleaved
+}
+
+// https://tools.ietf.org/html/rfc4697 2.1
+// Servers of zone not authoritative for it refer to the zone itself or to one up the tree,
+// instead of answering or referring down to a child.
+pub fn is_lame_referral(response: &DNSQueryResponse, zone: &str) -> bool {
+    if response.query.header.is_authoritative_answer || !response.answers.is_empty() {
+        return false;
+    }
+    let zone = normalize(zone);
+    response.authority.iter().any(|rr| {
+        let owner = normalize(&rr.name);
+        matches!(rr.r#type, Type::NS(_)) && (owner == zone || !in_zone(&owner, &zone))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{interleave_families, is_lame_referral, InfraCache, Outcome, HOLD_DOWN};
+    use crate::business::models::{
+        Class, DNSQuery, DNSQueryHeaderSection, DNSQueryResponse, DNSQuestionQuery, OpCode, QClass,
+        QType, ResourceRecord, ResponseCode, Type,
+    };
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    const ZONE: Option<&str> = Some("example.");
+
+    fn addrs() -> Vec<SocketAddr> {
+        vec![
+            "192.0.2.1:53".parse().unwrap(),
+            "192.0.2.2:53".parse().unwrap(),
+            "[2001:db8::3]:53".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_infra_cache_fastest_first() {
+        // Arrange
+        let infra = InfraCache::new();
+        let now = Instant::now();
+        infra.report_at(
+            &addrs()[0],
+            ZONE,
+            Outcome::Answered(Duration::from_millis(900)),
+            now,
+        );
+        infra.report_at(
+            &addrs()[2],
+            ZONE,
+            Outcome::Answered(Duration::from_millis(20)),
+            now,
+        );
+
+        // Act
+        let ordered = infra.order_exploring(&addrs(), ZONE, None, now);
+
+        // Assert
+        assert_eq!(ordered, vec![addrs()[2], addrs()[1], addrs()[0]]);
+    }
+
+    #[test]
+    fn test_infra_cache_holds_down_failing_servers() {
+        // Arrange
+        let infra = InfraCache::new();
+        let now = Instant::now();
+        for _ in 0..3 {
+            infra.report_at(&addrs()[0], ZONE, Outcome::Failed, now);
+        }
+        infra.report_at(&addrs()[1], ZONE, Outcome::Lame, now);
+        infra.report_at(&addrs()[2], ZONE, Outcome::Failed, now);
+
+        // Act
+        let held_down = infra.order_exploring(&addrs(), ZONE, Some(1), now);
+        let other_zone = infra.order_exploring(&addrs(), Some("example.org."), None, now);
+        let released = infra.order_exploring(&addrs(), ZONE, None, now + HOLD_DOWN);
+
+        // Assert
+        assert_eq!(held_down, vec![addrs()[2], addrs()[1], addrs()[0]]);
+        assert_eq!(
+            other_zone,
+            vec![addrs()[1], addrs()[2], addrs()[0]],
+            "lame for example. only"
+        );
+        assert_eq!(released, vec![addrs()[1], addrs()[0], addrs()[2]]);
+    }
+
+    #[test]
+    fn test_infra_cache_answer_lifts_hold_down() {
+        // Arrange
+        let infra = InfraCache::new();
+        let now = Instant::now();
+        infra.report_at(&addrs()[0], ZONE, Outcome::Lame, now);
+        infra.report_at(
+            &addrs()[0],
+            ZONE,
+            Outcome::Answered(Duration::from_millis(1)),
+            now,
+        );
+        infra.report_at(
+            &addrs()[1],
+            ZONE,
+            Outcome::Answered(Duration::from_millis(1)),
+            now,
+        );
+        for _ in 0..3 {
+            infra.report_at(&addrs()[1], ZONE, Outcome::Failed, now);
+        }
+
+        // Act
+        let ordered = infra.order_exploring(&addrs(), ZONE, None, now);
+
+        // Assert
+        assert_eq!(ordered, vec![addrs()[0], addrs()[2], addrs()[1]]);
+    }
+
+    #[test]
+    fn test_infra_cache_explores() {
+        // Arrange
+        let infra = InfraCache::new();
+        let now = Instant::now();
+        infra.report_at(
+            &addrs()[0],
+            ZONE,
+            Outcome::Answered(Duration::from_millis(10)),
+            now,
+        );
+        infra.report_at(
+            &addrs()[1],
+            ZONE,
+            Outcome::Answered(Duration::from_millis(20)),
+            now,
+        );
+        infra.report_at(&addrs()[2], ZONE, Outcome::Lame, now);
+
+        // Act
+        let ordered = infra.order_exploring(&addrs(), ZONE, Some(3), now);
+
+        // Assert
+        assert_eq!(ordered, vec![addrs()[1], addrs()[0], addrs()[2]]);
+    }
+
+    fn referral(is_authoritative_answer: bool, owner: &str) -> DNSQueryResponse {
+        DNSQueryResponse {
+            query: DNSQuery {
+                header: DNSQueryHeaderSection {
+                    id: 1,
+                    op_code: OpCode::Query,
+                    is_query: false,
+                    is_truncated: false,
+                    is_authoritative_answer,
+                    is_recursion_available: false,
+                    is_authentic_data: false,
+                    is_checking_disabled: false,
+                    is_recursion_desired: false,
+                    response_code: ResponseCode::NoError,
+                    questions_count: 1,
+                    answers_count: 0,
+                    ns_rr_count: 1,
+                    additional_rr_count: 0,
+                },
+                questions: vec![DNSQuestionQuery {
+                    qname: "www.example.com.".to_string(),
+                    qclass: QClass::IN,
+                    qtype: QType::A,
+                }],
+                additionals: vec![],
+                edns: None,
+            },
+            answers: vec![],
+            authority: vec![ResourceRecord {
+                name: owner.to_string(),
+                r#type: Type::NS("ns1.example.net.".to_string()),
+                class: Class::IN,
+                ttl: 3600,
+                rd_length: 0,
+            }],
+            additional: vec![],
+        }
+    }
+
+    #[test]
+    fn test_is_lame_referral() {
+        // Arrange
+        let down = referral(false, "example.com.");
+        let itself = referral(false, "Example.COM");
+        let up = referral(false, ".");
+        let sideways = referral(false, "example.org.");
+        let authoritative = referral(true, "example.com.");
+
+        // Act
+        let zone = "com.";
+
+        // Assert
+        assert!(!is_lame_referral(&down, zone));
+        assert!(is_lame_referral(&itself, "example.com."));
+        assert!(is_lame_referral(&up, zone));
+        assert!(is_lame_referral(&sideways, zone));
+        assert!(!is_lame_referral(&authoritative, "example.com."));
+    }
+
+    #[test]
+    fn test_interleave_families() {
+        // Arrange
+        let addrs: Vec<SocketAddr> = vec![
+            "[2001:db8::1]:53".parse().unwrap(),
+            "[2001:db8::2]:53".parse().unwrap(),
+            "192.0.2.1:53".parse().unwrap(),
+            "[2001:db8::3]:53".parse().unwrap(),
+            "192.0.2.2:53".parse().unwrap(),
+            "192.0.2.3:53".parse().unwrap(),
+            "192.0.2.4:53".parse().unwrap(),
+        ];
+
+        // Act
+        let interleaved = interleave_families(&addrs);
+
+        // Assert
+        assert_eq!(
+            interleaved,
+            vec![addrs[0], addrs[2], addrs[1], addrs[4], addrs[3], addrs[5], addrs[6]]
+        );
+        assert_eq!(interleave_families(&[]), vec![]);
+    }
+}
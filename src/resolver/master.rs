@@ -0,0 +1,818 @@
+use crate::business::models::{
+    CAAData, Class, DNSKEYData, DSData, MXData, NAPTRData, NSEC3Data, NSECData, QType, RRSIGData,
+    RRSet, ResourceRecord, SOAData, SRVData, TXTData, Type,
+};
+use crate::config::decode_hex;
+use crate::error::MasterFileError;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+// $INCLUDE nested deeper than this is taken for a file including itself.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+// https://tools.ietf.org/html/rfc1035 5
+// Records of the master file at path,  names not ending with "." are relative to origin.
+// $INCLUDE paths are relative to the directory of the file including them.
+pub fn load(path: &Path, origin: &str) -> Result<RRSet, MasterFileError> {
+    let mut parser = Parser::new(origin);
+    parser.parse_file(path, 0)?;
+    Ok(parser.records)
+}
+
+// Like load,  $INCLUDE paths are relative to the working directory.
+pub fn parse(contents: &str, origin: &str) -> Result<RRSet, MasterFileError> {
+    let mut parser = Parser::new(origin);
+    parser.parse(contents, Path::new("."), 0)?;
+    Ok(parser.records)
+}
+
+struct Parser {
+    origin: String,
+    // https://tools.ietf.org/html/rfc2308 4
+    // Set by $TTL,  used by records without a TTL of their own.
+    default_ttl: Option<u32>,
+    last_owner: Option<String>,
+    last_ttl: Option<u32>,
+    records: RRSet,
+}
+
+// One entry of a master file,  which may span lines within parentheses.
+struct Entry {
+    line: usize,
+    // Entry starts with a blank,  its owner is the one of the entry before.
+    inherits_owner: bool,
+    tokens: Vec<Token>,
+}
+
+struct Token {
+    text: String,
+    // text as sent in <character-string>s,  \DDD escapes above 127 are octets but no char.
+    octets: Vec<u8>,
+    quoted: bool,
+}
+
+impl Parser {
+    fn new(origin: &str) -> Self {
+        Self {
+            origin: absolute(origin, "."),
+            default_ttl: None,
+            last_owner: None,
+            last_ttl: None,
+            records: vec![],
+        }
+    }
+
+    fn parse_file(&mut self, path: &Path, depth: usize) -> Result<(), MasterFileError> {
+        let contents = fs::read_to_string(path)?;
+        self.parse(&contents, path.parent().unwrap_or(Path::new(".")), depth)
+    }
+
+    fn parse(&mut self, contents: &str, dir: &Path, depth: usize) -> Result<(), MasterFileError> {
+        for entry in entries(contents)? {
+            let line = entry.line;
+            let syntax = move |err: String| MasterFileError::Syntax(line, err);
+            let argument = |i: usize| {
+                entry
+                    .tokens
+                    .get(i)
+                    .map(|token| token.text.as_str())
+                    .ok_or_else(|| syntax(format!("{} needs an argument", entry.tokens[0].text)))
+            };
+            match entry.tokens[0].text.as_str() {
+                "$ORIGIN" if !entry.inherits_owner => {
+                    self.origin = absolute(argument(1)?, &self.origin);
+                }
+                "$TTL" if !entry.inherits_owner => {
+                    let ttl = argument(1)?;
+                    self.default_ttl =
+                        Some(parse_ttl(ttl).ok_or_else(|| syntax(format!("bad TTL {}", ttl)))?);
+                }
+                "$INCLUDE" if !entry.inherits_owner => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(syntax("$INCLUDE nested too deep".to_string()));
+                    }
+                    // https://tools.ietf.org/html/rfc1035 5.1
+                    // Origin given to $INCLUDE only applies to the included file.
+                    let origin = self.origin.clone();
+                    if let Ok(include_origin) = argument(2) {
+                        self.origin = absolute(include_origin, &origin);
+                    }
+                    self.parse_file(&dir.join(argument(1)?), depth + 1)?;
+                    self.origin = origin;
+                }
+                _ => {
+                    let rr = self.parse_record(&entry).map_err(syntax)?;
+                    self.records.push(rr);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // <domain-name> [<TTL>] [<class>] <type> <RDATA>,  TTL and class in either order.
+    fn parse_record(&mut self, entry: &Entry) -> Result<ResourceRecord, String> {
+        let mut tokens = entry.tokens.iter();
+        let owner = if entry.inherits_owner {
+            self.last_owner.clone().ok_or("no owner to inherit")?
+        } else {
+            absolute(&tokens.next().unwrap().text, &self.origin)
+        };
+        let mut ttl = None;
+        let mut class = Class::IN;
+        let qtype = loop {
+            let token = tokens.next().ok_or("missing type")?;
+            match token.text.to_uppercase().as_str() {
+                "IN" => class = Class::IN,
+                "CH" => class = Class::CH,
+                text => match parse_ttl(text) {
+                    Some(value) if ttl.is_none() => ttl = Some(value),
+                    _ => break QType::from_str(text)?,
+                },
+            }
+        };
+        if ttl.is_some() {
+            self.last_ttl = ttl;
+        }
+        let ttl = ttl
+            .or(self.default_ttl)
+            .or(self.last_ttl)
+            .ok_or("no TTL and no $TTL")?;
+        let rdata: Vec<&Token> = tokens.collect();
+        let r#type = parse_rdata(qtype, &rdata, &self.origin)?;
+        self.last_owner = Some(owner.clone());
+
+        Ok(ResourceRecord {
+            name: owner,
+            rd_length: r#type.serialize_rdata().len() as u16,
+            r#type,
+            class,
+            ttl,
+        })
+    }
+}
+
+// https://tools.ietf.org/html/rfc1035 5.1
+// Splits contents in entries:  comments are dropped,  parentheses join lines and quotes group
+// blanks into a single token.
+fn entries(contents: &str) -> Result<Vec<Entry>, MasterFileError> {
+    let mut entries = vec![];
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut start_line = 1;
+    let mut inherits_owner = false;
+    let mut parentheses = 0;
+    let mut at_line_start = true;
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        if at_line_start && parentheses == 0 {
+            inherits_owner = c == ' ' || c == '\t';
+            start_line = line;
+        }
+        at_line_start = false;
+        match c {
+            '\n' => {
+                line += 1;
+                at_line_start = true;
+                if parentheses == 0 && !tokens.is_empty() {
+                    entries.push(Entry {
+                        line: start_line,
+                        inherits_owner,
+                        tokens: std::mem::take(&mut tokens),
+                    });
+                }
+            }
+            ' ' | '\t' | '\r' => {}
+            ';' => {
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            }
+            '(' => parentheses += 1,
+            ')' if parentheses == 0 => {
+                return Err(MasterFileError::Syntax(line, "unbalanced )".to_string()));
+            }
+            ')' => parentheses -= 1,
+            '"' => {
+                let mut octets = vec![];
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => unescape(&mut chars, &mut octets).ok_or_else(|| {
+                            MasterFileError::Syntax(line, "bad escape".to_string())
+                        })?,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            push_char(&mut octets, c)
+                        }
+                        None => {
+                            return Err(MasterFileError::Syntax(
+                                start_line,
+                                "unterminated quote".to_string(),
+                            ))
+                        }
+                    }
+                }
+                tokens.push(Token {
+                    text: String::from_utf8_lossy(&octets).into_owned(),
+                    octets,
+                    quoted: true,
+                });
+            }
+            c => {
+                let mut text = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if c.is_whitespace() || ";()\"".contains(*c) {
+                        break;
+                    }
+                    text.push(chars.next().unwrap());
+                }
+                tokens.push(Token {
+                    octets: text.as_bytes().to_vec(),
+                    text,
+                    quoted: false,
+                });
+            }
+        }
+    }
+    if parentheses > 0 {
+        return Err(MasterFileError::Syntax(
+            start_line,
+            "unbalanced (".to_string(),
+        ));
+    }
+    if !tokens.is_empty() {
+        entries.push(Entry {
+            line: start_line,
+            inherits_owner,
+            tokens,
+        });
+    }
+    Ok(entries)
+}
+
+// \X is X,  \DDD is the octet with decimal value DDD.
+fn unescape(chars: &mut std::iter::Peekable<std::str::Chars>, octets: &mut Vec<u8>) -> Option<()> {
+    let c = chars.next()?;
+    if !c.is_ascii_digit() {
+        push_char(octets, c);
+        return Some(());
+    }
+    let digits: String = [Some(c), chars.next(), chars.next()]
+        .iter()
+        .copied()
+        .collect::<Option<String>>()?;
+    octets.push(digits.parse::<u8>().ok()?);
+    Some(())
+}
+
+fn push_char(octets: &mut Vec<u8>, c: char) {
+    octets.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+fn parse_rdata(qtype: QType, rdata: &[&Token], origin: &str) -> Result<Type, String> {
+    let field = |i: usize| {
+        rdata
+            .get(i)
+            .map(|token| token.text.as_str())
+            .ok_or(format!("{} is missing fields", qtype))
+    };
+    let name = |i: usize| field(i).map(|name| absolute(name, origin));
+    let octets = |i: usize| {
+        rdata
+            .get(i)
+            .map(|token| token.octets.clone())
+            .ok_or(format!("{} is missing fields", qtype))
+    };
+    let string = |i: usize| {
+        let token = rdata.get(i).ok_or(format!("{} is missing fields", qtype))?;
+        Ok::<Vec<u8>, String>(strings(&[*token])?.remove(0))
+    };
+    let rest = |i: usize| {
+        rdata
+            .iter()
+            .skip(i)
+            .map(|token| token.text.as_str())
+            .collect::<String>()
+    };
+    let types = |i: usize| {
+        rdata
+            .iter()
+            .skip(i)
+            .map(|token| QType::from_str(&token.text))
+            .collect::<Result<Vec<QType>, String>>()
+    };
+
+    // https://tools.ietf.org/html/rfc3597 5
+    // \# <length> <hex>,  RDATA of any type in the generic form.
+    if rdata
+        .first()
+        .is_some_and(|token| !token.quoted && token.text == "\\#")
+    {
+        let length: usize = number(field(1)?)?;
+        let data = decode_hex(&rest(2)).ok_or("bad hex in generic RDATA")?;
+        if data.len() != length {
+            return Err(format!("generic RDATA is not {} octets long", length));
+        }
+        return Ok(Type::Unknown {
+            code: qtype.to_u16(),
+            rdata: data,
+        });
+    }
+
+    Ok(match qtype {
+        QType::A => Type::A(field(0)?.parse().map_err(|_| "bad IPv4 address")?),
+        QType::AAAA => Type::AAAA(field(0)?.parse().map_err(|_| "bad IPv6 address")?),
+        QType::NS => Type::NS(name(0)?),
+        QType::CNAME => Type::CNAME(name(0)?),
+        QType::PTR => Type::PTR(name(0)?),
+        QType::SOA => {
+            let seconds = |i: usize| {
+                field(i)
+                    .and_then(|value| parse_ttl(value).ok_or(format!("bad SOA field {}", value)))
+            };
+            Type::SOA(SOAData {
+                mname: name(0)?,
+                rname: name(1)?,
+                serial: number(field(2)?)?,
+                refresh_in_secs: seconds(3)?,
+                retry_in_secs: seconds(4)?,
+                expire_in_secs: seconds(5)?,
+                minimum: seconds(6)?,
+            })
+        }
+        QType::MX => Type::MX(MXData {
+            preference: number(field(0)?)?,
+            exchange: name(1)?,
+        }),
+        // https://tools.ietf.org/html/rfc1035 3.3.14
+        QType::TXT => {
+            if rdata.is_empty() {
+                return Err("TXT needs at least one string".to_string());
+            }
+            Type::TXT(TXTData {
+                strings: strings(rdata)?,
+            })
+        }
+        // https://tools.ietf.org/html/rfc1035 3.3.2
+        QType::HINFO => {
+            if rdata.len() != 2 {
+                return Err("HINFO needs CPU and OS".to_string());
+            }
+            Type::Unknown {
+                code: qtype.to_u16(),
+                rdata: character_strings(rdata)?,
+            }
+        }
+        QType::SRV => Type::SRV(SRVData {
+            priority: number(field(0)?)?,
+            weight: number(field(1)?)?,
+            port: number(field(2)?)?,
+            target: name(3)?,
+        }),
+        QType::NAPTR => Type::NAPTR(NAPTRData {
+            order: number(field(0)?)?,
+            preference: number(field(1)?)?,
+            flags: string(2)?,
+            services: string(3)?,
+            regexp: string(4)?,
+            replacement: name(5)?,
+        }),
+        // https://tools.ietf.org/html/rfc8659 4.1
+        QType::CAA => {
+            let tag = field(1)?;
+            if tag.is_empty() || tag.len() > u8::MAX as usize {
+                return Err(format!("CAA tag must be 1 to 255 octets: {}", tag));
+            }
+            Type::CAA(CAAData {
+                flags: number(field(0)?)?,
+                tag: tag.to_string(),
+                value: octets(2)?,
+            })
+        }
+        // https://tools.ietf.org/html/rfc4034 5.3
+        QType::DS => Type::DS(DSData {
+            key_tag: number(field(0)?)?,
+            algorithm: number(field(1)?)?,
+            digest_type: number(field(2)?)?,
+            digest: decode_hex(&rest(3)).ok_or("bad DS digest")?,
+        }),
+        // https://tools.ietf.org/html/rfc4034 2.2
+        QType::DNSKEY => Type::DNSKEY(DNSKEYData {
+            flags: number(field(0)?)?,
+            protocol: number(field(1)?)?,
+            algorithm: number(field(2)?)?,
+            public_key: decode_base64(&rest(3)).ok_or("bad DNSKEY public key")?,
+        }),
+        // https://tools.ietf.org/html/rfc4034 3.2
+        QType::RRSIG => Type::RRSIG(RRSIGData {
+            type_covered: QType::from_str(field(0)?)?,
+            algorithm: number(field(1)?)?,
+            labels: number(field(2)?)?,
+            original_ttl: number(field(3)?)?,
+            expiration: parse_timestamp(field(4)?)?,
+            inception: parse_timestamp(field(5)?)?,
+            key_tag: number(field(6)?)?,
+            signer_name: name(7)?,
+            signature: decode_base64(&rest(8)).ok_or("bad RRSIG signature")?,
+        }),
+        // https://tools.ietf.org/html/rfc4034 4.2
+        QType::NSEC => Type::NSEC(NSECData {
+            next_domain_name: name(0)?,
+            types: types(1)?,
+        }),
+        // https://tools.ietf.org/html/rfc5155 3.3
+        QType::NSEC3 => Type::NSEC3(NSEC3Data {
+            hash_algorithm: number(field(0)?)?,
+            flags: number(field(1)?)?,
+            iterations: number(field(2)?)?,
+            salt: match field(3)? {
+                "-" => vec![],
+                salt => decode_hex(salt).ok_or("bad NSEC3 salt")?,
+            },
+            next_hashed_owner_name: decode_base32hex(field(4)?)
+                .ok_or("bad NSEC3 next hashed owner name")?,
+            types: types(5)?,
+        }),
+        _ => return Err(format!("{} needs RDATA in the \\# form", qtype)),
+    })
+}
+
+fn number<T: FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("bad number {}", value))
+}
+
+// https://tools.ietf.org/html/rfc1035 5.1
+// "@" is the origin,  names not ending with "." are relative to it.
+fn absolute(name: &str, origin: &str) -> String {
+    let name = name.to_lowercase();
+    if name == "@" {
+        origin.to_string()
+    } else if name.ends_with('.') {
+        name
+    } else if origin == "." {
+        format!("{}.", name)
+    } else {
+        format!("{}.{}", name, origin)
+    }
+}
+
+// Seconds,  or a duration in the units BIND writes them in:  "1w",  "1h30m".
+fn parse_ttl(value: &str) -> Option<u32> {
+    if let Ok(secs) = value.parse() {
+        return Some(secs);
+    }
+    let mut total: u32 = 0;
+    let mut digits = String::new();
+    for c in value.to_lowercase().chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return None,
+        };
+        total = total.checked_add(digits.parse::<u32>().ok()?.checked_mul(unit)?)?;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        total = total.checked_add(digits.parse().ok()?)?;
+    }
+    Some(total)
+}
+
+// https://tools.ietf.org/html/rfc4034 3.2
+// YYYYMMDDHHmmSS in UTC,  or seconds since epoch.
+fn parse_timestamp(value: &str) -> Result<u32, String> {
+    if value.len() != 14 || !value.chars().all(|c| c.is_ascii_digit()) {
+        return number(value);
+    }
+    let field = |start: usize, end: usize| value[start..end].parse::<i64>().unwrap();
+    let (year, month, day) = (field(0, 4), field(4, 6), field(6, 8));
+    // Days since epoch of a date in the proleptic Gregorian calendar.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    let secs = days * 86400 + field(8, 10) * 3600 + field(10, 12) * 60 + field(12, 14);
+    // https://tools.ietf.org/html/rfc1982
+    // Serial number arithmetic,  times past 2106 wrap around.
+    Ok(secs as u32)
+}
+
+// https://tools.ietf.org/html/rfc1035 3.3
+fn character_strings(tokens: &[&Token]) -> Result<Vec<u8>, String> {
+    let mut result = vec![];
+    for string in strings(tokens)? {
+        result.push(string.len() as u8);
+        result.extend(string);
+    }
+    Ok(result)
+}
+
+// Every token as one <character-string>,  without the length octet.
+fn strings(tokens: &[&Token]) -> Result<Vec<Vec<u8>>, String> {
+    tokens
+        .iter()
+        .map(|token| {
+            if token.octets.len() > u8::MAX as usize {
+                return Err(format!("string longer than 255 octets: {}", token.text));
+            }
+            Ok(token.octets.clone())
+        })
+        .collect()
+}
+
+// https://tools.ietf.org/html/rfc4648 4
+fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    decode_bits(
+        value.trim_end_matches('='),
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/",
+        6,
+    )
+}
+
+// https://tools.ietf.org/html/rfc4648 5
+pub fn decode_base64url(value: &str) -> Option<Vec<u8>> {
+    decode_bits(
+        value.trim_end_matches('='),
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_",
+        6,
+    )
+}
+
+// https://tools.ietf.org/html/rfc4648 7
+fn decode_base32hex(value: &str) -> Option<Vec<u8>> {
+    decode_bits(
+        &value.trim_end_matches('=').to_uppercase(),
+        b"0123456789ABCDEFGHIJKLMNOPQRSTUV",
+        5,
+    )
+}
+
+fn decode_bits(value: &str, alphabet: &[u8], bits_per_char: u32) -> Option<Vec<u8>> {
+    let mut result = vec![];
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in value.bytes() {
+        let digit = alphabet.iter().position(|a| *a == c)? as u32;
+        buffer = (buffer << bits_per_char) | digit;
+        bits += bits_per_char;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, parse, parse_timestamp, parse_ttl};
+    use crate::business::models::{CAAData, NAPTRData, QType, TXTData, Type};
+    use crate::error::MasterFileError;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_master_parse_zone() {
+        // Arrange
+        let contents = r#"
+$ORIGIN example.com.
+$TTL 1h
+@   IN  SOA ns1 hostmaster (
+            2020060801 ; serial
+            7200       ; refresh
+            1h         ; retry
+            1w         ; expire
+            300 )      ; minimum
+        NS  ns1
+        NS  ns1.example.net.
+ns1 300 A   192.0.2.53
+    IN 600  AAAA 2001:db8::53
+www     CNAME ns1
+@       MX  10 mail
+@       TXT "v=spf1 -all; really"
+*.dev   A   192.0.2.80
+"#;
+
+        // Act
+        let rrs = parse(contents, ".").unwrap();
+
+        // Assert
+        assert_eq!(rrs.len(), 9);
+        match &rrs[0].r#type {
+            Type::SOA(soa) => {
+                assert_eq!(rrs[0].name, "example.com.");
+                assert_eq!(rrs[0].ttl, 3600);
+                assert_eq!(soa.mname, "ns1.example.com.");
+                assert_eq!(soa.rname, "hostmaster.example.com.");
+                assert_eq!(soa.serial, 2020060801);
+                assert_eq!(soa.retry_in_secs, 3600);
+                assert_eq!(soa.expire_in_secs, 604800);
+                assert_eq!(soa.minimum, 300);
+            }
+            _ => panic!("expected SOA"),
+        }
+        assert_eq!(rrs[1].name, "example.com.");
+        assert_eq!(rrs[1].r#type, Type::NS("ns1.example.com.".to_string()));
+        assert_eq!(rrs[2].r#type, Type::NS("ns1.example.net.".to_string()));
+        assert_eq!(rrs[3].name, "ns1.example.com.");
+        assert_eq!(rrs[3].ttl, 300);
+        assert_eq!(rrs[4].name, "ns1.example.com.");
+        assert_eq!(rrs[4].ttl, 600);
+        assert_eq!(rrs[5].r#type, Type::CNAME("ns1.example.com.".to_string()));
+        assert_eq!(rrs[6].r#type.to_qtype(), QType::MX);
+        match &rrs[7].r#type {
+            Type::TXT(txt) => assert_eq!(txt.strings, vec![b"v=spf1 -all; really".to_vec()]),
+            _ => panic!("expected TXT"),
+        }
+        assert_eq!(rrs[8].name, "*.dev.example.com.");
+        assert_eq!(rrs[8].rd_length, 4);
+    }
+
+    #[test]
+    fn test_master_parse_dnssec_and_generic_records() {
+        // Arrange
+        let contents = "
+example. 3600 DNSKEY 257 3 8 AwEAAQ==
+example. 3600 RRSIG DNSKEY 8 1 3600 20200608000000 1591574400 12345 example. AAEC AwQ=
+example. 3600 NSEC3 1 0 10 AABBCCDD 2T7B4G4VSA5SMI47K61MV5BV1A22BOJR A RRSIG
+example. 3600 TYPE65534 \\# 3 abcdef
+";
+
+        // Act
+        let rrs = parse(contents, ".").unwrap();
+
+        // Assert
+        match &rrs[0].r#type {
+            Type::DNSKEY(dnskey) => assert_eq!(dnskey.public_key, vec![3, 1, 0, 1]),
+            _ => panic!("expected DNSKEY"),
+        }
+        match &rrs[1].r#type {
+            Type::RRSIG(rrsig) => {
+                assert_eq!(rrsig.type_covered, QType::DNSKEY);
+                assert_eq!(rrsig.expiration, rrsig.inception);
+                assert_eq!(rrsig.signature, vec![0, 1, 2, 3, 4]);
+            }
+            _ => panic!("expected RRSIG"),
+        }
+        match &rrs[2].r#type {
+            Type::NSEC3(nsec3) => {
+                assert_eq!(nsec3.salt, vec![0xaa, 0xbb, 0xcc, 0xdd]);
+                assert_eq!(nsec3.next_hashed_owner_name.len(), 20);
+                assert_eq!(nsec3.types, vec![QType::A, QType::RRSIG]);
+            }
+            _ => panic!("expected NSEC3"),
+        }
+        assert_eq!(
+            rrs[3].r#type,
+            Type::Unknown {
+                code: 65534,
+                rdata: vec![0xab, 0xcd, 0xef]
+            }
+        );
+    }
+
+    #[test]
+    fn test_master_parse_txt_strings() {
+        // Arrange
+        let long = "k".repeat(200);
+        let contents = format!("example. 60 TXT \"v=DKIM1; p=\" \"{}\" {}\n", long, long);
+
+        // Act
+        let rrs = parse(&contents, ".").unwrap();
+
+        // Assert
+        match &rrs[0].r#type {
+            Type::TXT(txt) => assert_eq!(
+                txt.strings,
+                vec![
+                    b"v=DKIM1; p=".to_vec(),
+                    long.as_bytes().to_vec(),
+                    long.as_bytes().to_vec()
+                ]
+            ),
+            _ => panic!("expected TXT"),
+        }
+        assert!(parse(&format!("example. 60 TXT {}\n", "k".repeat(256)), ".").is_err());
+    }
+
+    #[test]
+    fn test_master_parse_escaped_octets() {
+        // Arrange
+        let contents = "example. 60 TXT \"caf\\233 \\\"\\255\\000\" caf\u{e9}\n";
+
+        // Act
+        let rrs = parse(contents, ".").unwrap();
+
+        // Assert
+        assert_eq!(
+            rrs[0].r#type,
+            Type::TXT(TXTData {
+                strings: vec![
+                    vec![b'c', b'a', b'f', 233, b' ', b'"', 255, 0],
+                    vec![b'c', b'a', b'f', 0xc3, 0xa9]
+                ],
+            })
+        );
+        assert!(parse("example. 60 TXT \"\\256\"\n", ".").is_err());
+    }
+
+    #[test]
+    fn test_master_parse_caa_and_naptr_octets() {
+        // Arrange
+        let contents = concat!(
+            "example. 60 CAA 0 iodef \"mailto:\\255\"\n",
+            "example. 60 NAPTR 100 10 \"S\" \"SIP+D2U\" \"\\000\" _sip._udp\n",
+        );
+        let long_tag = format!("example. 60 CAA 0 {} \"x\"\n", "a".repeat(256));
+
+        // Act
+        let rrs = parse(contents, "example.").unwrap();
+
+        // Assert
+        assert_eq!(
+            rrs[0].r#type,
+            Type::CAA(CAAData {
+                flags: 0,
+                tag: "iodef".to_string(),
+                value: vec![b'm', b'a', b'i', b'l', b't', b'o', b':', 255],
+            })
+        );
+        assert_eq!(
+            rrs[1].r#type,
+            Type::NAPTR(NAPTRData {
+                order: 100,
+                preference: 10,
+                flags: b"S".to_vec(),
+                services: b"SIP+D2U".to_vec(),
+                regexp: vec![0],
+                replacement: "_sip._udp.example.".to_string(),
+            })
+        );
+        assert!(parse(&long_tag, ".").is_err());
+    }
+
+    #[test]
+    fn test_master_load_include() {
+        // Arrange
+        let dir = env::temp_dir().join(format!("rrdns-master-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("example.com.zone"),
+            "$TTL 300\n$INCLUDE hosts.inc lan.example.com.\nfoo A 192.0.2.2\n",
+        )
+        .unwrap();
+        fs::write(dir.join("hosts.inc"), "printer A 192.0.2.1\n").unwrap();
+
+        // Act
+        let rrs = load(&dir.join("example.com.zone"), "example.com").unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Assert
+        assert_eq!(rrs[0].name, "printer.lan.example.com.");
+        assert_eq!(rrs[1].name, "foo.example.com.");
+    }
+
+    #[test]
+    fn test_master_parse_errors() {
+        assert!(matches!(
+            parse("$TTL 60\na A 192.0.2.1\nb A 192.0.2.300\n", "example."),
+            Err(MasterFileError::Syntax(3, _))
+        ));
+        assert!(matches!(
+            parse("a 60 A 192.0.2.1\n(\n", "example."),
+            Err(MasterFileError::Syntax(2, _))
+        ));
+        assert!(matches!(
+            parse("a A 192.0.2.1\n", "example."),
+            Err(MasterFileError::Syntax(1, _))
+        ));
+    }
+
+    #[test]
+    fn test_master_parse_ttl() {
+        assert_eq!(parse_ttl("86400"), Some(86400));
+        assert_eq!(parse_ttl("1h30m"), Some(5400));
+        assert_eq!(parse_ttl("1W"), Some(604800));
+        assert_eq!(parse_ttl("MX"), None);
+    }
+
+    #[test]
+    fn test_master_parse_timestamp() {
+        assert_eq!(parse_timestamp("20200608000000"), Ok(1591574400));
+        assert_eq!(parse_timestamp("20000229123456"), Ok(951827696));
+        assert_eq!(parse_timestamp("1591574400"), Ok(1591574400));
+    }
+}
@@ -0,0 +1,118 @@
+use super::upstream::UpstreamPool;
+use super::zone::{in_zone, normalize};
+use crate::config::{ForwardPolicy, Forwarder, ZoneRoute, ZoneRouteKind};
+use std::net::SocketAddr;
+
+pub enum Route {
+    // Recursive resolvers for the zone,  asked with RD=1.
+    Forward(UpstreamPool),
+    // Authoritative servers for the zone,  asked instead of the ones found from the root.
+    Stub(Vec<SocketAddr>),
+}
+
+// Zones whose names are not resolved from the root.  The longest matching zone wins,  so that
+// "a.10.in-addr.arpa." can be routed apart from "10.in-addr.arpa.".
+pub struct ZoneRoutes {
+    routes: Vec<(String, Route)>,
+}
+
+impl ZoneRoutes {
+    pub fn new(zone_routes: &[ZoneRoute], policy: &ForwardPolicy) -> Self {
+        let mut routes: Vec<(String, Route)> = zone_routes
+            .iter()
+            .map(|zone_route| {
+                let route = match zone_route.kind {
+                    ZoneRouteKind::Forward => {
+                        let forwarders: Vec<Forwarder> = zone_route
+                            .servers
+                            .iter()
+                            .copied()
+                            .map(Forwarder::udp)
+                            .collect();
+                        Route::Forward(UpstreamPool::new(&forwarders, policy.clone()))
+                    }
+                    ZoneRouteKind::Stub => Route::Stub(zone_route.servers.clone()),
+                };
+                (normalize(&zone_route.zone), route)
+            })
+            .collect();
+        routes.sort_by_key(|(zone, _)| std::cmp::Reverse(zone.len()));
+        Self { routes }
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Route> {
+        self.find_zone(name).map(|(_, route)| route)
+    }
+
+    // Same as find,  along with the zone that was matched.
+    pub fn find_zone(&self, name: &str) -> Option<(&str, &Route)> {
+        let name = normalize(name);
+        self.routes
+            .iter()
+            .find(|(zone, _)| in_zone(&name, zone))
+            .map(|(zone, route)| (zone.as_str(), route))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Route, ZoneRoutes};
+    use crate::config::{ForwardPolicy, ZoneRoute, ZoneRouteKind};
+
+    fn zone_routes() -> ZoneRoutes {
+        ZoneRoutes::new(
+            &[
+                ZoneRoute {
+                    zone: "corp.example.".to_string(),
+                    kind: ZoneRouteKind::Forward,
+                    servers: vec!["10.0.0.1:53".parse().unwrap()],
+                },
+                ZoneRoute {
+                    zone: "10.in-addr.arpa".to_string(),
+                    kind: ZoneRouteKind::Forward,
+                    servers: vec!["10.0.0.1:53".parse().unwrap()],
+                },
+                ZoneRoute {
+                    zone: "lab.10.in-addr.arpa.".to_string(),
+                    kind: ZoneRouteKind::Stub,
+                    servers: vec!["10.0.9.1:53".parse().unwrap()],
+                },
+            ],
+            &ForwardPolicy::Failover,
+        )
+    }
+
+    #[test]
+    fn test_zone_routes_find_suffix() {
+        // Arrange
+        let routes = zone_routes();
+
+        // Act
+        let apex = routes.find("corp.example.");
+        let below = routes.find("WWW.Corp.Example.");
+        let outside = routes.find("notcorp.example.");
+
+        // Assert
+        assert!(matches!(apex, Some(Route::Forward(_))));
+        assert!(matches!(below, Some(Route::Forward(_))));
+        assert!(outside.is_none());
+    }
+
+    #[test]
+    fn test_zone_routes_longest_zone_wins() {
+        // Arrange
+        let routes = zone_routes();
+
+        // Act
+        let actual = routes.find_zone("1.lab.10.in-addr.arpa.");
+
+        // Assert
+        match actual {
+            Some((zone, Route::Stub(servers))) => {
+                assert_eq!(zone, "lab.10.in-addr.arpa.");
+                assert_eq!(servers[0], "10.0.9.1:53".parse().unwrap());
+            }
+            _ => panic!("expected the stub zone"),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use super::infra::InfraCache;
+use crate::config::{ForwardPolicy, Forwarder};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Recursive resolvers queries are forwarded to,  and the order to try them in.  Plain DNS
+// upstreams are only a fallback for encrypted ones,  they are asked after all of those failed.
+pub struct UpstreamPool {
+    upstreams: Vec<Forwarder>,
+    policy: ForwardPolicy,
+    next: AtomicUsize,
+}
+
+impl UpstreamPool {
+    pub fn new(forwarders: &[Forwarder], policy: ForwardPolicy) -> Self {
+        Self {
+            upstreams: forwarders.to_vec(),
+            policy,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    // Every upstream once,  the one to ask first at the front.
+    pub fn ordered(&self, infra: &InfraCache) -> Vec<Forwarder> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let (encrypted, plain): (Vec<Forwarder>, Vec<Forwarder>) = self
+            .upstreams
+            .iter()
+            .cloned()
+            .partition(Forwarder::is_encrypted);
+        let mut forwarders = self.order(encrypted, infra, start);
+        forwarders.append(&mut self.order(plain, infra, start));
+        forwarders
+    }
+
+    fn order(
+        &self,
+        mut upstreams: Vec<Forwarder>,
+        infra: &InfraCache,
+        start: usize,
+    ) -> Vec<Forwarder> {
+        match self.policy {
+            ForwardPolicy::Failover => {}
+            ForwardPolicy::RoundRobin => {
+                let start = start % upstreams.len().max(1);
+                upstreams.rotate_left(start)
+            }
+            // Upstreams are measured like authorities,  see InfraCache::order.
+            ForwardPolicy::LowestLatency => {
+                let addrs: Vec<_> = upstreams.iter().map(|u| u.addr).collect();
+                let ordered = infra.order(&addrs, None);
+                upstreams.sort_by_key(|u| ordered.iter().position(|addr| *addr == u.addr))
+            }
+        }
+        upstreams
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UpstreamPool;
+    use crate::config::{parse_forwarder, ForwardPolicy, Forwarder};
+    use crate::resolver::infra::{InfraCache, Outcome};
+    use std::time::Duration;
+
+    fn addrs() -> Vec<Forwarder> {
+        vec![
+            Forwarder::udp("10.0.0.1:53".parse().unwrap()),
+            Forwarder::udp("10.0.0.2:53".parse().unwrap()),
+            Forwarder::udp("10.0.0.3:53".parse().unwrap()),
+        ]
+    }
+
+    #[test]
+    fn test_upstream_pool_failover_keeps_configured_order() {
+        // Arrange
+        let infra = InfraCache::new();
+        let pool = UpstreamPool::new(&addrs(), ForwardPolicy::Failover);
+        infra.report(&addrs()[0].addr, None, Outcome::Failed);
+
+        // Act
+        let ordered = pool.ordered(&infra);
+
+        // Assert
+        assert_eq!(ordered, addrs());
+    }
+
+    #[test]
+    fn test_upstream_pool_round_robin_rotates() {
+        // Arrange
+        let infra = InfraCache::new();
+        let pool = UpstreamPool::new(&addrs(), ForwardPolicy::RoundRobin);
+
+        // Act
+        let first = pool.ordered(&infra);
+        let second = pool.ordered(&infra);
+
+        // Assert
+        assert_eq!(first, addrs());
+        assert_eq!(
+            second,
+            vec![addrs()[1].clone(), addrs()[2].clone(), addrs()[0].clone()]
+        );
+    }
+
+    #[test]
+    fn test_upstream_pool_lowest_latency_holds_down_failing_upstreams() {
+        // Arrange
+        let infra = InfraCache::new();
+        let pool = UpstreamPool::new(&addrs(), ForwardPolicy::LowestLatency);
+        infra.report(
+            &addrs()[0].addr,
+            None,
+            Outcome::Answered(Duration::from_millis(80)),
+        );
+        for _ in 0..3 {
+            infra.report(&addrs()[1].addr, None, Outcome::Failed);
+        }
+        infra.report(
+            &addrs()[2].addr,
+            None,
+            Outcome::Answered(Duration::from_millis(20)),
+        );
+
+        // Act
+        let ordered = pool.ordered(&infra);
+
+        // Assert
+        assert_eq!(ordered.len(), 3);
+        assert_eq!(ordered[2], addrs()[1]);
+    }
+
+    #[test]
+    fn test_upstream_pool_plain_dns_is_a_fallback() {
+        // Arrange
+        let forwarders = vec![
+            Forwarder::udp("10.0.0.1:53".parse().unwrap()),
+            parse_forwarder("tls://9.9.9.9#dns.quad9.net").unwrap(),
+            parse_forwarder("https://1.1.1.1#cloudflare-dns.com").unwrap(),
+        ];
+        let infra = InfraCache::new();
+        let pool = UpstreamPool::new(&forwarders, ForwardPolicy::RoundRobin);
+
+        // Act
+        let first = pool.ordered(&infra);
+        let second = pool.ordered(&infra);
+
+        // Assert
+        let [udp, tls, https] = [&forwarders[0], &forwarders[1], &forwarders[2]];
+        assert_eq!(first, vec![tls.clone(), https.clone(), udp.clone()]);
+        assert_eq!(second, vec![https.clone(), tls.clone(), udp.clone()]);
+    }
+}
@@ -1,5 +1,6 @@
 extern crate itertools;
 use itertools::Itertools;
+use rand::random;
 
 pub fn zonify(domain: &str) -> String {
     format!(
@@ -22,9 +23,39 @@ pub fn parent_zone(zone: &str) -> String {
     zone[first_dot_index + 1..].to_string()
 }
 
+// Given "www.google.com." and "google.com." return true.
+// Given "notgoogle.com." and "google.com." return false.
+pub fn in_zone(name: &str, zone: &str) -> bool {
+    zone == "." || name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+// Given "WWW.Google.com" return "www.google.com.".
+pub fn normalize(name: &str) -> String {
+    let name = name.to_lowercase();
+    if name.ends_with('.') {
+        name
+    } else {
+        format!("{}.", name)
+    }
+}
+
+// https://tools.ietf.org/html/draft-vixie-dnsext-dns0x20-00 4
+// Given "www.google.com." return "wWw.GOoGlE.cOm." or another mix of cases.
+pub fn randomize_case(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if random() {
+                c.to_ascii_uppercase()
+            } else {
+                c.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{parent_zone, zonify};
+    use super::{in_zone, parent_zone, randomize_case, zonify};
 
     #[test]
     fn test_zone_zonify_fqdn() {
@@ -91,4 +122,25 @@ mod tests {
         // Assert
         assert_eq!(expected_zone, actual_zone);
     }
+
+    #[test]
+    fn test_zone_in_zone() {
+        assert!(in_zone("www.google.com.", "google.com."));
+        assert!(in_zone("google.com.", "google.com."));
+        assert!(in_zone("google.com.", "."));
+        assert!(!in_zone("notgoogle.com.", "google.com."));
+    }
+
+    #[test]
+    fn test_zone_randomize_case() {
+        // Arrange
+        let name = "www.google-123.com.";
+
+        // Act
+        let names: Vec<String> = (0..32).map(|_| randomize_case(name)).collect();
+
+        // Assert
+        assert!(names.iter().all(|n| n.eq_ignore_ascii_case(name)));
+        assert!(names.iter().any(|n| n != name));
+    }
 }
@@ -1,5 +1,6 @@
 // Server invokes handler.
 
+use crate::config::Config;
 use crate::handler::Handler;
 use std::net::{SocketAddr, UdpSocket};
 
@@ -12,7 +13,7 @@ pub struct DNSServer {
 impl DNSServer {
     pub fn new(addr: &'static str) -> Self {
         Self {
-            handler: Handler::new(),
+            handler: Handler::new(&Config::default()),
             addr: addr.parse().unwrap(),
         }
     }